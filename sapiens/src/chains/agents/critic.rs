@@ -0,0 +1,217 @@
+use tracing::{debug, trace, warn};
+
+use crate::chains::agents::{attempt_chitchat, Error};
+use crate::chains::{Context, Message};
+use crate::context::ChatHistory;
+use crate::models::ModelRef;
+use crate::tools::toolbox::Toolbox;
+use crate::{chains, prompt, SapiensConfig};
+
+const PREFIX: &str = r"You are a critic reviewing the Actions of an agent named Sapiens before they are executed.
+You will be given a task, what the agent did so far and the Action it proposes.
+
+- Check that the Action uses one of the Tools below with valid parameters, makes progress toward the answer and does nothing harmful.
+- Do not execute the Action. Do not propose the Observation.
+- Be concise.
+";
+
+const TOOL_PREFIX: &str = r"
+# The following are the ONLY Tools the agent can use for its Actions:
+";
+
+const RESPONSE_FORMAT: &str = r"
+# Format of your response
+
+You must use the following format for your response. Comments are in bold and should be removed from your response.
+====================
+**If the Action has to be revised: a short critique to help the agent revise it.**
+Verdict: <approve|revise>
+====================
+";
+
+/// Configuration of the critic reviewing the Actions before they are executed
+#[derive(Clone)]
+pub struct CriticConfig {
+    /// The model of the critic - the one of the agents if not set
+    pub model: Option<ModelRef>,
+    /// Maximum number of revisions of an Action - the last revision is
+    /// reviewed too and not executed if rejected
+    pub max_revisions: usize,
+}
+
+/// A critic - approves or critiques the proposed Action, the last message of
+/// the [`Context`]
+pub struct Critic {
+    prompt_manager: prompt::Manager,
+    config: SapiensConfig,
+}
+
+impl Critic {
+    /// Create a new [`Critic`] - with the model of the [`CriticConfig`] of
+    /// the `config` if any.
+    #[must_use]
+    pub fn new(mut config: SapiensConfig, toolbox: Toolbox) -> Self {
+        if let Some(model) = config.critic.as_ref().and_then(|c| c.model.clone()) {
+            config.model = model;
+        }
+
+        let system_prompt =
+            "You are a critic reviewing the Actions of an agent interacting with the WORLD."
+                .to_string();
+
+        let prompt = "Should the proposed Action be executed or revised?\nVerdict?".to_string();
+
+        let prompt_manager = prompt::Manager::new(
+            toolbox,
+            system_prompt,
+            prompt,
+            PREFIX.to_string(),
+            TOOL_PREFIX.to_string(),
+            RESPONSE_FORMAT.to_string(),
+        );
+        Self {
+            prompt_manager,
+            config,
+        }
+    }
+
+    async fn convert_context_to_chat_history(
+        &self,
+        context: &Context,
+    ) -> Result<ChatHistory, Error> {
+        let max_token = { self.config.model.context_size().await };
+        let mut chat_history = ChatHistory::new(self.config.clone(), max_token);
+
+        self.prompt_manager
+            .populate_chat_history(&mut chat_history, vec![])
+            .await;
+
+        // the proposed Action is the last message
+        let (proposed, previous) = match context.messages.split_last() {
+            Some((Message::Action { content, .. }, previous)) => (content.as_str(), previous),
+            _ => ("", context.messages.as_slice()),
+        };
+
+        let task = context.get_latest_task().unwrap_or_default();
+        let task = self.prompt_manager.build_task_prompt_with_notes(
+            &task,
+            &format!("# Proposed Action:\n{}\n", proposed.trim()),
+        );
+
+        for entry in attempt_chitchat(previous, task.to_prompt()) {
+            chat_history.add_chitchat(entry);
+        }
+
+        // prune the history if needed
+        chat_history.purge().await?;
+
+        Ok(chat_history)
+    }
+}
+
+/// Whether the verdict on the last `Verdict:` line approves the Action - an
+/// Action without a verdict is approved
+fn parse_verdict(review: &str) -> bool {
+    review
+        .lines()
+        .rev()
+        .find_map(|line| line.split_once("Verdict:"))
+        .map_or_else(
+            || {
+                warn!("No verdict in the review - approved");
+                true
+            },
+            |(_, verdict)| !verdict.to_lowercase().contains("revise"),
+        )
+}
+
+#[async_trait::async_trait]
+impl chains::Agent for Critic {
+    type Error = Error;
+
+    async fn act(&self, context: &Context) -> Result<Message, Error> {
+        let chat_history = self.convert_context_to_chat_history(context).await?;
+
+        let input = chat_history.make_input();
+
+        debug!(
+            "Querying model for a review with {} entries",
+            input.chat.len()
+        );
+
+        trace!("Querying model:\n{:#?}", input);
+
+        let res = self
+            .config
+            .model
+            .query(input, self.config.max_tokens)
+            .await?;
+
+        trace!("Got model response:\n{:#?}", res);
+
+        let action = match context.messages.last() {
+            Some(Message::Action { content, .. }) => content.clone(),
+            _ => String::new(),
+        };
+
+        // the scheduler notifies the observers of the critique
+        Ok(Message::Critique {
+            action,
+            approved: parse_verdict(&res.msg),
+            content: res.msg,
+            usage: res.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+    use crate::chains::Outcome;
+    use crate::tools::ToolUseError;
+
+    #[test]
+    fn verdicts_are_parsed() {
+        assert!(parse_verdict("Looks good.\nVerdict: approve"));
+        assert!(!parse_verdict(
+            "The parameter is missing.\nVerdict: **revise**"
+        ));
+        assert!(parse_verdict("Looks good."));
+    }
+
+    #[tokio::test]
+    async fn it_converts_context_to_chat_history() {
+        let mut context = Context::new();
+
+        context.add_message(Message::Task {
+            content: "Make me a bowl of cereal with milk".to_string(),
+        });
+        context.add_message(Message::Action {
+            content: "Action:\n```yaml\ntool_name: Closet\nparameters:\n  item: bowl\n```"
+                .to_string(),
+            usage: None,
+        });
+        context.add_message(Message::ActionResult {
+            invocation_count: 1,
+            tool_name: Some("Closet".to_string()),
+            extracted_input: None,
+            outcome: Outcome::ToolUseError {
+                e: ToolUseError::InvocationFailed("The closet is locked".to_string()),
+            },
+            warnings: vec![],
+        });
+        context.add_message(Message::Action {
+            content: "Action:\n```yaml\ntool_name: Closet\nparameters:\n  item: bowl\n```"
+                .to_string(),
+            usage: None,
+        });
+
+        let critic = Critic::new(SapiensConfig::default(), Toolbox::default());
+
+        let chat_history = critic.convert_context_to_chat_history(&context).await;
+
+        assert_debug_snapshot!(chat_history);
+    }
+}
@@ -1,10 +1,33 @@
+/// Critic agent - reviews the Actions before they are executed
+pub mod critic;
+
 /// OODA agents
 pub mod ooda;
 
-use crate::chains::Outcome;
+/// `ReAct` agent - Thought, Action, Observation
+pub mod react;
+
+/// Reflexion agent - reflects on a failed attempt
+pub mod reflexion;
+
+/// Planner agent - maintains the plan of a task
+pub mod plan;
+
+/// Routers - pick the next agent of a [`DynamicScheduler`]
+///
+/// [`DynamicScheduler`]: crate::chains::schedulers::DynamicScheduler
+pub mod router;
+
+/// Tree of Thoughts agents - propose and evaluate thoughts
+pub mod tot;
+
+use std::fmt::Display;
+
+use crate::chains::{Message, Outcome};
 use crate::context;
+use crate::context::ChatEntry;
+use crate::models::Role;
 use crate::prompt::Task;
-use crate::tools::ToolUseError;
 
 /// Error from the agent
 #[derive(thiserror::Error, Debug)]
@@ -17,6 +40,69 @@ pub enum Error {
     ModelError(#[from] crate::models::Error),
 }
 
+/// Convert the actions and their results into chat entries followed by a
+/// `prompt` from the User
+///
+/// The `prompt` follows the last result in the same entry as two consecutive
+/// entries from the User would be merged by the chat history.
+pub(crate) fn attempt_chitchat(messages: &[Message], prompt: String) -> Vec<ChatEntry> {
+    let mut entries = Vec::new();
+    for m in messages {
+        match m {
+            Message::Action { content, .. } => {
+                entries.push(ChatEntry {
+                    msg: content.clone(),
+                    role: Role::Assistant,
+                });
+            }
+            Message::ActionResult {
+                invocation_count,
+                tool_name,
+                outcome,
+                warnings,
+                ..
+            } => {
+                entries.push(ChatEntry {
+                    msg: format_outcome_only(*invocation_count, tool_name, outcome, warnings),
+                    role: Role::User,
+                });
+            }
+            Message::Critique {
+                action, content, ..
+            } => {
+                entries.push(ChatEntry {
+                    msg: action.clone(),
+                    role: Role::Assistant,
+                });
+                entries.push(ChatEntry {
+                    msg: Task::critique_prompt(content),
+                    role: Role::User,
+                });
+            }
+            _ => {
+                // Nothing
+            }
+        }
+    }
+
+    match entries.last_mut() {
+        Some(last) if last.role == Role::User => {
+            last.msg = format!("{}\n{prompt}", last.msg);
+        }
+        _ => entries.push(ChatEntry {
+            msg: prompt,
+            role: Role::User,
+        }),
+    }
+
+    entries
+}
+
+/// Format the critique of a rejected Action
+pub(crate) fn format_critique(task: &Task, critique: &str) -> String {
+    format!("{}\n{}", Task::critique_prompt(critique), task.to_prompt())
+}
+
 /// Format the outcome of a task
 #[allow(clippy::ref_option)]
 pub(crate) fn format_outcome(
@@ -24,44 +110,53 @@ pub(crate) fn format_outcome(
     invocation_count: usize,
     tool_name: &Option<String>,
     outcome: &Outcome,
+    warnings: &[impl Display],
 ) -> String {
-    /// Maximum number of characters in the response
-    const MAX_RESPONSE_CHAR: usize = 2048;
+    let msg = format_outcome_only(invocation_count, tool_name, outcome, warnings);
+    format!("{}\n{}", msg, task.to_prompt())
+}
 
-    match outcome {
-        Outcome::Success { result } => {
-            let msg = Task::action_success_prompt(
-                tool_name.clone().unwrap_or_else(|| "unknown".to_string()),
-                invocation_count,
-                result,
-            );
-
-            // if the response is too long, we add an error message to the chat
-            // history instead
-            if msg.len() > MAX_RESPONSE_CHAR {
-                let msg = format!("The response is too long ({}B). Max allowed is {}B. Ask for a shorter response or use SandboxedPython Tool to process the response the data.",
-                                      msg.len(), MAX_RESPONSE_CHAR);
-                let e = ToolUseError::InvocationFailed(msg);
-                let msg = Task::action_failed_prompt(
-                    tool_name.clone().unwrap_or_else(|| "unknown".to_string()),
-                    &e,
-                );
-
-                format!("{}\n{}", msg, task.to_prompt())
-            } else {
-                format!("{}\n{}", msg, task.to_prompt())
-            }
-        }
+/// Format the outcome of an invocation - without the task prompt
+#[allow(clippy::ref_option)]
+pub(crate) fn format_outcome_only(
+    invocation_count: usize,
+    tool_name: &Option<String>,
+    outcome: &Outcome,
+    warnings: &[impl Display],
+) -> String {
+    let msg = match outcome {
+        // the too long results are already replaced by a preview of their
+        // artifact - see `ArtifactStore::shrink`
+        Outcome::Success { result } => Task::action_success_prompt(
+            tool_name.clone().unwrap_or_else(|| "unknown".to_string()),
+            invocation_count,
+            result,
+        ),
         Outcome::NoValidInvocationsFound { e } | Outcome::NoInvocationsFound { e } => {
-            let msg = Task::invalid_action_prompt(e);
-            format!("{}\n{}", msg, task.to_prompt())
+            Task::invalid_action_prompt(e)
         }
-        Outcome::ToolUseError { e } => {
-            let msg = Task::action_failed_prompt(
-                tool_name.clone().unwrap_or_else(|| "unknown".to_string()),
-                e,
-            );
-            format!("{}\n{}", msg, task.to_prompt())
+        Outcome::ToolUseError { e } => Task::action_failed_prompt(
+            tool_name.clone().unwrap_or_else(|| "unknown".to_string()),
+            e,
+        ),
+        Outcome::Multiple { outcomes } => {
+            let mut msgs = vec![Task::multiple_actions_prompt(
+                outcomes.len(),
+                invocation_count,
+            )];
+
+            // each of them is formatted as if it was the only one
+            msgs.extend(outcomes.iter().map(|o| {
+                format_outcome_only(1, &Some(o.tool_name.clone()), &o.outcome, &o.warnings)
+            }));
+
+            msgs.join("\n")
         }
+    };
+
+    if warnings.is_empty() {
+        msg
+    } else {
+        format!("{}\n{}", msg, Task::invocation_warnings_prompt(warnings))
     }
 }
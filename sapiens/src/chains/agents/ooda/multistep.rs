@@ -2,7 +2,7 @@ use std::fmt::{Debug, Formatter};
 
 use tracing::{debug, trace};
 
-use crate::chains::agents::{format_outcome, Error};
+use crate::chains::agents::{format_critique, format_outcome, Error};
 use crate::chains::{Context, Message};
 use crate::context::{ChatEntry, ChatHistory};
 use crate::models::Role;
@@ -318,14 +318,29 @@ impl AgentRole {
                             invocation_count,
                             tool_name,
                             outcome,
+                            warnings,
                             ..
                         } => {
-                            let entry =
-                                format_outcome(&task, *invocation_count, tool_name, outcome);
+                            let entry = format_outcome(
+                                &task,
+                                *invocation_count,
+                                tool_name,
+                                outcome,
+                                warnings,
+                            );
 
                             user_msg.push(entry);
                         }
-                        Message::Task { .. } => {
+                        Message::Critique {
+                            action, content, ..
+                        } => {
+                            user_msg.push(action.clone());
+                            user_msg.push(format_critique(&task, content));
+                        }
+                        Message::Task { .. }
+                        | Message::Reflection { .. }
+                        | Message::Routing { .. }
+                        | Message::Plan { .. } => {
                             // Nothing
                         }
                     }
@@ -363,14 +378,29 @@ impl AgentRole {
                             invocation_count,
                             tool_name,
                             outcome,
+                            warnings,
                             ..
                         } => {
-                            let entry =
-                                format_outcome(&task, *invocation_count, tool_name, outcome);
+                            let entry = format_outcome(
+                                &task,
+                                *invocation_count,
+                                tool_name,
+                                outcome,
+                                warnings,
+                            );
 
                             user_msg.push(entry);
                         }
-                        Message::Task { .. } => {
+                        Message::Critique {
+                            action, content, ..
+                        } => {
+                            user_msg.push(action.clone());
+                            user_msg.push(format_critique(&task, content));
+                        }
+                        Message::Task { .. }
+                        | Message::Reflection { .. }
+                        | Message::Routing { .. }
+                        | Message::Plan { .. } => {
                             // Nothing
                         }
                     }
@@ -406,14 +436,29 @@ impl AgentRole {
                             invocation_count,
                             tool_name,
                             outcome,
+                            warnings,
                             ..
                         } => {
-                            let entry =
-                                format_outcome(&task, *invocation_count, tool_name, outcome);
+                            let entry = format_outcome(
+                                &task,
+                                *invocation_count,
+                                tool_name,
+                                outcome,
+                                warnings,
+                            );
 
                             user_msg.push(entry);
                         }
-                        Message::Task { .. } => {
+                        Message::Critique {
+                            action, content, ..
+                        } => {
+                            user_msg.push(action.clone());
+                            user_msg.push(format_critique(&task, content));
+                        }
+                        Message::Task { .. }
+                        | Message::Reflection { .. }
+                        | Message::Routing { .. }
+                        | Message::Plan { .. } => {
                             // Nothing
                         }
                     }
@@ -448,14 +493,44 @@ impl AgentRole {
                             invocation_count,
                             tool_name,
                             outcome,
+                            warnings,
                             ..
                         } => {
-                            let entry =
-                                format_outcome(&task, *invocation_count, tool_name, outcome);
+                            let entry = format_outcome(
+                                &task,
+                                *invocation_count,
+                                tool_name,
+                                outcome,
+                                warnings,
+                            );
 
                             user_msg.push(entry);
                         }
-                        Message::Task { .. } => {
+                        Message::Critique {
+                            action, content, ..
+                        } => {
+                            if !user_msg.is_empty() {
+                                // Add the user message to the chat history as a message from the
+                                // User
+                                chat_history.add_chitchat(ChatEntry {
+                                    msg: user_msg.join("\n"),
+                                    role: Role::User,
+                                });
+
+                                user_msg.clear();
+                            }
+
+                            // the rejected Action and its critique
+                            chat_history.add_chitchat(ChatEntry {
+                                msg: action.clone(),
+                                role: Role::Assistant,
+                            });
+                            user_msg.push(format_critique(&task, content));
+                        }
+                        Message::Task { .. }
+                        | Message::Reflection { .. }
+                        | Message::Routing { .. }
+                        | Message::Plan { .. } => {
                             // Nothing
                         }
                     }
@@ -998,6 +1073,7 @@ mod tests {
                 .trim()
                 .to_string(),
             },
+            warnings: vec![],
         });
         context
     }
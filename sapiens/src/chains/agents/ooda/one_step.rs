@@ -1,6 +1,6 @@
 use tracing::{debug, trace};
 
-use crate::chains::agents::{format_outcome, Error};
+use crate::chains::agents::{format_critique, format_outcome, Error};
 use crate::chains::{Context, Message};
 use crate::context::{ChatEntry, ChatHistory};
 use crate::models::Role;
@@ -184,9 +184,11 @@ impl Agent {
                     invocation_count,
                     tool_name,
                     outcome,
+                    warnings,
                     ..
                 } => {
-                    let entry = format_outcome(&task, *invocation_count, tool_name, outcome);
+                    let entry =
+                        format_outcome(&task, *invocation_count, tool_name, outcome, warnings);
 
                     // add an error message to the chat history
                     let entry = ChatEntry {
@@ -197,6 +199,19 @@ impl Agent {
                     // Add the response to the chat history
                     chat_history.add_chitchat(entry);
                 }
+                Message::Critique {
+                    action, content, ..
+                } => {
+                    // the rejected action and its critique
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: action.clone(),
+                        role: Role::Assistant,
+                    });
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: format_critique(&task, content),
+                        role: Role::User,
+                    });
+                }
                 _ => {
                     // Nothing
                 }
@@ -330,6 +345,7 @@ mod tests {
                 "}
                 .to_string(),
             },
+            warnings: vec![],
         });
 
         let toolbox = Toolbox::default();
@@ -0,0 +1,310 @@
+use tracing::{debug, trace, warn};
+
+use crate::chains::agents::{format_outcome_only, Error};
+use crate::chains::plan::{Goal, GoalStatus, Plan};
+use crate::chains::{Context, Message};
+use crate::context::{ChatEntry, ChatHistory};
+use crate::models::Role;
+use crate::tools::toolbox::Toolbox;
+use crate::{chains, prompt, SapiensConfig, WeakRuntimeObserver};
+
+const PREFIX: &str = r"You are Sapiens, a large language model planning how to answer questions with the tools available to another agent.
+You maintain a plan: a list of sub-goals, in order, each with its status. The other agent works through the sub-goals, one Action at a time.
+
+- After each Action, describe to yourself what happened, explain it and update the plan.
+- Mark the sub-goals as `done` when achieved, as `failed` when they cannot be achieved and the one being worked on as `in_progress`.
+- Replace the failed sub-goals with other ways to reach the goal.
+- The last sub-goal is always to use the Conclude Tool to provide the answer.
+- Only use YAML for the plan. Be concise.
+";
+
+const TOOL_PREFIX: &str = r"
+# The following are the ONLY Tools the other agent can use for its Actions:
+";
+
+const RESPONSE_FORMAT: &str = r"
+# Format of your response
+
+You must use the following format for your response. Comments are in bold and should be removed from your response.
+====================
+```yaml
+goals:
+  - description: <What is to be achieved>
+    status: <pending|in_progress|done|failed>
+```
+====================
+";
+
+const PROTO_EXCHANGE_2: &str = r"
+```yaml
+goals:
+  - description: Sort the list with the sorted() function of Python in the SandboxedPython Tool
+    status: in_progress
+  - description: Use the Conclude Tool to provide the sorted list
+    status: pending
+```
+";
+
+/// A planner - updates the [`Plan`] after each step
+pub struct Planner {
+    prompt_manager: prompt::Manager,
+    config: SapiensConfig,
+    observer: WeakRuntimeObserver,
+}
+
+impl Planner {
+    /// Create a new [`Planner`].
+    #[must_use]
+    pub fn new(config: SapiensConfig, toolbox: Toolbox, observer: WeakRuntimeObserver) -> Self {
+        let system_prompt =
+            "You are an agent named Sapiens planning how to interact with the WORLD.".to_string();
+
+        let prompt = "Update the plan: the statuses of the sub-goals and the sub-goals themselves if needed.\nPlan?".to_string();
+
+        let prompt_manager = prompt::Manager::new(
+            toolbox,
+            system_prompt,
+            prompt,
+            PREFIX.to_string(),
+            TOOL_PREFIX.to_string(),
+            RESPONSE_FORMAT.to_string(),
+        );
+        Self {
+            prompt_manager,
+            config,
+            observer,
+        }
+    }
+
+    /// The notes on the last step and the current plan
+    fn notes(context: &Context) -> String {
+        let mut notes = String::new();
+
+        // the last Action and its result
+        let mut last_action = None;
+        let mut last_result = None;
+        for m in &context.messages {
+            match m {
+                Message::Action { content, .. } => {
+                    last_action = Some(content.as_str());
+                    last_result = None;
+                }
+                Message::ActionResult {
+                    invocation_count,
+                    tool_name,
+                    outcome,
+                    warnings,
+                    ..
+                } => {
+                    last_result = Some(format_outcome_only(
+                        *invocation_count,
+                        tool_name,
+                        outcome,
+                        warnings,
+                    ));
+                }
+                _ => {
+                    // Nothing
+                }
+            }
+        }
+
+        if let Some(action) = last_action {
+            notes.push_str("# Last Action:\n");
+            notes.push_str(action.trim());
+            notes.push('\n');
+        }
+
+        if let Some(result) = last_result {
+            notes.push_str("# Its result:\n");
+            notes.push_str(result.trim());
+            notes.push('\n');
+        }
+
+        notes.push_str("# Current plan:\n");
+        match context.get_plan() {
+            Some(plan) => notes.push_str(&plan.to_string()),
+            None => notes.push_str("No plan yet.\n"),
+        }
+
+        notes
+    }
+
+    async fn convert_context_to_chat_history(
+        &self,
+        context: &Context,
+    ) -> Result<ChatHistory, Error> {
+        let max_token = { self.config.model.context_size().await };
+        let mut chat_history = ChatHistory::new(self.config.clone(), max_token);
+
+        let warmup_task = self.prompt_manager.build_task_prompt_with_notes(
+            "Sort in ascending order: [2, 3, 1, 4, 5]",
+            "# Current plan:\nNo plan yet.\n",
+        );
+
+        let examples = vec![(warmup_task.to_prompt(), PROTO_EXCHANGE_2.trim().to_string())];
+
+        self.prompt_manager
+            .populate_chat_history(&mut chat_history, examples)
+            .await;
+
+        // the plan carries what happened before the last step
+        let task = context.get_latest_task().unwrap();
+        let task = self
+            .prompt_manager
+            .build_task_prompt_with_notes(&task, &Self::notes(context));
+
+        chat_history.add_chitchat(ChatEntry {
+            msg: task.to_prompt(),
+            role: Role::User,
+        });
+
+        // prune the history if needed
+        chat_history.purge().await?;
+
+        Ok(chat_history)
+    }
+}
+
+/// Parse the plan in the first YAML block of a response - or in the whole
+/// response
+fn parse_plan(response: &str) -> Option<Plan> {
+    let yaml = response
+        .split_once("```yaml")
+        .map_or(response, |(_, rest)| {
+            rest.split_once("```").map_or(rest, |(yaml, _)| yaml)
+        });
+
+    serde_yaml::from_str::<Plan>(yaml)
+        .ok()
+        .filter(|plan| !plan.goals.is_empty())
+}
+
+#[async_trait::async_trait]
+impl chains::Agent for Planner {
+    type Error = Error;
+
+    async fn act(&self, context: &Context) -> Result<Message, Error> {
+        let chat_history = self.convert_context_to_chat_history(context).await?;
+
+        let input = chat_history.make_input();
+
+        debug!(
+            "Querying model for a plan with {} entries",
+            input.chat.len()
+        );
+
+        trace!("Querying model:\n{:#?}", input);
+
+        let res = self
+            .config
+            .model
+            .query(input, self.config.max_tokens)
+            .await?;
+
+        trace!("Got model response:\n{:#?}", res);
+
+        if let Some(observer) = self.observer.upgrade() {
+            observer
+                .lock()
+                .await
+                .on_model_update(res.clone().into())
+                .await;
+        }
+
+        // keep the previous plan - or make the task the only sub-goal - if the
+        // response is not a plan
+        let plan = parse_plan(&res.msg).unwrap_or_else(|| {
+            warn!("Invalid plan - keeping the previous one");
+            context.get_plan().unwrap_or_else(|| Plan {
+                goals: vec![Goal {
+                    description: context.get_latest_task().unwrap_or_default(),
+                    status: GoalStatus::InProgress,
+                }],
+            })
+        });
+
+        Ok(Message::Plan {
+            plan,
+            usage: res.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+    use crate::chains::Outcome;
+    use crate::void_observer;
+
+    fn context_with_a_plan() -> Context {
+        let mut context = Context::new();
+
+        context.add_message(Message::Task {
+            content: "Sort in ascending order: [2, 3, 1, 4, 5]".to_string(),
+        });
+
+        context.add_message(Message::Plan {
+            plan: parse_plan(PROTO_EXCHANGE_2).unwrap(),
+            usage: None,
+        });
+
+        context.add_message(Message::Action {
+            content: "Thought: Python can sort.\nAction:\n```yaml\ntool_name: SandboxedPython\nparameters:\n  code: print(sorted([2, 3, 1, 4, 5]))\n```".to_string(),
+            usage: None,
+        });
+
+        context.add_message(Message::ActionResult {
+            invocation_count: 1,
+            tool_name: Some("SandboxedPython".to_string()),
+            extracted_input: None,
+            outcome: Outcome::Success {
+                result: "stdout: |\n  [1, 2, 3, 4, 5]\nstderr: ''\n".to_string(),
+            },
+            warnings: vec![],
+        });
+
+        context
+    }
+
+    #[test]
+    fn plans_are_parsed() {
+        let plan = parse_plan(PROTO_EXCHANGE_2).unwrap();
+
+        assert_eq!(plan.goals.len(), 2);
+        assert_eq!(plan.goals[0].status, GoalStatus::InProgress);
+        assert_eq!(plan.current_goal(), Some(&plan.goals[0]));
+        assert_eq!(
+            plan.to_string(),
+            "1. [in_progress] Sort the list with the sorted() function of Python in the SandboxedPython Tool\n2. [pending] Use the Conclude Tool to provide the sorted list\n"
+        );
+
+        assert_eq!(
+            parse_plan("goals:\n  - description: Conclude"),
+            Some(Plan {
+                goals: vec![Goal {
+                    description: "Conclude".to_string(),
+                    status: GoalStatus::Pending,
+                }],
+            })
+        );
+        assert_eq!(parse_plan("I will sort the list."), None);
+    }
+
+    #[tokio::test]
+    async fn it_converts_context_to_chat_history() {
+        let observer = void_observer();
+        let weak_observer = Arc::downgrade(&observer);
+        let planner = Planner::new(SapiensConfig::default(), Toolbox::default(), weak_observer);
+
+        let chat_history = planner
+            .convert_context_to_chat_history(&context_with_a_plan())
+            .await;
+
+        assert_debug_snapshot!(chat_history);
+    }
+}
@@ -0,0 +1,367 @@
+use tracing::{debug, trace};
+
+use crate::chains::agents::{format_critique, format_outcome, Error};
+use crate::chains::{Context, Message};
+use crate::context::{ChatEntry, ChatHistory};
+use crate::models::Role;
+use crate::tools::toolbox::Toolbox;
+use crate::{chains, prompt, SapiensConfig, WeakRuntimeObserver};
+
+/// A `ReAct` agent - alternates reasoning traces and actions
+pub struct Agent {
+    prompt_manager: prompt::Manager,
+    config: SapiensConfig,
+    observer: WeakRuntimeObserver,
+}
+
+const PREFIX: &str = r"You are Sapiens, a large language model assisting the WORLD. Use available tools to answer the question as best as you can.
+You will proceed iteratively with a Thought, an Action and its Observation.
+
+- The Observation of the Action will be provided to you.
+- Never produce the Observation yourself.
+- Only use YAML for the Action.
+- The loop will repeated until you have the answer to the original question.
+- No task is complete until the Conclude Tool is used to provide the answer.
+- You cannot use jinja2 templating in your response. Be concise.
+";
+
+const TOOL_PREFIX: &str = r"
+# The following are the ONLY Tools you can use for your Actions:
+";
+
+const RESPONSE_FORMAT: &str = r"
+# Format of your response
+
+You must use the following format for your response. Comments are in bold and should be removed from your response.
+====================
+Thought: **Reason about the last Observation and what to do next to answer the question.**
+Action:
+```yaml
+tool_name: <ToolName>
+parameters:
+    <...>
+```
+====================
+
+Notes:
+- Action has the following fields: `tool_name` and `parameters` ONLY.
+- `parameters` uses the format specified for the Tool.
+- `responses_content` is the format you can expect of the Observation of the Action. Never use it in your response.
+- One Thought and one Action at a time. No more. No less.
+";
+
+const PROTO_EXCHANGE_2: &str = r#"
+Thought: I need to sort the list [2, 3, 1, 4, 5] in ascending order. The sorted() function of Python in the SandboxedPython Tool can do it.
+Action:
+```yaml
+tool_name: SandboxedPython
+parameters:
+  code: |
+    lst = [2, 3, 1, 4, 5]
+    sorted_list = sorted(lst)
+    print(f"The sorted list is {sorted_list}")
+```
+"#;
+
+const PROTO_EXCHANGE_3: &str = r"
+Observation:
+# Action SandboxedPython response:
+```yaml
+stdout: |
+  The sorted list is [1, 2, 3, 4, 5]
+stderr: ''
+```
+";
+
+const PROTO_EXCHANGE_4: &str = r"
+Thought: The sorted list is [1, 2, 3, 4, 5]. I have the answer, I need to use the Conclude Tool.
+Action:
+```yaml
+tool_name: Conclude
+parameters:
+  original_question: |
+    Sort in ascending order: [2, 3, 1, 4, 5]
+  conclusion: |
+    The ascending sorted list is [1, 2, 3, 4, 5].
+```
+";
+
+impl Agent {
+    /// Create a new [`Agent`].
+    #[must_use]
+    pub fn new(config: SapiensConfig, toolbox: Toolbox, observer: WeakRuntimeObserver) -> Self {
+        let system_prompt =
+            "You are an agent named Sapiens interacting with the WORLD. Listen to the WORLD!"
+                .to_string();
+
+        let prompt = "Do you have the answer? Use the Conclude Tool to terminate the task.\nThought, Action?".to_string();
+
+        let prompt_manager = prompt::Manager::new(
+            toolbox,
+            system_prompt,
+            prompt,
+            PREFIX.to_string(),
+            TOOL_PREFIX.to_string(),
+            RESPONSE_FORMAT.to_string(),
+        );
+        Self {
+            prompt_manager,
+            config,
+            observer,
+        }
+    }
+
+    async fn convert_context_to_chat_history(
+        &self,
+        context: &Context,
+    ) -> Result<ChatHistory, Error> {
+        // Create a new chat history
+        let max_token = { self.config.model.context_size().await };
+        let mut chat_history = ChatHistory::new(self.config.clone(), max_token);
+
+        let warmup_task = self
+            .prompt_manager
+            .build_task_prompt("Sort in ascending order: [2, 3, 1, 4, 5]");
+
+        let examples = vec![
+            (warmup_task.to_prompt(), PROTO_EXCHANGE_2.trim().to_string()),
+            (
+                (format!("{}{}", PROTO_EXCHANGE_3, warmup_task.to_prompt()))
+                    .trim()
+                    .to_string(),
+                PROTO_EXCHANGE_4.trim().to_string(),
+            ),
+        ];
+
+        // Add the prompts to the chat history
+        self.prompt_manager
+            .populate_chat_history(&mut chat_history, examples)
+            .await;
+
+        // Convert the context to a chat history
+        // - get the latest 'Task' from the context
+        let task = context.get_latest_task().unwrap();
+        let mut notes = prompt::Task::reflections_notes(&context.get_reflections());
+        if let Some(plan) = context.get_plan() {
+            notes.push_str(&prompt::Task::plan_notes(&plan));
+        }
+        let task = self
+            .prompt_manager
+            .build_task_prompt_with_notes(&task, &notes);
+
+        // - get the thoughts+actions and their observations
+        for m in &context.messages {
+            match m {
+                Message::Action { content, .. } => {
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: content.clone(),
+                        role: Role::Assistant,
+                    });
+                }
+                Message::ActionResult {
+                    invocation_count,
+                    tool_name,
+                    outcome,
+                    warnings,
+                    ..
+                } => {
+                    let entry =
+                        format_outcome(&task, *invocation_count, tool_name, outcome, warnings);
+
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: format!("Observation:\n{entry}"),
+                        role: Role::User,
+                    });
+                }
+                Message::Critique {
+                    action, content, ..
+                } => {
+                    // the rejected action and its critique
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: action.clone(),
+                        role: Role::Assistant,
+                    });
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: format_critique(&task, content),
+                        role: Role::User,
+                    });
+                }
+                _ => {
+                    // Nothing
+                }
+            }
+        }
+
+        if chat_history.is_chitchat_empty() {
+            // Add the recurring prompts to the chat history
+            chat_history.add_chitchat(ChatEntry {
+                msg: task.to_prompt(),
+                role: Role::User,
+            });
+        }
+
+        // prune the history if needed
+        chat_history.purge().await?;
+
+        Ok(chat_history)
+    }
+}
+
+#[async_trait::async_trait]
+impl chains::Agent for Agent {
+    type Error = Error;
+
+    async fn act(&self, context: &Context) -> Result<Message, Error> {
+        let chat_history = self.convert_context_to_chat_history(context).await?;
+
+        // Query the model
+        let input = chat_history.make_input();
+
+        debug!(
+            min_tokens = self.config.min_tokens_for_completion,
+            max_tokens = self.config.max_tokens,
+            "Querying model with {} entries",
+            input.chat.len()
+        );
+
+        trace!("Querying model:\n{:#?}", input);
+
+        let res = self
+            .config
+            .model
+            .query(input, self.config.max_tokens)
+            .await?;
+
+        trace!("Got model response:\n{:#?}", res);
+
+        // Show the message from the assistant
+        if let Some(observer) = self.observer.upgrade() {
+            observer
+                .lock()
+                .await
+                .on_model_update(res.clone().into())
+                .await;
+        }
+
+        // The Thought and the Action - the Observation is added by the runtime
+        Ok(Message::Action {
+            content: res.msg,
+            usage: res.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use indoc::indoc;
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+    use crate::chains::plan::{Goal, GoalStatus, Plan};
+    use crate::chains::Outcome;
+    use crate::void_observer;
+
+    #[tokio::test]
+    async fn it_converts_context_to_chat_history() {
+        let mut context = Context::new();
+
+        context.add_message(Message::Task {
+            content: "Sort in ascending order: [2, 3, 1, 4, 5]".to_string(),
+        });
+
+        context.add_message(Message::Action {
+            content: indoc! {r"
+            Thought: I need to sort the list. SandboxedPython can do it.
+            Action:
+            ```yaml
+            tool_name: SandboxedPython
+            parameters:
+              code: |
+                print(sorted([2, 3, 1, 4, 5]))
+            ```
+            "}
+            .to_string(),
+            usage: None,
+        });
+
+        context.add_message(Message::ActionResult {
+            invocation_count: 1,
+            tool_name: Some("SandboxedPython".to_string()),
+            extracted_input: None,
+            outcome: Outcome::Success {
+                result: indoc! {r"
+                stdout: |
+                  [1, 2, 3, 4, 5]
+                stderr: ''
+                "}
+                .to_string(),
+            },
+            warnings: vec![],
+        });
+
+        let toolbox = Toolbox::default();
+
+        let observer = void_observer();
+        let weak_observer = Arc::downgrade(&observer);
+        let agent = Agent::new(SapiensConfig::default(), toolbox, weak_observer);
+
+        let chat_history = agent.convert_context_to_chat_history(&context).await;
+
+        assert_debug_snapshot!(chat_history);
+    }
+
+    #[tokio::test]
+    async fn it_repeats_the_plan_in_the_last_prompt() {
+        let mut context = Context::new();
+
+        context.add_message(Message::Task {
+            content: "Sort in ascending order: [2, 3, 1, 4, 5]".to_string(),
+        });
+
+        let plan = Plan {
+            goals: vec![
+                Goal {
+                    description: "Sort the list with Python".to_string(),
+                    status: GoalStatus::Done,
+                },
+                Goal {
+                    description: "Conclude".to_string(),
+                    status: GoalStatus::InProgress,
+                },
+            ],
+        };
+        context.add_message(Message::Plan {
+            plan: plan.clone(),
+            usage: None,
+        });
+
+        context.add_message(Message::Action {
+            content: "Action:\n```yaml\ntool_name: SandboxedPython\nparameters:\n  code: print(sorted([2, 3, 1, 4, 5]))\n```".to_string(),
+            usage: None,
+        });
+
+        context.add_message(Message::ActionResult {
+            invocation_count: 1,
+            tool_name: Some("SandboxedPython".to_string()),
+            extracted_input: None,
+            outcome: Outcome::Success {
+                result: "stdout: |\n  [1, 2, 3, 4, 5]\nstderr: ''\n".to_string(),
+            },
+            warnings: vec![],
+        });
+
+        let observer = void_observer();
+        let weak_observer = Arc::downgrade(&observer);
+        let agent = Agent::new(SapiensConfig::default(), Toolbox::default(), weak_observer);
+
+        let chat_history = agent
+            .convert_context_to_chat_history(&context)
+            .await
+            .unwrap();
+
+        let last = chat_history.make_input().chat.pop().unwrap();
+        assert!(last.msg.contains(&plan.to_string()));
+    }
+}
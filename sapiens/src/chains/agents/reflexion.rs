@@ -0,0 +1,156 @@
+use tracing::{debug, trace};
+
+use crate::chains::agents::{attempt_chitchat, Error};
+use crate::chains::Message;
+use crate::context::{ChatEntry, ChatHistory};
+use crate::models::Role;
+use crate::{SapiensConfig, WeakRuntimeObserver};
+
+const SYSTEM_PROMPT: &str =
+    "You are an advanced reasoning agent that can improve based on self-reflection.";
+
+const INSTRUCTIONS: &str = r"You will be given a previous attempt at a task. It failed.
+Diagnose in a few sentences a possible reason for the failure and devise a new, concise, high level plan that aims to mitigate the same failure.
+Use complete sentences. Do not give any Action.
+";
+
+/// A Reflexion agent - writes a self-reflection on a failed attempt
+pub struct Reflector {
+    config: SapiensConfig,
+    observer: WeakRuntimeObserver,
+}
+
+impl Reflector {
+    /// Create a new [`Reflector`].
+    #[must_use]
+    pub const fn new(config: SapiensConfig, observer: WeakRuntimeObserver) -> Self {
+        Self { config, observer }
+    }
+
+    /// Convert the attempt into a chat history - the oldest steps are pruned
+    /// if needed
+    async fn convert_attempt_to_chat_history(
+        &self,
+        task: &str,
+        attempt: &[Message],
+        failure: &str,
+    ) -> Result<ChatHistory, Error> {
+        let max_token = { self.config.model.context_size().await };
+        let mut chat_history = ChatHistory::new(self.config.clone(), max_token);
+
+        chat_history.set_context(vec![
+            ChatEntry {
+                role: Role::System,
+                msg: SYSTEM_PROMPT.to_string(),
+            },
+            ChatEntry {
+                role: Role::User,
+                msg: format!("{INSTRUCTIONS}\n# Task:\n{task}")
+                    .trim()
+                    .to_string(),
+            },
+        ]);
+
+        let failure = format!("# The attempt failed:\n{failure}\n# Your reflection:");
+        for entry in attempt_chitchat(attempt, failure) {
+            chat_history.add_chitchat(entry);
+        }
+
+        // prune the history if needed
+        chat_history.purge().await?;
+
+        Ok(chat_history)
+    }
+
+    /// Reflect on a failed `attempt` at a `task`
+    ///
+    /// Returns a [`Message::Reflection`].
+    pub async fn reflect(
+        &self,
+        task: &str,
+        attempt: &[Message],
+        failure: &str,
+    ) -> Result<Message, Error> {
+        let chat_history = self
+            .convert_attempt_to_chat_history(task, attempt, failure)
+            .await?;
+
+        let input = chat_history.make_input();
+
+        debug!(
+            "Querying model for a reflection with {} entries",
+            input.chat.len()
+        );
+
+        trace!("Querying model:\n{:#?}", input);
+
+        let res = self
+            .config
+            .model
+            .query(input, self.config.max_tokens)
+            .await?;
+
+        trace!("Got model response:\n{:#?}", res);
+
+        if let Some(observer) = self.observer.upgrade() {
+            observer
+                .lock()
+                .await
+                .on_model_update(res.clone().into())
+                .await;
+        }
+
+        Ok(Message::Reflection {
+            content: res.msg,
+            usage: res.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+    use crate::chains::Outcome;
+    use crate::tools::ToolUseError;
+    use crate::void_observer;
+
+    #[tokio::test]
+    async fn it_converts_the_attempt_to_chat_history() {
+        let attempt = vec![
+            Message::Task {
+                content: "Make me a bowl of cereal with milk".to_string(),
+            },
+            Message::Action {
+                content: "Thought: I need a bowl.\nAction:\n```yaml\ntool_name: Closet\nparameters:\n  item: bowl\n```".to_string(),
+                usage: None,
+            },
+            Message::ActionResult {
+                invocation_count: 1,
+                tool_name: Some("Closet".to_string()),
+                extracted_input: None,
+                outcome: Outcome::ToolUseError {
+                    e: ToolUseError::InvocationFailed("The closet is locked".to_string()),
+                },
+                warnings: vec![],
+            },
+        ];
+
+        let observer = void_observer();
+        let weak_observer = Arc::downgrade(&observer);
+        let reflector = Reflector::new(SapiensConfig::default(), weak_observer);
+
+        let chat_history = reflector
+            .convert_attempt_to_chat_history(
+                "Make me a bowl of cereal with milk",
+                &attempt,
+                "Max steps reached",
+            )
+            .await;
+
+        assert_debug_snapshot!(chat_history);
+    }
+}
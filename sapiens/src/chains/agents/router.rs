@@ -0,0 +1,311 @@
+use tracing::{debug, trace};
+
+use crate::chains::agents::Error;
+use crate::chains::{Context, Message, Outcome};
+use crate::context::{ChatEntry, ChatHistory};
+use crate::models::Role;
+use crate::{chains, SapiensConfig};
+
+/// How many of the last messages are shown to the [`ModelRouter`]
+const MAX_SHOWN_MESSAGES: usize = 3;
+
+const SYSTEM_PROMPT: &str = "You are a router deciding which agent of a team works next on a task.";
+
+const INSTRUCTIONS: &str = r"You will be given a task, the agents of the team and the last messages they produced.
+Pick the agent that should work next. Skip the agents whose work is not needed.
+Use the following format for your response:
+Agent: <name of the agent>
+Reason: <why, in one sentence>
+";
+
+/// The names of the agents of the multi-step OODA chain
+pub mod ooda {
+    /// Observes the task and the results of the Actions
+    pub const OBSERVER: &str = "Observer";
+    /// Orients the work from the observations
+    pub const ORIENTER: &str = "Orienter";
+    /// Decides what to do next
+    pub const DECIDER: &str = "Decider";
+    /// Acts on the decision with a Tool
+    pub const ACTOR: &str = "Actor";
+}
+
+/// Rules picking the next agent - its name and the reason
+pub type Rules = Box<dyn Fn(&Context) -> (String, String) + Send + Sync>;
+
+/// A router following rules over the last [`Message`]
+pub struct RuleRouter {
+    rules: Rules,
+}
+
+impl RuleRouter {
+    /// Create a new [`RuleRouter`]
+    #[must_use]
+    pub fn new(rules: Rules) -> Self {
+        Self { rules }
+    }
+
+    /// The rules of the multi-step OODA chain - the Orientation is skipped
+    /// after a successful Action
+    #[must_use]
+    pub fn ooda() -> Self {
+        Self::new(Box::new(|context| {
+            let (agent, reason) = match context.messages.last() {
+                Some(Message::Observation { .. }) => {
+                    let last_result = context.messages.iter().rev().find_map(|m| match m {
+                        Message::ActionResult { outcome, .. } => Some(outcome),
+                        _ => None,
+                    });
+
+                    match last_result {
+                        Some(Outcome::Success { .. }) => (
+                            ooda::DECIDER,
+                            "The last Action succeeded - no need to reorient",
+                        ),
+                        Some(_) => (ooda::ORIENTER, "The last Action failed - reorient"),
+                        None => (ooda::ORIENTER, "The task has to be oriented"),
+                    }
+                }
+                Some(Message::Orientation { .. }) => (ooda::DECIDER, "Oriented - decide"),
+                Some(Message::Decision { .. }) => (ooda::ACTOR, "Decided - act"),
+                Some(Message::ActionResult { .. }) => {
+                    (ooda::OBSERVER, "Observe the result of the Action")
+                }
+                _ => (ooda::OBSERVER, "Observe the task"),
+            };
+
+            (agent.to_string(), reason.to_string())
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl chains::Agent for RuleRouter {
+    type Error = Error;
+
+    async fn act(&self, context: &Context) -> Result<Message, Error> {
+        let (agent, reason) = (self.rules)(context);
+
+        Ok(Message::Routing {
+            agent,
+            reason,
+            usage: None,
+        })
+    }
+}
+
+/// A router asking a model - with a lightweight prompt
+pub struct ModelRouter {
+    config: SapiensConfig,
+    /// The names and the descriptions of the agents
+    agents: Vec<(String, String)>,
+}
+
+impl ModelRouter {
+    /// Create a new [`ModelRouter`] for the `agents` - their names and
+    /// descriptions
+    #[must_use]
+    pub const fn new(config: SapiensConfig, agents: Vec<(String, String)>) -> Self {
+        Self { config, agents }
+    }
+
+    /// The router of the multi-step OODA chain
+    #[must_use]
+    pub fn ooda(config: SapiensConfig) -> Self {
+        let agents = [
+            (
+                ooda::OBSERVER,
+                "Observes the task and the results of the Actions",
+            ),
+            (
+                ooda::ORIENTER,
+                "Orients the work from the observations - needed when something unexpected happened",
+            ),
+            (ooda::DECIDER, "Decides what to do next"),
+            (ooda::ACTOR, "Acts on the decision with a Tool"),
+        ];
+
+        Self::new(
+            config,
+            agents
+                .into_iter()
+                .map(|(name, description)| (name.to_string(), description.to_string()))
+                .collect(),
+        )
+    }
+
+    fn convert_context_to_chat_history(&self, context: &Context, max_token: usize) -> ChatHistory {
+        let mut chat_history = ChatHistory::new(self.config.clone(), max_token);
+
+        let agents = self
+            .agents
+            .iter()
+            .map(|(name, description)| format!("- {name}: {description}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        chat_history.set_context(vec![
+            ChatEntry {
+                role: Role::System,
+                msg: SYSTEM_PROMPT.to_string(),
+            },
+            ChatEntry {
+                role: Role::User,
+                msg: format!("{INSTRUCTIONS}\n# Agents:\n{agents}"),
+            },
+        ]);
+
+        let task = context.get_latest_task().unwrap_or_default();
+        let last_messages = context
+            .messages
+            .iter()
+            .filter(|m| !matches!(m, Message::Task { .. }))
+            .rev()
+            .take(MAX_SHOWN_MESSAGES)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        let last_messages = if last_messages.is_empty() {
+            "None yet.".to_string()
+        } else {
+            last_messages.join("\n")
+        };
+
+        chat_history.add_chitchat(ChatEntry {
+            msg: format!("# Task:\n{task}\n# Last messages:\n{last_messages}\n# Next agent?"),
+            role: Role::User,
+        });
+
+        chat_history
+    }
+}
+
+/// The agent and the reason in a response of the [`ModelRouter`]
+fn parse_route(response: &str) -> (String, String) {
+    let field = |name: &str| {
+        response
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(name))
+            .map(|value| value.trim().trim_matches('*').trim().to_string())
+            .unwrap_or_default()
+    };
+
+    (field("Agent:"), field("Reason:"))
+}
+
+#[async_trait::async_trait]
+impl chains::Agent for ModelRouter {
+    type Error = Error;
+
+    async fn act(&self, context: &Context) -> Result<Message, Error> {
+        let max_token = { self.config.model.context_size().await };
+        let mut chat_history = self.convert_context_to_chat_history(context, max_token);
+
+        // prune the history if needed
+        chat_history.purge().await?;
+
+        let input = chat_history.make_input();
+
+        debug!("Querying model for a route");
+
+        trace!("Querying model:\n{:#?}", input);
+
+        let res = self
+            .config
+            .model
+            .query(input, self.config.max_tokens)
+            .await?;
+
+        trace!("Got model response:\n{:#?}", res);
+
+        let (agent, reason) = parse_route(&res.msg);
+
+        Ok(Message::Routing {
+            agent,
+            reason,
+            usage: res.usage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chains::Agent;
+
+    async fn route(router: &RuleRouter, messages: Vec<Message>) -> String {
+        let mut context = Context::new();
+        for message in messages {
+            context.add_message(message);
+        }
+
+        match router.act(&context).await.unwrap() {
+            Message::Routing { agent, .. } => agent,
+            m => panic!("Not a route: {m:?}"),
+        }
+    }
+
+    fn result(outcome: Outcome) -> Message {
+        Message::ActionResult {
+            invocation_count: 1,
+            tool_name: Some("SandboxedPython".to_string()),
+            extracted_input: None,
+            outcome,
+            warnings: vec![],
+        }
+    }
+
+    fn observation() -> Message {
+        Message::Observation {
+            content: "Observed".to_string(),
+            usage: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn the_orientation_is_skipped_after_a_successful_action() {
+        let router = RuleRouter::ooda();
+
+        let task = || Message::Task {
+            content: "Sort [2, 1]".to_string(),
+        };
+
+        assert_eq!(route(&router, vec![task()]).await, ooda::OBSERVER);
+        assert_eq!(
+            route(&router, vec![task(), observation()]).await,
+            ooda::ORIENTER
+        );
+
+        let success = result(Outcome::Success {
+            result: "[1, 2]".to_string(),
+        });
+        assert_eq!(
+            route(&router, vec![task(), success.clone()]).await,
+            ooda::OBSERVER
+        );
+        assert_eq!(
+            route(&router, vec![task(), success, observation()]).await,
+            ooda::DECIDER
+        );
+
+        let failure = result(Outcome::ToolUseError {
+            e: crate::tools::ToolUseError::InvocationFailed("Nope".to_string()),
+        });
+        assert_eq!(
+            route(&router, vec![task(), failure, observation()]).await,
+            ooda::ORIENTER
+        );
+    }
+
+    #[test]
+    fn routes_are_parsed() {
+        assert_eq!(
+            parse_route("Agent: **Decider**\nReason: The Action succeeded."),
+            ("Decider".to_string(), "The Action succeeded.".to_string())
+        );
+        assert_eq!(parse_route("Decider"), (String::new(), String::new()));
+    }
+}
@@ -0,0 +1,395 @@
+use tracing::{debug, trace, warn};
+
+use crate::chains::agents::{attempt_chitchat, format_critique, format_outcome, Error};
+use crate::chains::{Context, Message};
+use crate::context::{ChatEntry, ChatHistory};
+use crate::models::{ModelResponse, Role};
+use crate::tools::toolbox::Toolbox;
+use crate::{prompt, SapiensConfig};
+
+/// The best score of a thought - see [`Evaluator`]
+pub const MAX_SCORE: u8 = 10;
+
+const PREFIX: &str = r"You are Sapiens, a large language model assisting the WORLD. Use available tools to answer the question as best as you can.
+You will reason one Thought at a time. Several Thoughts are explored and only the most promising ones are continued.
+
+- Give an Action only when your Thoughts are sufficient to act. Its Observation will be provided to you.
+- Never produce the Observation yourself.
+- Only use YAML for the Action.
+- No task is complete until the Conclude Tool is used to provide the answer.
+- You cannot use jinja2 templating in your response. Be concise.
+";
+
+const TOOL_PREFIX: &str = r"
+# The following are the ONLY Tools you can use for your Actions:
+";
+
+const RESPONSE_FORMAT: &str = r"
+# Format of your response
+
+You must use the following format for your response. Comments are in bold and should be removed from your response.
+====================
+Thought: **One step of reasoning about the last Observation and what to do next to answer the question.**
+Action:
+```yaml
+tool_name: <ToolName>
+parameters:
+    <...>
+```
+====================
+
+Notes:
+- The Action is optional: omit it until you are ready to act.
+- Action has the following fields: `tool_name` and `parameters` ONLY.
+- `parameters` uses the format specified for the Tool.
+- `responses_content` is the format you can expect of the Observation of the Action. Never use it in your response.
+- One Thought and at most one Action at a time.
+";
+
+const PROTO_EXCHANGE_2: &str = r"
+Thought: I need to sort the list [2, 3, 1, 4, 5] in ascending order. Python can do it.
+";
+
+const PROTO_EXCHANGE_3: &str = r"
+# Your thoughts so far:
+Thought: I need to sort the list [2, 3, 1, 4, 5] in ascending order. Python can do it.
+";
+
+const PROTO_EXCHANGE_4: &str = r#"
+Thought: The sorted() function of Python in the SandboxedPython Tool can sort the list.
+Action:
+```yaml
+tool_name: SandboxedPython
+parameters:
+  code: |
+    lst = [2, 3, 1, 4, 5]
+    sorted_list = sorted(lst)
+    print(f"The sorted list is {sorted_list}")
+```
+"#;
+
+const EVALUATOR_SYSTEM_PROMPT: &str =
+    "You are an advanced reasoning agent that evaluates the reasoning of another agent.";
+
+const EVALUATOR_INSTRUCTIONS: &str = r"You will be given a task, what the agent did so far and its proposed Thoughts.
+Evaluate how likely the proposed Thoughts are to lead to the answer: correct, relevant and making progress.
+Explain briefly, then give a score from 0 (impossible) to 10 (sure) on the last line as `Score: <score>`.
+";
+
+/// Proposes the next thought of a branch - a Thought and optionally an Action
+pub struct Proposer {
+    prompt_manager: prompt::Manager,
+    config: SapiensConfig,
+}
+
+impl Proposer {
+    /// Create a new [`Proposer`].
+    #[must_use]
+    pub fn new(config: SapiensConfig, toolbox: Toolbox) -> Self {
+        let system_prompt =
+            "You are an agent named Sapiens interacting with the WORLD. Listen to the WORLD!"
+                .to_string();
+
+        let prompt = "Next Thought?".to_string();
+
+        let prompt_manager = prompt::Manager::new(
+            toolbox,
+            system_prompt,
+            prompt,
+            PREFIX.to_string(),
+            TOOL_PREFIX.to_string(),
+            RESPONSE_FORMAT.to_string(),
+        );
+        Self {
+            prompt_manager,
+            config,
+        }
+    }
+
+    async fn convert_context_to_chat_history(
+        &self,
+        context: &Context,
+        notes: &str,
+    ) -> Result<ChatHistory, Error> {
+        // Create a new chat history
+        let max_token = { self.config.model.context_size().await };
+        let mut chat_history = ChatHistory::new(self.config.clone(), max_token);
+
+        let warmup_task = self
+            .prompt_manager
+            .build_task_prompt("Sort in ascending order: [2, 3, 1, 4, 5]");
+        let warmup_task_with_thoughts = self.prompt_manager.build_task_prompt_with_notes(
+            "Sort in ascending order: [2, 3, 1, 4, 5]",
+            PROTO_EXCHANGE_3.trim_start(),
+        );
+
+        let examples = vec![
+            (warmup_task.to_prompt(), PROTO_EXCHANGE_2.trim().to_string()),
+            (
+                warmup_task_with_thoughts.to_prompt(),
+                PROTO_EXCHANGE_4.trim().to_string(),
+            ),
+        ];
+
+        // Add the prompts to the chat history
+        self.prompt_manager
+            .populate_chat_history(&mut chat_history, examples)
+            .await;
+
+        // Convert the context to a chat history
+        // - get the latest 'Task' from the context
+        let task = context.get_latest_task().unwrap();
+        let task = self
+            .prompt_manager
+            .build_task_prompt_with_notes(&task, notes);
+
+        // - get the committed actions and their observations
+        for m in &context.messages {
+            match m {
+                Message::Action { content, .. } => {
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: content.clone(),
+                        role: Role::Assistant,
+                    });
+                }
+                Message::ActionResult {
+                    invocation_count,
+                    tool_name,
+                    outcome,
+                    warnings,
+                    ..
+                } => {
+                    let entry =
+                        format_outcome(&task, *invocation_count, tool_name, outcome, warnings);
+
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: format!("Observation:\n{entry}"),
+                        role: Role::User,
+                    });
+                }
+                Message::Critique {
+                    action, content, ..
+                } => {
+                    // the rejected action and its critique
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: action.clone(),
+                        role: Role::Assistant,
+                    });
+                    chat_history.add_chitchat(ChatEntry {
+                        msg: format_critique(&task, content),
+                        role: Role::User,
+                    });
+                }
+                _ => {
+                    // Nothing
+                }
+            }
+        }
+
+        if chat_history.is_chitchat_empty() {
+            // Add the recurring prompts to the chat history
+            chat_history.add_chitchat(ChatEntry {
+                msg: task.to_prompt(),
+                role: Role::User,
+            });
+        }
+
+        // prune the history if needed
+        chat_history.purge().await?;
+
+        Ok(chat_history)
+    }
+
+    /// Propose the next thought after `thoughts` - different from the
+    /// `proposed` ones
+    ///
+    /// With `must_act`, the model is asked for an Action.
+    pub async fn propose(
+        &self,
+        context: &Context,
+        thoughts: &[String],
+        proposed: &[String],
+        must_act: bool,
+    ) -> Result<ModelResponse, Error> {
+        let notes = prompt::Task::thoughts_notes(thoughts, proposed, must_act);
+        let chat_history = self
+            .convert_context_to_chat_history(context, &notes)
+            .await?;
+
+        let input = chat_history.make_input();
+
+        debug!(
+            depth = thoughts.len(),
+            proposed = proposed.len(),
+            must_act,
+            "Querying model for a thought with {} entries",
+            input.chat.len()
+        );
+
+        trace!("Querying model:\n{:#?}", input);
+
+        let res = self
+            .config
+            .model
+            .query(input, self.config.max_tokens)
+            .await?;
+
+        trace!("Got model response:\n{:#?}", res);
+
+        Ok(res)
+    }
+}
+
+/// Scores the thoughts of a branch from 0 to [`MAX_SCORE`]
+pub struct Evaluator {
+    config: SapiensConfig,
+}
+
+impl Evaluator {
+    /// Create a new [`Evaluator`].
+    #[must_use]
+    pub const fn new(config: SapiensConfig) -> Self {
+        Self { config }
+    }
+
+    async fn convert_branch_to_chat_history(
+        &self,
+        context: &Context,
+        thoughts: &[String],
+    ) -> Result<ChatHistory, Error> {
+        let max_token = { self.config.model.context_size().await };
+        let mut chat_history = ChatHistory::new(self.config.clone(), max_token);
+
+        let task = context.get_latest_task().unwrap_or_default();
+
+        chat_history.set_context(vec![
+            ChatEntry {
+                role: Role::System,
+                msg: EVALUATOR_SYSTEM_PROMPT.to_string(),
+            },
+            ChatEntry {
+                role: Role::User,
+                msg: format!("{EVALUATOR_INSTRUCTIONS}\n# Task:\n{task}")
+                    .trim()
+                    .to_string(),
+            },
+        ]);
+
+        let branch = format!(
+            "# Proposed Thoughts:\n{}\n# Your evaluation:",
+            thoughts.join("\n")
+        );
+        for entry in attempt_chitchat(&context.messages, branch) {
+            chat_history.add_chitchat(entry);
+        }
+
+        // prune the history if needed
+        chat_history.purge().await?;
+
+        Ok(chat_history)
+    }
+
+    /// Score the `thoughts` of a branch
+    ///
+    /// Returns the score and the response of the model. An evaluation without
+    /// a score is scored 0.
+    pub async fn evaluate(
+        &self,
+        context: &Context,
+        thoughts: &[String],
+    ) -> Result<(u8, ModelResponse), Error> {
+        let chat_history = self
+            .convert_branch_to_chat_history(context, thoughts)
+            .await?;
+
+        let input = chat_history.make_input();
+
+        debug!(
+            depth = thoughts.len(),
+            "Querying model for an evaluation with {} entries",
+            input.chat.len()
+        );
+
+        trace!("Querying model:\n{:#?}", input);
+
+        let res = self
+            .config
+            .model
+            .query(input, self.config.max_tokens)
+            .await?;
+
+        trace!("Got model response:\n{:#?}", res);
+
+        let score = parse_score(&res.msg).unwrap_or_else(|| {
+            warn!("No score in the evaluation - scored 0");
+            0
+        });
+
+        Ok((score, res))
+    }
+}
+
+/// The score on the last `Score: <score>` line - at most [`MAX_SCORE`]
+fn parse_score(evaluation: &str) -> Option<u8> {
+    evaluation.lines().rev().find_map(|line| {
+        let (_, score) = line.split_once("Score:")?;
+        let digits = score
+            .trim()
+            .trim_start_matches('*')
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>();
+        digits.parse::<u8>().ok().map(|s| s.min(MAX_SCORE))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+    use crate::chains::Outcome;
+
+    #[test]
+    fn scores_are_parsed() {
+        assert_eq!(parse_score("Looks right.\nScore: 8"), Some(8));
+        assert_eq!(parse_score("Score: 3\nScore: **7**/10"), Some(7));
+        assert_eq!(parse_score("Score: 42"), Some(MAX_SCORE));
+        assert_eq!(parse_score("Looks right."), None);
+    }
+
+    #[tokio::test]
+    async fn it_converts_the_branch_to_chat_history() {
+        let mut context = Context::new();
+
+        context.add_message(Message::Task {
+            content: "Sort in ascending order: [2, 3, 1, 4, 5]".to_string(),
+        });
+
+        context.add_message(Message::Action {
+            content: "Thought: Python can sort.\nAction:\n```yaml\ntool_name: SandboxedPython\nparameters:\n  code: print(sorted([2, 3, 1, 4, 5]))\n```".to_string(),
+            usage: None,
+        });
+
+        context.add_message(Message::ActionResult {
+            invocation_count: 1,
+            tool_name: Some("SandboxedPython".to_string()),
+            extracted_input: None,
+            outcome: Outcome::Success {
+                result: "stdout: |\n  [1, 2, 3, 4, 5]\nstderr: ''\n".to_string(),
+            },
+            warnings: vec![],
+        });
+
+        let evaluator = Evaluator::new(SapiensConfig::default());
+
+        let chat_history = evaluator
+            .convert_branch_to_chat_history(
+                &context,
+                &["Thought: The list is sorted. I need to conclude.".to_string()],
+            )
+            .await;
+
+        assert_debug_snapshot!(chat_history);
+    }
+}
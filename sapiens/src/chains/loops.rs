@@ -0,0 +1,294 @@
+//! Detection of the loops of a task
+//!
+//! The model sometimes repeats the same failing Action until the maximum
+//! number of steps is reached. After each Action, the [`Runtime`] looks for
+//! the same - or nearly the same - Action repeated, or the same error
+//! repeated, and responds as configured - see [`LoopResponse`].
+//!
+//! [`Runtime`]: crate::chains::Runtime
+use std::str::FromStr;
+
+use clap::builder::PossibleValue;
+use serde::{Deserialize, Serialize};
+
+use crate::chains::reflexion::similarity;
+use crate::chains::{Message, Outcome};
+
+/// What to do when a loop is detected
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopResponse {
+    /// Warn the model in the result of the last Action
+    #[default]
+    Correct,
+    /// Ask the user how to proceed - and warn the model with the answer in
+    /// the result of the last Action, see
+    /// [`Toolbox::ask_user`](crate::tools::toolbox::Toolbox::ask_user)
+    AskUser,
+    /// Stop the task with [`Error::LoopDetected`](crate::chains::Error)
+    Abort,
+}
+
+impl FromStr for LoopResponse {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "correct" => Ok(Self::Correct),
+            "ask-user" => Ok(Self::AskUser),
+            "abort" => Ok(Self::Abort),
+            _ => Err(format!("Unknown loop response: {s}")),
+        }
+    }
+}
+
+#[cfg(feature = "clap")]
+impl clap::ValueEnum for LoopResponse {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Correct, Self::AskUser, Self::Abort]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        match self {
+            Self::Correct => Some(PossibleValue::new("correct")),
+            Self::AskUser => Some(PossibleValue::new("ask-user")),
+            Self::Abort => Some(PossibleValue::new("abort")),
+        }
+    }
+}
+
+/// Configuration of the detection of the loops
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoopDetectionConfig {
+    /// Number of repetitions making a loop - the response is repeated every
+    /// `max_repeats` repetitions
+    pub max_repeats: usize,
+    /// How similar the inputs of two invocations of a tool have to be to be
+    /// the same Action - from 0 to 1, see [`similarity`]
+    pub similarity: f64,
+    /// What to do when a loop is detected
+    pub response: LoopResponse,
+}
+
+impl Default for LoopDetectionConfig {
+    fn default() -> Self {
+        Self {
+            max_repeats: 3,
+            similarity: 0.9,
+            response: LoopResponse::default(),
+        }
+    }
+}
+
+/// What is repeated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoopKind {
+    /// The same Action - or nearly the same
+    RepeatedAction {
+        /// The name of the invoked tool
+        tool_name: String,
+    },
+    /// The same error - possibly with different Actions
+    RepeatedError {
+        /// The error
+        error: String,
+    },
+}
+
+/// A loop of a task
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Loop {
+    /// What is repeated
+    pub kind: LoopKind,
+    /// How many times in a row
+    pub repeats: usize,
+}
+
+impl std::fmt::Display for Loop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            LoopKind::RepeatedAction { tool_name } => write!(
+                f,
+                "the same Action with {tool_name} repeated {} times",
+                self.repeats
+            ),
+            LoopKind::RepeatedError { error } => {
+                write!(f, "the same error repeated {} times: {error}", self.repeats)
+            }
+        }
+    }
+}
+
+/// The error of an outcome - the first one of several invocations
+fn error(outcome: &Outcome) -> Option<String> {
+    match outcome {
+        Outcome::Success { .. } => None,
+        Outcome::NoValidInvocationsFound { e } | Outcome::NoInvocationsFound { e } => {
+            Some(e.to_string())
+        }
+        Outcome::ToolUseError { e } => Some(e.to_string()),
+        Outcome::Multiple { outcomes } => outcomes.iter().find_map(|o| error(&o.outcome)),
+    }
+}
+
+/// Detects the loops in the results of the Actions
+pub(crate) struct LoopDetector {
+    config: LoopDetectionConfig,
+}
+
+impl LoopDetector {
+    /// Create a new [`LoopDetector`]
+    pub(crate) const fn new(config: LoopDetectionConfig) -> Self {
+        Self { config }
+    }
+
+    /// What to do when a loop is detected
+    pub(crate) const fn response(&self) -> LoopResponse {
+        self.config.response
+    }
+
+    /// Detect a loop ending with the last result of an Action in `messages`
+    ///
+    /// A loop is only reported every `max_repeats` repetitions.
+    pub(crate) fn detect(&self, messages: &[Message]) -> Option<Loop> {
+        // the tool names, inputs and errors - the last one first
+        let mut results = messages.iter().rev().filter_map(|m| match m {
+            Message::ActionResult {
+                tool_name,
+                extracted_input,
+                outcome,
+                ..
+            } => Some((
+                tool_name.as_deref(),
+                extracted_input.as_deref(),
+                error(outcome),
+            )),
+            _ => None,
+        });
+
+        let (tool_name, input, last_error) = results.next()?;
+        let results = results.collect::<Vec<_>>();
+
+        let max_repeats = self.config.max_repeats.max(2);
+        let is_loop = |repeats: usize| repeats.is_multiple_of(max_repeats);
+
+        if let (Some(tool_name), Some(input)) = (tool_name, input) {
+            let repeats = 1 + results
+                .iter()
+                .take_while(|(t, i, _)| {
+                    *t == Some(tool_name)
+                        && i.is_some_and(|i| similarity(i, input) >= self.config.similarity)
+                })
+                .count();
+
+            if is_loop(repeats) {
+                return Some(Loop {
+                    kind: LoopKind::RepeatedAction {
+                        tool_name: tool_name.to_string(),
+                    },
+                    repeats,
+                });
+            }
+        }
+
+        let error = last_error?;
+        let repeats = 1 + results
+            .iter()
+            .take_while(|(_, _, e)| e.as_ref() == Some(&error))
+            .count();
+
+        is_loop(repeats).then_some(Loop {
+            kind: LoopKind::RepeatedError { error },
+            repeats,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ToolUseError;
+
+    fn result(tool_name: &str, input: &str, outcome: Outcome) -> Message {
+        Message::ActionResult {
+            invocation_count: 1,
+            tool_name: Some(tool_name.to_string()),
+            extracted_input: Some(input.to_string()),
+            outcome,
+            warnings: vec![],
+        }
+    }
+
+    fn failure(e: &str) -> Outcome {
+        Outcome::ToolUseError {
+            e: ToolUseError::InvocationFailed(e.to_string()),
+        }
+    }
+
+    fn success() -> Outcome {
+        Outcome::Success {
+            result: "42".to_string(),
+        }
+    }
+
+    #[test]
+    fn nearly_identical_actions_are_loops() {
+        let detector = LoopDetector::new(LoopDetectionConfig::default());
+
+        let mut messages = vec![
+            result("Search", "query: capital of France", success()),
+            result("Search", "query: Capital of France?", success()),
+        ];
+        assert_eq!(detector.detect(&messages), None);
+
+        messages.push(result("Search", "query: capital of  france", success()));
+        assert_eq!(
+            detector.detect(&messages),
+            Some(Loop {
+                kind: LoopKind::RepeatedAction {
+                    tool_name: "Search".to_string()
+                },
+                repeats: 3,
+            })
+        );
+
+        // reported again after 3 more repetitions
+        messages.push(result("Search", "query: capital of France", success()));
+        assert_eq!(detector.detect(&messages), None);
+
+        // a different input breaks the loop
+        messages.push(result("Search", "query: capital of Spain", success()));
+        assert_eq!(detector.detect(&messages), None);
+    }
+
+    #[test]
+    fn identical_errors_are_loops() {
+        let detector = LoopDetector::new(LoopDetectionConfig::default());
+
+        let messages = vec![
+            result("SandboxedPython", "code: print(x)", failure("NameError")),
+            Message::Observation {
+                content: "x is not defined".to_string(),
+                usage: None,
+            },
+            result("SandboxedPython", "code: print(y)", failure("NameError")),
+            result("Wikipedia", "query: x", failure("NameError")),
+        ];
+
+        assert_eq!(
+            detector.detect(&messages),
+            Some(Loop {
+                kind: LoopKind::RepeatedError {
+                    error: "Tool invocation failed: NameError".to_string()
+                },
+                repeats: 3,
+            })
+        );
+
+        let detector = LoopDetector::new(LoopDetectionConfig {
+            max_repeats: 4,
+            ..LoopDetectionConfig::default()
+        });
+        assert_eq!(detector.detect(&messages), None);
+    }
+}
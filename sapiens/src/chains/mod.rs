@@ -5,11 +5,14 @@
 //! - [ ] 2205.11916 - Zeroshot reasoners - "Let's think step by step" - 2022
 //! - [ ] 2207.05608 - Inner monologue - Different types of feedbacks - 2022
 //! - [ ] 2302.00083 - In context RALM - Jan 2023
-//! - [ ] 2302.01560 - DEPS - Describe, explain, plan, select stages. Feb 2023
-//! - [ ] 2210.03629 - `ReAct` - Reasoning + Action - Mar 2023
-//! - [ ] 2303.11366 - Reflexion - heuristic + self-reflection - Mar 2023
+//! - [x] 2302.01560 - DEPS - Describe, explain, plan, select stages. Feb 2023 -
+//!   See [`PlanAndExecuteChain`]
+//! - [x] 2210.03629 - `ReAct` - Reasoning + Action - Mar 2023 - See
+//!   [`ReActChain`]
+//! - [x] 2303.11366 - Reflexion - heuristic + self-reflection - Mar 2023 - See
+//!   [`ReflexionChain`]
 //! - [ ] 2303.17071 - DERA - Distinct roles+responsibilities - Mar 2023
-//! - [ ] 2305.10601 - Tree of Thoughts - May 2023
+//! - [x] 2305.10601 - Tree of Thoughts - May 2023 - See [`TreeOfThoughtsChain`]
 // TODO(ssoudan) + LLM self-consistency
 
 // FUTURE(ssoudan) more chains
@@ -19,20 +22,45 @@ pub mod agents;
 /// Schedulers are responsible for deciding which agent to run next.
 pub mod schedulers;
 
+/// Self-reflections on the failed attempts at a task
+pub mod reflexion;
+
+/// Tree of Thoughts search
+pub mod tot;
+
+/// Plans of sub-goals
+pub mod plan;
+
+/// Chains by name
+pub mod registry;
+
+/// Loops of the tasks
+pub mod loops;
+
+pub use plan::PlanAndExecuteChain;
+pub use reflexion::ReflexionChain;
+pub use tot::TreeOfThoughtsChain;
+
 #[cfg(test)]
 mod tests;
 
 use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 use crate::chains::agents::ooda::{multistep, one_step};
-use crate::chains::schedulers::{MultiAgentScheduler, SingleAgentScheduler};
+use crate::chains::agents::{react, router};
+use crate::chains::loops::{Loop, LoopDetectionConfig, LoopDetector, LoopResponse};
+use crate::chains::plan::Plan;
+use crate::chains::schedulers::{
+    CriticStage, DynamicScheduler, MultiAgentScheduler, Scheduling, SingleAgentScheduler,
+};
 use crate::context::ContextDump;
 use crate::models::Usage;
-use crate::tools::toolbox::{invoke_tool, InvokeResult, Toolbox};
+use crate::tools::toolbox::{invoke_tool, InvokeResult, Toolbox, PENDING_ANSWER};
 use crate::tools::{TerminationMessage, ToolUseError};
-use crate::{invocation, SapiensConfig, WeakRuntimeObserver};
+use crate::{invocation, LoopNotification, SapiensConfig, WeakRuntimeObserver};
 
 /// Outcome of an invocation
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -57,6 +85,25 @@ pub enum Outcome {
         /// The tool use error
         e: ToolUseError,
     },
+    /// Several invocations were executed
+    Multiple {
+        /// The outcomes of the executed invocations - in order
+        outcomes: Vec<InvocationOutcome>,
+    },
+}
+
+/// Outcome of one of several invocations - See [`Outcome::Multiple`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InvocationOutcome {
+    /// The name of the tool that was invoked
+    pub tool_name: String,
+    /// The extracted input for the tool
+    pub extracted_input: String,
+    /// The outcome of the invocation
+    pub outcome: Outcome,
+    /// The violations of the invocation policy that are only warnings
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<invocation::Error>,
 }
 
 /// A message that can be produced by an agent for another agent
@@ -105,6 +152,45 @@ pub enum Message {
         extracted_input: Option<String>,
         /// The outcome of the invocation
         outcome: Outcome,
+        /// The warnings fed back to the model with the outcome
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<Warning>,
+    },
+    /// A self-reflection on a failed attempt at the task - See
+    /// [`ReflexionChain`]
+    Reflection {
+        /// The reflection
+        content: String,
+        /// Token usage
+        usage: Option<Usage>,
+    },
+    /// A review of a proposed Action - See [`schedulers::CriticStage`]
+    Critique {
+        /// The reviewed Action
+        action: String,
+        /// Whether the Action is approved
+        approved: bool,
+        /// The review
+        content: String,
+        /// Token usage
+        usage: Option<Usage>,
+    },
+    /// The agent picked to work next and why - See
+    /// [`schedulers::DynamicScheduler`]
+    Routing {
+        /// The name of the agent
+        agent: String,
+        /// Why it was picked
+        reason: String,
+        /// Token usage
+        usage: Option<Usage>,
+    },
+    /// The updated plan of the task - See [`PlanAndExecuteChain`]
+    Plan {
+        /// The plan
+        plan: Plan,
+        /// Token usage
+        usage: Option<Usage>,
     },
 }
 
@@ -116,11 +202,24 @@ impl Display for Message {
             Self::Orientation { content ,..} => write!(f, "Orientation: {content}"),
             Self::Decision { content,.. } => write!(f, "Decision: {content}"),
             Self::Action { content ,..} => write!(f, "Action: {content}"),
+            Self::Reflection { content, .. } => write!(f, "Reflection: {content}"),
+            Self::Critique {
+                approved, content, ..
+            } => {
+                if *approved {
+                    write!(f, "Critique (approved): {content}")
+                } else {
+                    write!(f, "Critique (to revise): {content}")
+                }
+            }
+            Self::Routing { agent, reason, .. } => write!(f, "Routing: {agent} - {reason}"),
+            Self::Plan { plan, .. } => write!(f, "Plan:\n{plan}"),
             Self::ActionResult {
                 invocation_count,
                 tool_name,
                 extracted_input,
                 outcome,
+                ..
             } => write!(
                 f,
                 "ActionResult: {invocation_count} invocations found, tool_name: {tool_name:?}, extracted_input: {extracted_input:?}, outcome: {outcome:?}",                                
@@ -137,6 +236,7 @@ impl From<InvokeResult> for Message {
                 tool_name: None,
                 extracted_input: None,
                 outcome: Outcome::NoInvocationsFound { e },
+                warnings: vec![],
             },
             InvokeResult::NoValidInvocationsFound {
                 e,
@@ -146,33 +246,85 @@ impl From<InvokeResult> for Message {
                 tool_name: None,
                 extracted_input: None,
                 outcome: Outcome::NoValidInvocationsFound { e },
+                warnings: vec![],
             },
             InvokeResult::Success {
                 invocation_count,
                 tool_name,
                 extracted_input,
                 result,
+                warnings,
+                ..
             } => Self::ActionResult {
                 invocation_count,
                 tool_name: Some(tool_name),
                 extracted_input: Some(extracted_input),
                 outcome: Outcome::Success { result },
+                warnings: warnings.into_iter().map(Into::into).collect(),
             },
             InvokeResult::Error {
                 invocation_count,
                 tool_name,
                 e,
+                warnings,
                 ..
             } => Self::ActionResult {
                 invocation_count,
                 tool_name: Some(tool_name),
                 extracted_input: None,
                 outcome: Outcome::ToolUseError { e },
+                warnings: warnings.into_iter().map(Into::into).collect(),
+            },
+            InvokeResult::Multiple {
+                invocation_count,
+                results,
+            } => Self::ActionResult {
+                invocation_count,
+                tool_name: None,
+                extracted_input: None,
+                outcome: Outcome::Multiple {
+                    outcomes: results.into_iter().filter_map(Into::into).collect(),
+                },
+                warnings: vec![],
             },
         }
     }
 }
 
+impl From<InvokeResult> for Option<InvocationOutcome> {
+    fn from(result: InvokeResult) -> Self {
+        match result {
+            InvokeResult::Success {
+                tool_name,
+                extracted_input,
+                result,
+                warnings,
+                ..
+            } => Some(InvocationOutcome {
+                tool_name,
+                extracted_input,
+                outcome: Outcome::Success { result },
+                warnings,
+            }),
+            InvokeResult::Error {
+                tool_name,
+                extracted_input,
+                e,
+                warnings,
+                ..
+            } => Some(InvocationOutcome {
+                tool_name,
+                extracted_input,
+                outcome: Outcome::ToolUseError { e },
+                warnings,
+            }),
+            InvokeResult::NoInvocationsFound { .. }
+            | InvokeResult::NoValidInvocationsFound { .. }
+            | InvokeResult::Multiple { .. } => None,
+        }
+    }
+}
+
 /// The history of a [`Message`] produced by the [`Chain`].
 #[derive(Clone, Default)]
 pub struct Context {
@@ -194,6 +346,28 @@ impl Context {
         }
     }
 
+    /// Returns the reflections on the previous attempts at the task - see
+    /// [`ReflexionChain`]
+    #[must_use]
+    pub fn get_reflections(&self) -> Vec<String> {
+        self.messages
+            .iter()
+            .filter_map(|m| match m {
+                Message::Reflection { content, .. } => Some(content.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the latest plan - see [`PlanAndExecuteChain`]
+    #[must_use]
+    pub fn get_plan(&self) -> Option<Plan> {
+        self.messages.iter().rev().find_map(|m| match m {
+            Message::Plan { plan, .. } => Some(plan.clone()),
+            _ => None,
+        })
+    }
+
     /// Returns the latest task
     #[must_use]
     pub fn get_latest_task(&self) -> Option<String> {
@@ -209,18 +383,46 @@ impl Context {
     }
 }
 
+/// A warning fed back to the model with the result of an Action - See
+/// [`Message::ActionResult`]
+#[derive(thiserror::Error, Clone, Debug, Serialize, Deserialize)]
+pub enum Warning {
+    /// A violation of the invocation policy that is only a warning
+    #[error(transparent)]
+    Invocation(#[from] invocation::Error),
+    /// The Actions are going in circles - see [`loops`]
+    #[error("You are going in circles: {detected}. Try a different approach.{}", format_guidance(guidance.as_deref()))]
+    Repeated {
+        /// What is repeated
+        detected: String,
+        /// How to proceed - according to the user, see
+        /// [`LoopResponse::AskUser`]
+        guidance: Option<String>,
+    },
+}
+
+fn format_guidance(guidance: Option<&str>) -> String {
+    guidance.map_or_else(String::new, |g| format!(" The user says: {g}"))
+}
+
 /// An error that can occur during the creation or execution of a [`Chain`]
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     /// No terminal tool in the toolbox
     #[error("No terminal tool")]
     NoTerminalTool,
+    /// No agent to schedule
+    #[error("No agent")]
+    NoAgent,
     /// Max steps reached
     #[error("Max steps reached")]
     MaxStepsReached,
     /// Agent failed
     #[error("Agent failed: {0}")]
     AgentFailed(#[from] agents::Error),
+    /// The task is going in circles - see [`LoopResponse::Abort`]
+    #[error("Loop detected: {0}")]
+    LoopDetected(Loop),
 }
 
 /// An agent for sapiens
@@ -239,6 +441,13 @@ pub trait Scheduler: Send + Sync {
     /// Pick the next [`Agent`] to be called, call it and return the produced
     /// [`Message`]
     async fn schedule(&mut self, context: &Context) -> Result<Message, Error>;
+
+    /// Take the [`Message`]s produced while scheduling the last one - added
+    /// to the [`Context`] before it, e.g. the critiques of
+    /// [`schedulers::CriticStage`]
+    fn drain_messages(&mut self) -> Vec<Message> {
+        vec![]
+    }
 }
 
 /// A runtime for sapiens
@@ -247,6 +456,7 @@ pub struct Runtime {
     toolbox: Toolbox,
     scheduler: Box<dyn Scheduler>,
     observer: WeakRuntimeObserver,
+    loop_detector: Option<LoopDetector>,
 }
 
 /// The state of the runtime after it terminates
@@ -272,9 +482,17 @@ impl Runtime {
             toolbox,
             scheduler,
             observer,
+            loop_detector: None,
         })
     }
 
+    /// Detect the loops after each Action - none if not set
+    #[must_use]
+    pub fn with_loop_detection(mut self, config: Option<LoopDetectionConfig>) -> Self {
+        self.loop_detector = config.map(LoopDetector::new);
+        self
+    }
+
     /// Run the runtime until it terminates.
     pub async fn run(&mut self) -> Result<TerminalState, Error> {
         loop {
@@ -289,6 +507,10 @@ impl Runtime {
     pub async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error> {
         let message = self.scheduler.schedule(&self.context).await?;
 
+        // already reported to the observer
+        self.context
+            .messages
+            .extend(self.scheduler.drain_messages());
         self.context.messages.push(message.clone());
 
         if let Some(observer) = self.observer.upgrade() {
@@ -312,11 +534,115 @@ impl Runtime {
             }
 
             self.context.messages.push(res.into());
+
+            self.check_loops().await?;
         }
 
         // are we done?
         Ok(self.toolbox.termination_messages().await)
     }
+
+    /// Look for a loop ending with the last Action - and respond to it
+    async fn check_loops(&mut self) -> Result<(), Error> {
+        let Some(detector) = &self.loop_detector else {
+            return Ok(());
+        };
+
+        let Some(detected) = detector.detect(&self.context.messages) else {
+            return Ok(());
+        };
+
+        let response = detector.response();
+        warn!(%detected, ?response, "Loop detected");
+
+        if let Some(observer) = self.observer.upgrade() {
+            observer
+                .lock()
+                .await
+                .on_loop(LoopNotification {
+                    detected: detected.clone(),
+                    response,
+                })
+                .await;
+        }
+
+        let guidance = match response {
+            LoopResponse::Correct => None,
+            LoopResponse::AskUser => {
+                self.toolbox
+                    .ask_user(format!(
+                        "The task is going in circles: {detected}. How should it proceed?"
+                    ))
+                    .await;
+
+                // replaced by the answer when the task resumes
+                Some(PENDING_ANSWER.to_string())
+            }
+            LoopResponse::Abort => return Err(Error::LoopDetected(detected)),
+        };
+
+        // the model is warned with the result of the last Action
+        if let Some(Message::ActionResult { warnings, .. }) = self.context.messages.last_mut() {
+            warnings.push(Warning::Repeated {
+                detected: detected.to_string(),
+                guidance,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Answer the question asked to the user during the last step - see
+    /// [`Toolbox::ask_user`]
+    ///
+    /// The answer replaces [`PENDING_ANSWER`] in the result of the last
+    /// Action - and in its warnings, see [`LoopResponse::AskUser`].
+    pub fn answer(&mut self, answer: &str) {
+        let answer = serde_yaml::to_string(answer).unwrap_or_else(|_| answer.to_string());
+        let answer = answer.trim_end();
+
+        let last_result = self
+            .context
+            .messages
+            .iter_mut()
+            .rev()
+            .find_map(|m| match m {
+                Message::ActionResult {
+                    outcome, warnings, ..
+                } => Some((outcome, warnings)),
+                _ => None,
+            });
+
+        if let Some((outcome, warnings)) = last_result {
+            fill_answer(outcome, answer);
+
+            for warning in warnings {
+                if let Warning::Repeated {
+                    guidance: Some(guidance),
+                    ..
+                } = warning
+                {
+                    *guidance = guidance.replace(PENDING_ANSWER, answer);
+                }
+            }
+        } else {
+            warn!("No Action result to answer");
+        }
+    }
+}
+
+/// Replace [`PENDING_ANSWER`] with the `answer` in the results of an
+/// [`Outcome`]
+fn fill_answer(outcome: &mut Outcome, answer: &str) {
+    match outcome {
+        Outcome::Success { result } => *result = result.replace(PENDING_ANSWER, answer),
+        Outcome::Multiple { outcomes } => {
+            for outcome in outcomes {
+                fill_answer(&mut outcome.outcome, answer);
+            }
+        }
+        _ => {}
+    }
 }
 
 /// a chain of steps to perform a task.
@@ -327,6 +653,11 @@ pub trait Chain: Send + Sync {
 
     /// Execute a single step of the chain
     async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error>;
+
+    /// Answer the question asked to the user during the last step
+    ///
+    /// Nothing by default - for the chains without tools asking the user.
+    fn answer(&mut self, _answer: &str) {}
 }
 
 /// A single-step OODA chain
@@ -344,9 +675,12 @@ impl SingleStepOODAChain {
         let agent = one_step::Agent::new(config.clone(), toolbox.clone(), observer.clone());
 
         let scheduler =
-            SingleAgentScheduler::new(config.max_steps, Box::new(agent), observer.clone());
+            SingleAgentScheduler::new(config.max_steps, Box::new(agent), observer.clone())
+                .with_critic(CriticStage::from_config(&config, toolbox.clone()));
         Ok(Self {
-            runtime: Runtime::new(toolbox, Box::new(scheduler), observer).await?,
+            runtime: Runtime::new(toolbox, Box::new(scheduler), observer)
+                .await?
+                .with_loop_detection(config.loop_detection),
         })
     }
 
@@ -368,6 +702,10 @@ impl Chain for SingleStepOODAChain {
         self.runtime.context.dump()
     }
 
+    fn answer(&mut self, answer: &str) {
+        self.runtime.answer(answer);
+    }
+
     async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error> {
         self.runtime.step().await
     }
@@ -387,20 +725,52 @@ impl MultiStepOODAChain {
         observer: WeakRuntimeObserver,
     ) -> Result<Self, Error> {
         let agents = vec![
-            multistep::Agent::new_observer(config.clone(), toolbox.clone(), observer.clone()),
-            multistep::Agent::new_orienter(config.clone(), toolbox.clone(), observer.clone()),
-            multistep::Agent::new_decider(config.clone(), toolbox.clone(), observer.clone()),
-            multistep::Agent::new_actor(config.clone(), toolbox.clone(), observer.clone()),
+            (
+                router::ooda::OBSERVER,
+                multistep::Agent::new_observer(config.clone(), toolbox.clone(), observer.clone()),
+            ),
+            (
+                router::ooda::ORIENTER,
+                multistep::Agent::new_orienter(config.clone(), toolbox.clone(), observer.clone()),
+            ),
+            (
+                router::ooda::DECIDER,
+                multistep::Agent::new_decider(config.clone(), toolbox.clone(), observer.clone()),
+            ),
+            (
+                router::ooda::ACTOR,
+                multistep::Agent::new_actor(config.clone(), toolbox.clone(), observer.clone()),
+            ),
         ];
 
         let agents = agents
             .into_iter()
-            .map(|a| Box::new(a) as Box<dyn Agent<Error = agents::Error>>)
-            .collect();
+            .map(|(name, a)| (name, Box::new(a) as Box<dyn Agent<Error = agents::Error>>));
+
+        let critic = CriticStage::from_config(&config, toolbox.clone());
+        let router: Box<dyn Agent<Error = agents::Error>> = match config.scheduling {
+            Scheduling::RoundRobin => {
+                let agents = agents.map(|(_, a)| a).collect();
+                let scheduler =
+                    MultiAgentScheduler::new(config.max_steps, agents, observer.clone())
+                        .with_critic(critic);
+                return Ok(Self {
+                    runtime: Runtime::new(toolbox, Box::new(scheduler), observer)
+                        .await?
+                        .with_loop_detection(config.loop_detection),
+                });
+            }
+            Scheduling::Rules => Box::new(router::RuleRouter::ooda()),
+            Scheduling::Model => Box::new(router::ModelRouter::ooda(config.clone())),
+        };
 
-        let scheduler = MultiAgentScheduler::new(config.max_steps, agents, observer.clone());
+        let agents = agents.map(|(name, a)| (name.to_string(), a)).collect();
+        let scheduler = DynamicScheduler::new(config.max_steps, agents, router, observer.clone())
+            .with_critic(critic);
         Ok(Self {
-            runtime: Runtime::new(toolbox, Box::new(scheduler), observer).await?,
+            runtime: Runtime::new(toolbox, Box::new(scheduler), observer)
+                .await?
+                .with_loop_detection(config.loop_detection),
         })
     }
 
@@ -422,6 +792,63 @@ impl Chain for MultiStepOODAChain {
         self.runtime.context.dump()
     }
 
+    fn answer(&mut self, answer: &str) {
+        self.runtime.answer(answer);
+    }
+
+    async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error> {
+        self.runtime.step().await
+    }
+}
+
+/// `ReAct` chain - a single agent alternating a Thought and an Action, the
+/// Observation being the result of the Action
+pub struct ReActChain {
+    /// The runtime of the chain
+    runtime: Runtime,
+}
+
+impl ReActChain {
+    /// Create a new [`ReActChain`]
+    pub async fn new(
+        config: SapiensConfig,
+        toolbox: Toolbox,
+        observer: WeakRuntimeObserver,
+    ) -> Result<Self, Error> {
+        let agent = react::Agent::new(config.clone(), toolbox.clone(), observer.clone());
+
+        let scheduler =
+            SingleAgentScheduler::new(config.max_steps, Box::new(agent), observer.clone())
+                .with_critic(CriticStage::from_config(&config, toolbox.clone()));
+        Ok(Self {
+            runtime: Runtime::new(toolbox, Box::new(scheduler), observer)
+                .await?
+                .with_loop_detection(config.loop_detection),
+        })
+    }
+
+    /// Add a new task to the `ReAct` chain
+    #[must_use]
+    pub fn with_task(mut self, task: String) -> Self {
+        self.runtime
+            .context
+            .messages
+            .push(Message::Task { content: task });
+
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Chain for ReActChain {
+    fn dump(&self) -> ContextDump {
+        self.runtime.context.dump()
+    }
+
+    fn answer(&mut self, answer: &str) {
+        self.runtime.answer(answer);
+    }
+
     async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error> {
         self.runtime.step().await
     }
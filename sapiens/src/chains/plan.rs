@@ -0,0 +1,146 @@
+//! Plan and execute - after DEPS 2302.01560
+//!
+//! A planner keeps a structured [`Plan`] - sub-goals with their statuses - in
+//! the [`Context`](crate::chains::Context) and updates it after each Action.
+//! An executor works through the sub-goals. The latest plan is repeated in
+//! each prompt of the executor, so it survives the pruning of the history.
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chains::agents::{plan, react, Error as AgentError};
+use crate::chains::schedulers::{CriticStage, MultiAgentScheduler};
+use crate::chains::{Agent, Chain, Error, Message, Runtime};
+use crate::context::ContextDump;
+use crate::tools::toolbox::Toolbox;
+use crate::tools::TerminationMessage;
+use crate::{SapiensConfig, WeakRuntimeObserver};
+
+/// The status of a [`Goal`]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GoalStatus {
+    /// Not started
+    #[default]
+    Pending,
+    /// Being worked on
+    InProgress,
+    /// Achieved
+    Done,
+    /// Cannot be achieved
+    Failed,
+}
+
+impl Display for GoalStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending"),
+            Self::InProgress => write!(f, "in_progress"),
+            Self::Done => write!(f, "done"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// A sub-goal of a [`Plan`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Goal {
+    /// What is to be achieved
+    pub description: String,
+    /// Where it stands
+    #[serde(default)]
+    pub status: GoalStatus,
+}
+
+/// A plan - the sub-goals of a task, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    /// The sub-goals
+    pub goals: Vec<Goal>,
+}
+
+impl Plan {
+    /// The first sub-goal neither done nor failed
+    #[must_use]
+    pub fn current_goal(&self) -> Option<&Goal> {
+        self.goals
+            .iter()
+            .find(|g| matches!(g.status, GoalStatus::Pending | GoalStatus::InProgress))
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, goal) in self.goals.iter().enumerate() {
+            writeln!(
+                f,
+                "{}. [{}] {}",
+                i + 1,
+                goal.status,
+                goal.description.trim()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Plan-and-execute chain - a planner and an executor taking turns
+pub struct PlanAndExecuteChain {
+    /// The runtime of the chain
+    runtime: Runtime,
+}
+
+impl PlanAndExecuteChain {
+    /// Create a new [`PlanAndExecuteChain`]
+    pub async fn new(
+        config: SapiensConfig,
+        toolbox: Toolbox,
+        observer: WeakRuntimeObserver,
+    ) -> Result<Self, Error> {
+        let agents = vec![
+            Box::new(plan::Planner::new(
+                config.clone(),
+                toolbox.clone(),
+                observer.clone(),
+            )) as Box<dyn Agent<Error = AgentError>>,
+            Box::new(react::Agent::new(
+                config.clone(),
+                toolbox.clone(),
+                observer.clone(),
+            )),
+        ];
+
+        let scheduler = MultiAgentScheduler::new(config.max_steps, agents, observer.clone())
+            .with_critic(CriticStage::from_config(&config, toolbox.clone()));
+        Ok(Self {
+            runtime: Runtime::new(toolbox, Box::new(scheduler), observer)
+                .await?
+                .with_loop_detection(config.loop_detection),
+        })
+    }
+
+    /// Add a new task to the plan-and-execute chain
+    #[must_use]
+    pub fn with_task(mut self, task: String) -> Self {
+        self.runtime
+            .context
+            .messages
+            .push(Message::Task { content: task });
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Chain for PlanAndExecuteChain {
+    fn dump(&self) -> ContextDump {
+        self.runtime.context.dump()
+    }
+
+    fn answer(&mut self, answer: &str) {
+        self.runtime.answer(answer);
+    }
+
+    async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error> {
+        self.runtime.step().await
+    }
+}
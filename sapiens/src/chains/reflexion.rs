@@ -0,0 +1,373 @@
+//! Reflexion - 2303.11366
+//!
+//! When an attempt at a task fails, the model writes a short self-reflection.
+//! The reflections are kept in a [`ReflectionStore`] and recalled in the
+//! prompt of the next attempts at the same or a similar task.
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::chains::agents::reflexion::Reflector;
+use crate::chains::agents::{react, Error as AgentError};
+use crate::chains::schedulers::{CriticStage, SingleAgentScheduler};
+use crate::chains::{Chain, Error, Message, Runtime};
+use crate::context::ContextDump;
+use crate::tools::toolbox::Toolbox;
+use crate::tools::TerminationMessage;
+use crate::{SapiensConfig, WeakRuntimeObserver};
+
+/// How similar the words of two tasks must be for the reflections on one to
+/// be recalled for the other - see [`similarity`]
+pub const SIMILARITY_THRESHOLD: f64 = 0.5;
+
+/// How many reflections are recalled at most
+pub const MAX_RECALLED_REFLECTIONS: usize = 3;
+
+/// A self-reflection on a failed attempt at a task
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reflection {
+    /// The task
+    pub task: String,
+    /// Why the attempt failed
+    pub failure: String,
+    /// The reflection
+    pub content: String,
+}
+
+/// Where the reflections are kept
+#[async_trait::async_trait]
+pub trait ReflectionStore: Send + Sync {
+    /// Keep a reflection
+    async fn add(&self, reflection: Reflection);
+
+    /// All the reflections - the oldest first
+    async fn all(&self) -> Vec<Reflection>;
+}
+
+/// A [`ReflectionStore`] in memory - lost when the process stops
+#[derive(Default)]
+pub struct MemoryReflections {
+    reflections: RwLock<Vec<Reflection>>,
+}
+
+#[async_trait::async_trait]
+impl ReflectionStore for MemoryReflections {
+    async fn add(&self, reflection: Reflection) {
+        self.reflections.write().await.push(reflection);
+    }
+
+    async fn all(&self) -> Vec<Reflection> {
+        self.reflections.read().await.clone()
+    }
+}
+
+/// A [`ReflectionStore`] in a YAML file - shared across the runs
+pub struct FileReflections {
+    path: PathBuf,
+    /// Serializes the updates of the file
+    lock: RwLock<()>,
+}
+
+impl FileReflections {
+    /// Keep the reflections in the file at `path` - created when the first
+    /// reflection is added
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: RwLock::new(()),
+        }
+    }
+
+    async fn read(&self) -> Vec<Reflection> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => serde_yaml::from_str(&content).unwrap_or_else(|e| {
+                warn!(
+                    "Ignoring the invalid reflections in {}: {}",
+                    self.path.display(),
+                    e
+                );
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ReflectionStore for FileReflections {
+    async fn add(&self, reflection: Reflection) {
+        let _guard = self.lock.write().await;
+
+        let mut reflections = self.read().await;
+        reflections.push(reflection);
+
+        let written = match serde_yaml::to_string(&reflections) {
+            Ok(content) => tokio::fs::write(&self.path, content)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        if let Err(e) = written {
+            warn!(
+                "Failed to save a reflection in {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    async fn all(&self) -> Vec<Reflection> {
+        let _guard = self.lock.read().await;
+        self.read().await
+    }
+}
+
+/// The lowercase words of a text
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// How similar two tasks are - the Jaccard index of their words, 1 for the
+/// same words
+#[must_use]
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a = words(a);
+    let b = words(b);
+
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 1.;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let index = a.intersection(&b).count() as f64 / union as f64;
+    index
+}
+
+/// The reflections on the same or a similar task - the most similar and the
+/// most recent first
+pub async fn recall(store: &dyn ReflectionStore, task: &str) -> Vec<Reflection> {
+    let mut reflections = store
+        .all()
+        .await
+        .into_iter()
+        .rev()
+        .map(|r| (similarity(&r.task, task), r))
+        .filter(|(s, _)| *s >= SIMILARITY_THRESHOLD)
+        .collect::<Vec<_>>();
+
+    // stable: the most recent first among the equally similar ones
+    reflections.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    reflections
+        .into_iter()
+        .take(MAX_RECALLED_REFLECTIONS)
+        .map(|(_, r)| r)
+        .collect()
+}
+
+/// Reflect on a failed attempt at a task and keep the reflection in the store
+/// of the `config`
+///
+/// To be called when an attempt fails otherwise than by reaching the maximum
+/// number of steps - e.g. when the conclusion is wrong. The `attempt` is the
+/// context of the task - see [`crate::Stop::context`].
+pub async fn reflect(
+    config: &SapiensConfig,
+    attempt: &ContextDump,
+    failure: &str,
+    observer: WeakRuntimeObserver,
+) -> Result<Reflection, Error> {
+    let task = attempt
+        .messages
+        .iter()
+        .rev()
+        .find_map(|m| match m {
+            Message::Task { content } => Some(content.clone()),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let reflector = Reflector::new(config.clone(), observer.clone());
+    let message = reflector.reflect(&task, &attempt.messages, failure).await?;
+
+    if let Some(observer) = observer.upgrade() {
+        observer
+            .lock()
+            .await
+            .on_message(message.clone().into())
+            .await;
+    }
+
+    let Message::Reflection { content, .. } = message else {
+        unreachable!("a reflector produces reflections");
+    };
+
+    let reflection = Reflection {
+        task,
+        failure: failure.to_string(),
+        content,
+    };
+
+    info!(task = reflection.task, "New reflection");
+    config.reflections.add(reflection.clone()).await;
+
+    Ok(reflection)
+}
+
+/// Reflexion chain - a `ReAct` agent recalling the reflections on the previous
+/// attempts at the task
+///
+/// A reflection is written when the maximum number of steps is reached.
+pub struct ReflexionChain {
+    /// The runtime of the chain
+    runtime: Runtime,
+    /// The configuration - with the reflection store
+    config: SapiensConfig,
+    /// The observer
+    observer: WeakRuntimeObserver,
+}
+
+impl ReflexionChain {
+    /// Create a new [`ReflexionChain`]
+    pub async fn new(
+        config: SapiensConfig,
+        toolbox: Toolbox,
+        observer: WeakRuntimeObserver,
+    ) -> Result<Self, Error> {
+        let agent = react::Agent::new(config.clone(), toolbox.clone(), observer.clone());
+
+        let scheduler = SingleAgentScheduler::<AgentError>::new(
+            config.max_steps,
+            Box::new(agent),
+            observer.clone(),
+        )
+        .with_critic(CriticStage::from_config(&config, toolbox.clone()));
+        Ok(Self {
+            runtime: Runtime::new(toolbox, Box::new(scheduler), observer.clone())
+                .await?
+                .with_loop_detection(config.loop_detection),
+            config,
+            observer,
+        })
+    }
+
+    /// Add a new task to the Reflexion chain - with the reflections on the
+    /// previous attempts at it
+    pub async fn with_task(mut self, task: String) -> Self {
+        let reflections = recall(self.config.reflections.as_ref(), &task).await;
+
+        self.runtime
+            .context
+            .messages
+            .push(Message::Task { content: task });
+
+        for reflection in reflections {
+            self.runtime.context.messages.push(Message::Reflection {
+                content: reflection.content,
+                usage: None,
+            });
+        }
+
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Chain for ReflexionChain {
+    fn dump(&self) -> ContextDump {
+        self.runtime.context.dump()
+    }
+
+    fn answer(&mut self, answer: &str) {
+        self.runtime.answer(answer);
+    }
+
+    async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error> {
+        match self.runtime.step().await {
+            Err(Error::MaxStepsReached) => {
+                let attempt = self.runtime.context.dump();
+                if let Err(e) = reflect(
+                    &self.config,
+                    &attempt,
+                    "The maximum number of steps was reached before the task was concluded.",
+                    self.observer.clone(),
+                )
+                .await
+                {
+                    warn!(error = %e, "Failed to reflect on the attempt");
+                }
+
+                Err(Error::MaxStepsReached)
+            }
+            res => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reflection(task: &str, content: &str) -> Reflection {
+        Reflection {
+            task: task.to_string(),
+            failure: "Max steps reached".to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reflections_on_similar_tasks_are_recalled() {
+        let store = MemoryReflections::default();
+        store
+            .add(reflection("Make me a bowl of cereal with milk", "first"))
+            .await;
+        store
+            .add(reflection("What is the capital of France?", "unrelated"))
+            .await;
+        store
+            .add(reflection("Make me a bowl of cereal with milk", "second"))
+            .await;
+        store
+            .add(reflection(
+                "Make me a bowl of cereal with cold milk",
+                "similar",
+            ))
+            .await;
+
+        let recalled = recall(&store, "make me a bowl of cereal with milk").await;
+
+        let contents = recalled
+            .iter()
+            .map(|r| r.content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, vec!["second", "first", "similar"]);
+    }
+
+    #[tokio::test]
+    async fn reflections_are_kept_in_a_file() {
+        let path =
+            std::env::temp_dir().join(format!("sapiens-reflections-{}.yaml", std::process::id()));
+
+        FileReflections::new(&path)
+            .add(reflection("Sort [2, 1]", "Use sorted()"))
+            .await;
+
+        // from another instance
+        let store = FileReflections::new(&path);
+        assert_eq!(
+            store.all().await,
+            vec![reflection("Sort [2, 1]", "Use sorted()")]
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! Chains by name
+//!
+//! A [`ChainRegistry`] maps the names of the chains to the [`ChainFactory`]
+//! creating them. The built-in chains - see [`ChainType`] - are registered by
+//! default; a library user can register their own [`Chain`] and refer to it by
+//! name in [`SapiensConfig::chain`].
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chains::{
+    Chain, Error, MultiStepOODAChain, PlanAndExecuteChain, ReActChain, ReflexionChain,
+    SingleStepOODAChain, TreeOfThoughtsChain,
+};
+use crate::tools::toolbox::Toolbox;
+use crate::{ChainName, ChainType, SapiensConfig, WeakRuntimeObserver};
+
+/// Error while registering or looking up a chain
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistryError {
+    /// A chain already uses the name
+    #[error("A chain named {0} is already registered")]
+    AlreadyRegistered(String),
+    /// No chain has the name
+    #[error("No chain named {0} is registered")]
+    NotFound(String),
+}
+
+/// Creates the [`Chain`] of a task
+#[async_trait::async_trait]
+pub trait ChainFactory: Send + Sync {
+    /// Create a chain for the `task`
+    async fn create(
+        &self,
+        config: SapiensConfig,
+        toolbox: Toolbox,
+        observer: WeakRuntimeObserver,
+        task: String,
+    ) -> Result<Box<dyn Chain>, Error>;
+}
+
+/// Creates the built-in chains
+struct BuiltinChainFactory(ChainType);
+
+#[async_trait::async_trait]
+impl ChainFactory for BuiltinChainFactory {
+    async fn create(
+        &self,
+        config: SapiensConfig,
+        toolbox: Toolbox,
+        observer: WeakRuntimeObserver,
+        task: String,
+    ) -> Result<Box<dyn Chain>, Error> {
+        Ok(match self.0 {
+            ChainType::SingleStepOODA => Box::new(
+                SingleStepOODAChain::new(config, toolbox, observer)
+                    .await?
+                    .with_task(task),
+            ),
+            ChainType::MultiStepOODA => Box::new(
+                MultiStepOODAChain::new(config, toolbox, observer)
+                    .await?
+                    .with_task(task),
+            ),
+            ChainType::ReAct => Box::new(
+                ReActChain::new(config, toolbox, observer)
+                    .await?
+                    .with_task(task),
+            ),
+            ChainType::Reflexion => Box::new(
+                ReflexionChain::new(config, toolbox, observer)
+                    .await?
+                    .with_task(task)
+                    .await,
+            ),
+            ChainType::TreeOfThoughts => Box::new(
+                TreeOfThoughtsChain::new(config, toolbox, observer)
+                    .await?
+                    .with_task(task),
+            ),
+            ChainType::PlanAndExecute => Box::new(
+                PlanAndExecuteChain::new(config, toolbox, observer)
+                    .await?
+                    .with_task(task),
+            ),
+        })
+    }
+}
+
+/// The [`ChainFactory`] of the chains by name
+///
+/// Cloning it is cheap - the factories are shared.
+#[derive(Clone)]
+pub struct ChainRegistry {
+    factories: BTreeMap<ChainName, Arc<dyn ChainFactory>>,
+}
+
+impl Default for ChainRegistry {
+    /// A registry with the built-in chains
+    fn default() -> Self {
+        let factories = ChainType::ALL
+            .into_iter()
+            .map(|chain_type| {
+                (
+                    ChainName::from(chain_type),
+                    Arc::new(BuiltinChainFactory(chain_type)) as Arc<dyn ChainFactory>,
+                )
+            })
+            .collect();
+
+        Self { factories }
+    }
+}
+
+impl ChainRegistry {
+    /// A registry without any chain - not even the built-in ones
+    #[must_use]
+    pub const fn empty() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// Register the `factory` of a chain under `name`
+    ///
+    /// # Errors
+    ///
+    /// If a chain is already registered under `name`.
+    pub fn register(
+        &mut self,
+        name: impl Into<ChainName>,
+        factory: impl ChainFactory + 'static,
+    ) -> Result<(), RegistryError> {
+        let name = name.into();
+        if self.factories.contains_key(&name) {
+            return Err(RegistryError::AlreadyRegistered(name.to_string()));
+        }
+
+        self.factories.insert(name, Arc::new(factory));
+        Ok(())
+    }
+
+    /// Get the factory of the chain registered under `name`
+    ///
+    /// # Errors
+    ///
+    /// If no chain is registered under `name`.
+    pub fn get(&self, name: &ChainName) -> Result<Arc<dyn ChainFactory>, RegistryError> {
+        self.factories
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))
+    }
+
+    /// The names of the registered chains - in alphabetical order
+    #[must_use]
+    pub fn names(&self) -> Vec<ChainName> {
+        self.factories.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ContextDump;
+    use crate::tools::TerminationMessage;
+    use crate::{Error as TaskError, TaskState};
+
+    /// Concludes with the task right away
+    struct EchoChain {
+        task: String,
+    }
+
+    #[async_trait::async_trait]
+    impl Chain for EchoChain {
+        fn dump(&self) -> ContextDump {
+            ContextDump { messages: vec![] }
+        }
+
+        async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error> {
+            Ok(vec![TerminationMessage {
+                conclusion: self.task.clone(),
+                original_question: self.task.clone(),
+            }])
+        }
+    }
+
+    struct EchoChainFactory;
+
+    #[async_trait::async_trait]
+    impl ChainFactory for EchoChainFactory {
+        async fn create(
+            &self,
+            _config: SapiensConfig,
+            _toolbox: Toolbox,
+            _observer: WeakRuntimeObserver,
+            task: String,
+        ) -> Result<Box<dyn Chain>, Error> {
+            Ok(Box::new(EchoChain { task }))
+        }
+    }
+
+    #[test]
+    fn the_built_in_chains_keep_their_names() {
+        let registry = ChainRegistry::default();
+        assert_eq!(registry.names().len(), ChainType::ALL.len());
+
+        for chain_type in ChainType::ALL {
+            let name = ChainName::from(chain_type);
+            assert!(registry.get(&name).is_ok());
+
+            // as on the command line and as serialized
+            assert_eq!(chain_type.name().parse::<ChainType>(), Ok(chain_type));
+            assert_eq!(ChainName::from(format!("{chain_type:?}")), name);
+        }
+
+        let name: ChainName = serde_yaml::from_str("ReAct").unwrap();
+        assert_eq!(name, ChainType::ReAct.into());
+        assert_eq!(serde_yaml::to_string(&name).unwrap(), "react\n");
+
+        let name: ChainName = serde_yaml::from_str("mine").unwrap();
+        assert_eq!(name.as_str(), "mine");
+    }
+
+    #[tokio::test]
+    async fn custom_chains_are_created_by_name() {
+        let mut chains = ChainRegistry::default();
+        chains.register("echo", EchoChainFactory).unwrap();
+        assert_eq!(
+            chains.register(ChainType::ReAct, EchoChainFactory),
+            Err(RegistryError::AlreadyRegistered("react".to_string()))
+        );
+
+        let config = SapiensConfig {
+            chain: "echo".into(),
+            chains: Arc::new(chains),
+            ..SapiensConfig::default()
+        };
+
+        let stop = TaskState::new(config.clone(), Toolbox::default(), "Hello".to_string())
+            .await
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(stop.termination_messages[0].conclusion, "Hello");
+
+        let config = SapiensConfig {
+            chain: "unknown".into(),
+            ..config
+        };
+        let res = TaskState::new(config, Toolbox::default(), "Hello".to_string()).await;
+        assert!(matches!(
+            res,
+            Err(TaskError::ChainRegistryError(RegistryError::NotFound(name))) if name == "unknown"
+        ));
+    }
+}
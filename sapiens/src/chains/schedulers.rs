@@ -1,5 +1,113 @@
+use std::str::FromStr;
+
+use clap::builder::PossibleValue;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
 use super::{Agent, Context, Error, Message, Scheduler, WeakRuntimeObserver};
-use crate::chains;
+use crate::chains::agents;
+use crate::chains::agents::critic::Critic;
+use crate::tools::toolbox::Toolbox;
+use crate::{chains, SapiensConfig};
+
+/// Reviews the Actions before they are executed
+///
+/// A rejected Action is sent back to the agent with the critique, up to
+/// `max_revisions` times. The revisions do not count as steps and are reviewed
+/// too. An Action still rejected once the revisions are exhausted is not
+/// executed: its critique is scheduled instead.
+///
+/// The critiques are reported to the observer as [`Message::Critique`]. The
+/// ones of the rejected Actions are added to the [`Context`] of the runtime -
+/// see [`Scheduler::drain_messages`].
+pub struct CriticStage {
+    /// The critic
+    critic: Box<dyn Agent<Error = agents::Error>>,
+    /// Maximum number of revisions of an Action
+    max_revisions: usize,
+    /// The critiques of the rejected Actions not yet added to the [`Context`]
+    critiques: Vec<Message>,
+}
+
+impl CriticStage {
+    /// Create a new stage with a critic and a maximum number of revisions
+    #[must_use]
+    pub fn new(critic: Box<dyn Agent<Error = agents::Error>>, max_revisions: usize) -> Self {
+        Self {
+            critic,
+            max_revisions,
+            critiques: vec![],
+        }
+    }
+
+    /// The stage configured by the [`SapiensConfig::critic`] - if any
+    #[must_use]
+    pub fn from_config(config: &SapiensConfig, toolbox: Toolbox) -> Option<Self> {
+        let max_revisions = config.critic.as_ref()?.max_revisions;
+        let critic = Critic::new(config.clone(), toolbox);
+        Some(Self::new(Box::new(critic), max_revisions))
+    }
+
+    /// Review the `message` of the `agent` - and its revisions
+    ///
+    /// Returns the approved Action - or the last critique if the revisions
+    /// are exhausted.
+    async fn review<E>(
+        &mut self,
+        agent: &dyn Agent<Error = E>,
+        context: &Context,
+        mut message: Message,
+        observer: &WeakRuntimeObserver,
+    ) -> Result<Message, Error>
+    where
+        chains::Error: From<E>,
+    {
+        let mut context = context.clone();
+
+        for revision in 0..=self.max_revisions {
+            if !matches!(message, Message::Action { .. }) {
+                break;
+            }
+
+            let mut reviewed = context.clone();
+            reviewed.add_message(message);
+            let critique = self.critic.act(&reviewed).await?;
+            message = reviewed.messages.pop().unwrap();
+
+            let approved = matches!(critique, Message::Critique { approved: true, .. });
+            if !approved && revision == self.max_revisions {
+                // reported by the runtime
+                warn!(
+                    "The Action is still rejected after {revision} revisions - it is not executed"
+                );
+                return Ok(critique);
+            }
+
+            if let Some(observer) = observer.upgrade() {
+                observer
+                    .lock()
+                    .await
+                    .on_message(critique.clone().into())
+                    .await;
+            }
+
+            if approved {
+                break;
+            }
+
+            self.critiques.push(critique.clone());
+            context.add_message(critique);
+            message = agent.act(&context).await?;
+        }
+
+        Ok(message)
+    }
+
+    /// Take the critiques of the rejected Actions
+    fn drain_critiques(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.critiques)
+    }
+}
 
 /// A simple scheduler that can be used to schedule agents
 ///
@@ -11,8 +119,9 @@ pub struct SingleAgentScheduler<E> {
     /// The agent
     agent: Box<dyn Agent<Error = E>>,
     /// Observer
-    #[allow(dead_code)]
     observer: WeakRuntimeObserver,
+    /// The reviews of the Actions
+    critic: Option<CriticStage>,
 }
 
 impl<E> SingleAgentScheduler<E> {
@@ -27,8 +136,16 @@ impl<E> SingleAgentScheduler<E> {
             remaining_steps: max_steps,
             agent,
             observer,
+            critic: None,
         }
     }
+
+    /// Review the Actions of the agent before they are executed
+    #[must_use]
+    pub fn with_critic(mut self, critic: Option<CriticStage>) -> Self {
+        self.critic = critic;
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -46,7 +163,17 @@ where
 
         let message = agent.act(context).await?;
 
-        Ok(message)
+        match &mut self.critic {
+            Some(critic) => critic.review(agent, context, message, &self.observer).await,
+            None => Ok(message),
+        }
+    }
+
+    fn drain_messages(&mut self) -> Vec<Message> {
+        self.critic
+            .as_mut()
+            .map(CriticStage::drain_critiques)
+            .unwrap_or_default()
     }
 }
 
@@ -55,8 +182,8 @@ pub struct MultiAgentScheduler<E> {
     remaining_steps: usize,
     next_agent: usize,
     agents: Vec<Box<dyn Agent<Error = E>>>,
-    #[allow(dead_code)]
     observer: WeakRuntimeObserver,
+    critic: Option<CriticStage>,
 }
 
 impl<E> MultiAgentScheduler<E> {
@@ -73,8 +200,16 @@ impl<E> MultiAgentScheduler<E> {
             next_agent: 0,
             agents,
             observer,
+            critic: None,
         }
     }
+
+    /// Review the Actions of the agents before they are executed
+    #[must_use]
+    pub fn with_critic(mut self, critic: Option<CriticStage>) -> Self {
+        self.critic = critic;
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -92,11 +227,156 @@ where
             self.next_agent = 0;
         }
 
-        let agent = self.agents.get_mut(self.next_agent).unwrap();
+        let agent = self.agents[self.next_agent].as_ref();
         self.next_agent += 1;
 
         let message = agent.act(context).await?;
 
-        Ok(message)
+        match &mut self.critic {
+            Some(critic) => critic.review(agent, context, message, &self.observer).await,
+            None => Ok(message),
+        }
+    }
+
+    fn drain_messages(&mut self) -> Vec<Message> {
+        self.critic
+            .as_mut()
+            .map(CriticStage::drain_critiques)
+            .unwrap_or_default()
+    }
+}
+
+/// How the agents of a multi-agent chain are scheduled
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scheduling {
+    /// In a fixed order - see [`MultiAgentScheduler`]
+    #[default]
+    RoundRobin,
+    /// By rules over the last message - see
+    /// [`RuleRouter`](crate::chains::agents::router::RuleRouter)
+    Rules,
+    /// By a model - see
+    /// [`ModelRouter`](crate::chains::agents::router::ModelRouter)
+    Model,
+}
+
+impl FromStr for Scheduling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Self::RoundRobin),
+            "rules" => Ok(Self::Rules),
+            "model" => Ok(Self::Model),
+            _ => Err(format!("Unknown scheduling: {s}")),
+        }
+    }
+}
+
+#[cfg(feature = "clap")]
+impl clap::ValueEnum for Scheduling {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::RoundRobin, Self::Rules, Self::Model]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        match self {
+            Self::RoundRobin => Some(PossibleValue::new("round-robin")),
+            Self::Rules => Some(PossibleValue::new("rules")),
+            Self::Model => Some(PossibleValue::new("model")),
+        }
+    }
+}
+
+/// Scheduler letting a router pick the next agent
+///
+/// The router is an [`Agent`] producing a [`Message::Routing`] - reported to
+/// the observer. The agents are picked in order when the router picks none of
+/// them.
+pub struct DynamicScheduler<E> {
+    remaining_steps: usize,
+    next_agent: usize,
+    agents: Vec<(String, Box<dyn Agent<Error = E>>)>,
+    router: Box<dyn Agent<Error = agents::Error>>,
+    observer: WeakRuntimeObserver,
+    critic: Option<CriticStage>,
+}
+
+impl<E> DynamicScheduler<E> {
+    /// Create a new scheduler with a maximum number of steps, a list of
+    /// agents with their names and a router
+    ///
+    /// Scheduling fails with [`Error::NoAgent`] if there is no agent.
+    #[must_use]
+    pub fn new(
+        max_steps: usize,
+        agents: Vec<(String, Box<dyn Agent<Error = E>>)>,
+        router: Box<dyn Agent<Error = agents::Error>>,
+        observer: WeakRuntimeObserver,
+    ) -> Self {
+        Self {
+            remaining_steps: max_steps,
+            next_agent: 0,
+            agents,
+            router,
+            observer,
+            critic: None,
+        }
+    }
+
+    /// Review the Actions of the agents before they are executed
+    #[must_use]
+    pub fn with_critic(mut self, critic: Option<CriticStage>) -> Self {
+        self.critic = critic;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<E> Scheduler for DynamicScheduler<E>
+where
+    chains::Error: From<E>,
+{
+    async fn schedule(&mut self, context: &Context) -> Result<Message, Error> {
+        if self.agents.is_empty() {
+            return Err(Error::NoAgent);
+        }
+        if self.remaining_steps == 0 {
+            return Err(Error::MaxStepsReached);
+        }
+        self.remaining_steps -= 1;
+
+        let route = self.router.act(context).await?;
+
+        if let Some(observer) = self.observer.upgrade() {
+            observer.lock().await.on_message(route.clone().into()).await;
+        }
+
+        let picked = match &route {
+            Message::Routing { agent, .. } => self.agents.iter().position(|(n, _)| n == agent),
+            _ => None,
+        };
+
+        let index = picked.unwrap_or_else(|| {
+            warn!(route = %route, "No agent picked - the next one in order is");
+            self.next_agent % self.agents.len()
+        });
+        self.next_agent = index + 1;
+
+        let agent = self.agents[index].1.as_ref();
+
+        let message = agent.act(context).await?;
+
+        match &mut self.critic {
+            Some(critic) => critic.review(agent, context, message, &self.observer).await,
+            None => Ok(message),
+        }
+    }
+
+    fn drain_messages(&mut self) -> Vec<Message> {
+        self.critic
+            .as_mut()
+            .map(CriticStage::drain_critiques)
+            .unwrap_or_default()
     }
 }
@@ -5,6 +5,9 @@ use serde_yaml::Value;
 use tokio::sync::Mutex;
 
 use super::*;
+use crate::control::ControlHandle;
+use crate::models::{ChatEntryTokenNumber, ChatInput, Model, ModelResponse};
+use crate::tools::toolbox::InvocationMode;
 use crate::tools::{FieldFormat, Format, TerminalTool, Tool, ToolDescription};
 use crate::void_observer;
 
@@ -90,7 +93,10 @@ impl TerminalTool for ConcludeTool {
 async fn observes_too_much() {
     let toolbox = {
         let toolbox = Toolbox::default();
-        toolbox.add_terminal_tool(ConcludeTool::default()).await;
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
         toolbox
     };
     let observer = void_observer();
@@ -147,7 +153,10 @@ impl Agent for NoAsSimpleAgent {
 async fn observes_and_conclude() {
     let toolbox = {
         let toolbox = Toolbox::default();
-        toolbox.add_terminal_tool(ConcludeTool::default()).await;
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
         toolbox
     };
 
@@ -177,3 +186,656 @@ async fn observes_and_conclude() {
     let message = &terminal_state.messages[0];
     assert_eq!(message.conclusion, "Done");
 }
+
+struct ManyActionsAgent {}
+
+#[async_trait::async_trait]
+impl Agent for ManyActionsAgent {
+    type Error = ();
+
+    async fn act(&self, _context: &Context) -> Result<Message, ()> {
+        Ok(Message::Action {
+            content: indoc! {r#"
+            ```yaml
+            - tool_name: Inexistent
+              parameters:
+                  something: "else"
+            - tool_name: ConcludeTool
+              parameters:
+                  conclusion: "Done"
+            ```
+            "#
+            }
+            .to_string(),
+            usage: None,
+        })
+    }
+}
+
+async fn run_one_step_with_mode(mode: InvocationMode) -> (Vec<TerminationMessage>, Message) {
+    let toolbox = {
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
+        toolbox.set_invocation_mode(mode).await;
+        toolbox
+    };
+
+    let observer = void_observer();
+    let observer = Arc::downgrade(&observer);
+
+    let scheduler = Box::new(schedulers::SingleAgentScheduler::new(
+        10,
+        Box::new(ManyActionsAgent {}),
+        observer.clone(),
+    ));
+    let mut runtime = Runtime::new(toolbox, scheduler, observer).await.unwrap();
+
+    let termination_messages = runtime.step().await.unwrap();
+
+    (
+        termination_messages,
+        runtime.context.messages.last().unwrap().clone(),
+    )
+}
+
+#[tokio::test]
+async fn invokes_only_the_first_action() {
+    let (termination_messages, last) = run_one_step_with_mode(InvocationMode::FirstOnly).await;
+
+    assert!(termination_messages.is_empty());
+    assert!(matches!(
+        last,
+        Message::ActionResult {
+            invocation_count: 2,
+            outcome: Outcome::ToolUseError {
+                e: ToolUseError::ToolNotFound(_)
+            },
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn invokes_all_the_actions() {
+    let (termination_messages, last) = run_one_step_with_mode(InvocationMode::All {
+        concurrent: true,
+        stop_on_error: false,
+    })
+    .await;
+
+    assert_eq!(termination_messages.len(), 1);
+
+    let Message::ActionResult {
+        invocation_count: 2,
+        outcome: Outcome::Multiple { outcomes },
+        ..
+    } = last
+    else {
+        panic!("Unexpected message: {last:?}");
+    };
+
+    assert_eq!(outcomes.len(), 2);
+    assert_eq!(outcomes[0].tool_name, "Inexistent");
+    assert!(matches!(outcomes[0].outcome, Outcome::ToolUseError { .. }));
+    assert_eq!(outcomes[1].tool_name, "ConcludeTool");
+    assert!(matches!(outcomes[1].outcome, Outcome::Success { .. }));
+}
+
+#[tokio::test]
+async fn stops_at_the_first_failed_action() {
+    let (termination_messages, last) = run_one_step_with_mode(InvocationMode::All {
+        concurrent: false,
+        stop_on_error: true,
+    })
+    .await;
+
+    assert!(termination_messages.is_empty());
+
+    let Message::ActionResult {
+        invocation_count: 2,
+        outcome: Outcome::Multiple { outcomes },
+        ..
+    } = last
+    else {
+        panic!("Unexpected message: {last:?}");
+    };
+
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].tool_name, "Inexistent");
+}
+
+/// Proposes the scripted thoughts in order - scores 1 the branches with `bad`
+/// and 9 the others
+struct ScriptedModel {
+    thoughts: Mutex<Vec<&'static str>>,
+    evaluations: Arc<Mutex<usize>>,
+}
+
+#[async_trait::async_trait]
+impl ChatEntryTokenNumber for ScriptedModel {
+    async fn num_tokens(&self, _input: ChatInput) -> usize {
+        0
+    }
+
+    async fn context_size(&self) -> usize {
+        4096
+    }
+}
+
+#[async_trait::async_trait]
+impl Model for ScriptedModel {
+    async fn query(
+        &self,
+        input: ChatInput,
+        _max_tokens: Option<usize>,
+    ) -> Result<ModelResponse, crate::models::Error> {
+        let msg = if input.context[0].msg.contains("evaluates") {
+            *self.evaluations.lock().await += 1;
+
+            let branch = &input.chat.last().unwrap().msg;
+            if branch.contains("bad") {
+                "Score: 1".to_string()
+            } else {
+                "Score: 9".to_string()
+            }
+        } else {
+            self.thoughts.lock().await.remove(0).to_string()
+        };
+
+        Ok(ModelResponse {
+            msg,
+            usage: Some(Usage {
+                prompt_tokens: 1,
+                completion_tokens: 1,
+                total_tokens: 2,
+            }),
+            finish_reason: None,
+        })
+    }
+}
+
+#[tokio::test]
+async fn tree_of_thoughts_commits_the_action_of_the_best_branch_once() {
+    let evaluations = Arc::new(Mutex::new(0));
+    let model = ScriptedModel {
+        thoughts: Mutex::new(vec![
+            "Thought: a bad idea.",
+            "Thought: a good idea.",
+            indoc! {r#"
+            Thought: a good plan.
+            Action:
+            ```yaml
+            tool_name: ConcludeTool
+            parameters:
+                conclusion: "Done"
+            ```"#},
+            "Thought: a bad turn.",
+        ]),
+        evaluations: evaluations.clone(),
+    };
+
+    let config = SapiensConfig {
+        model: Arc::new(Box::new(model)),
+        tree_of_thoughts: tot::TreeOfThoughtsConfig {
+            candidates: 2,
+            breadth: 1,
+            depth: 2,
+            prune_below: 3,
+        },
+        ..SapiensConfig::default()
+    };
+
+    let toolbox = Toolbox::default();
+    toolbox
+        .add_terminal_tool(ConcludeTool::default())
+        .await
+        .unwrap();
+
+    let observer = void_observer();
+    let observer = Arc::downgrade(&observer);
+
+    let mut chain = TreeOfThoughtsChain::new(config, toolbox, observer)
+        .await
+        .unwrap()
+        .with_task("Conclude".to_string());
+
+    // the Conclude tool fails when invoked twice
+    let termination_messages = chain.step().await.unwrap();
+    assert_eq!(termination_messages.len(), 1);
+    assert_eq!(termination_messages[0].conclusion, "Done");
+    assert_eq!(*evaluations.lock().await, 4);
+
+    let Some(Message::Action { content, usage }) = chain.dump().messages.get(1).cloned() else {
+        panic!("No committed Action");
+    };
+    assert!(content.starts_with("Thought: a good idea.\nThought: a good plan."));
+    assert_eq!(usage.unwrap().total_tokens, 16);
+}
+
+/// Proposes a first Action - and a revised one once critiqued
+struct RevisingAgent {}
+
+#[async_trait::async_trait]
+impl Agent for RevisingAgent {
+    type Error = agents::Error;
+
+    async fn act(&self, context: &Context) -> Result<Message, agents::Error> {
+        let critiqued = context
+            .messages
+            .iter()
+            .any(|m| matches!(m, Message::Critique { .. }));
+
+        Ok(Message::Action {
+            content: if critiqued { "revised" } else { "first" }.to_string(),
+            usage: None,
+        })
+    }
+}
+
+/// Rejects the first Action
+struct StrictCritic {
+    reviews: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait::async_trait]
+impl Agent for StrictCritic {
+    type Error = agents::Error;
+
+    async fn act(&self, context: &Context) -> Result<Message, agents::Error> {
+        let Some(Message::Action { content, .. }) = context.messages.last() else {
+            panic!("Nothing to review");
+        };
+        self.reviews.lock().await.push(content.clone());
+
+        Ok(Message::Critique {
+            action: content.clone(),
+            approved: content != "first",
+            content: "Not the first one.".to_string(),
+            usage: None,
+        })
+    }
+}
+
+#[tokio::test]
+async fn rejected_actions_are_revised() {
+    let reviews = Arc::new(Mutex::new(vec![]));
+    let critic = StrictCritic {
+        reviews: reviews.clone(),
+    };
+
+    let observer = void_observer();
+    let observer = Arc::downgrade(&observer);
+
+    let mut scheduler =
+        schedulers::SingleAgentScheduler::new(10, Box::new(RevisingAgent {}), observer)
+            .with_critic(Some(schedulers::CriticStage::new(Box::new(critic), 2)));
+
+    let message = scheduler.schedule(&Context::new()).await.unwrap();
+
+    assert!(matches!(message, Message::Action { content, .. } if content == "revised"));
+    assert_eq!(*reviews.lock().await, vec!["first", "revised"]);
+
+    // the critique of the rejected Action goes in the context of the runtime
+    let critiques = scheduler.drain_messages();
+    assert!(matches!(
+        critiques.as_slice(),
+        [Message::Critique { action, approved: false, .. }] if action == "first"
+    ));
+    assert!(scheduler.drain_messages().is_empty());
+}
+
+#[tokio::test]
+async fn revisions_are_bounded() {
+    let reviews = Arc::new(Mutex::new(vec![]));
+    let critic = StrictCritic {
+        reviews: reviews.clone(),
+    };
+
+    let observer = void_observer();
+    let observer = Arc::downgrade(&observer);
+
+    let mut scheduler =
+        schedulers::SingleAgentScheduler::new(10, Box::new(RevisingAgent {}), observer)
+            .with_critic(Some(schedulers::CriticStage::new(Box::new(critic), 0)));
+
+    let message = scheduler.schedule(&Context::new()).await.unwrap();
+
+    // the Action is reviewed even without revisions - and not executed when
+    // rejected
+    assert!(matches!(
+        message,
+        Message::Critique { action, approved: false, .. } if action == "first"
+    ));
+    assert_eq!(*reviews.lock().await, vec!["first"]);
+    assert!(scheduler.drain_messages().is_empty());
+}
+
+/// Observes with its name
+struct NamedAgent {
+    name: &'static str,
+}
+
+#[async_trait::async_trait]
+impl Agent for NamedAgent {
+    type Error = agents::Error;
+
+    async fn act(&self, _context: &Context) -> Result<Message, agents::Error> {
+        Ok(Message::Observation {
+            content: self.name.to_string(),
+            usage: None,
+        })
+    }
+}
+
+#[tokio::test]
+async fn the_router_picks_the_next_agent() {
+    let agents = ["first", "second", "third"]
+        .into_iter()
+        .map(|name| {
+            (
+                name.to_string(),
+                Box::new(NamedAgent { name }) as Box<dyn Agent<Error = agents::Error>>,
+            )
+        })
+        .collect();
+
+    // the third agent after a Task, the first one otherwise - an unknown one
+    // after the first one
+    let router = agents::router::RuleRouter::new(Box::new(|context| {
+        let agent = match context.messages.last() {
+            Some(Message::Task { .. }) => "third",
+            Some(Message::Observation { content, .. }) if content == "first" => "fourth",
+            _ => "first",
+        };
+        (agent.to_string(), "Because".to_string())
+    }));
+
+    let observer = void_observer();
+    let observer = Arc::downgrade(&observer);
+
+    let mut scheduler = schedulers::DynamicScheduler::new(10, agents, Box::new(router), observer);
+
+    let mut context = Context::new();
+    context.add_message(Message::Task {
+        content: "Pick".to_string(),
+    });
+
+    let mut picked = vec![];
+    for _ in 0..3 {
+        let message = scheduler.schedule(&context).await.unwrap();
+        if let Message::Observation { content, .. } = &message {
+            picked.push(content.clone());
+        }
+        context.add_message(message);
+    }
+
+    // no agent is named "fourth": the next one in order is picked
+    assert_eq!(picked, vec!["third", "first", "second"]);
+}
+
+#[tokio::test]
+async fn the_router_needs_agents() {
+    let router =
+        agents::router::RuleRouter::new(Box::new(|_| ("first".to_string(), "Because".to_string())));
+
+    let observer = void_observer();
+    let observer = Arc::downgrade(&observer);
+
+    let mut scheduler =
+        schedulers::DynamicScheduler::<agents::Error>::new(10, vec![], Box::new(router), observer);
+
+    assert!(matches!(
+        scheduler.schedule(&Context::new()).await,
+        Err(Error::NoAgent)
+    ));
+}
+
+/// Never answers - counts the queries
+struct StuckModel {
+    queries: Arc<Mutex<usize>>,
+}
+
+#[async_trait::async_trait]
+impl ChatEntryTokenNumber for StuckModel {
+    async fn num_tokens(&self, _input: ChatInput) -> usize {
+        0
+    }
+
+    async fn context_size(&self) -> usize {
+        4096
+    }
+}
+
+#[async_trait::async_trait]
+impl Model for StuckModel {
+    async fn query(
+        &self,
+        _input: ChatInput,
+        _max_tokens: Option<usize>,
+    ) -> Result<ModelResponse, crate::models::Error> {
+        *self.queries.lock().await += 1;
+        std::future::pending().await
+    }
+}
+
+async fn stuck_task(
+    max_duration: Option<std::time::Duration>,
+    control: ControlHandle,
+) -> (crate::TaskState, Arc<Mutex<usize>>) {
+    let queries = Arc::new(Mutex::new(0));
+    let config = SapiensConfig {
+        model: Arc::new(Box::new(StuckModel {
+            queries: queries.clone(),
+        })),
+        chain: crate::ChainType::ReAct.into(),
+        max_duration,
+        ..SapiensConfig::default()
+    };
+
+    let observer = void_observer();
+    let observer = Arc::downgrade(&observer);
+
+    let toolbox = Toolbox::default();
+    toolbox
+        .add_terminal_tool(ConcludeTool::default())
+        .await
+        .unwrap();
+
+    let task_state =
+        crate::TaskState::with_control(config, toolbox, "Wait".to_string(), observer, control)
+            .await
+            .unwrap();
+
+    (task_state, queries)
+}
+
+#[tokio::test]
+async fn cancelling_interrupts_the_model_calls() {
+    let control = ControlHandle::new();
+    let (task_state, queries) = stuck_task(None, control.clone()).await;
+
+    let canceller = control.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        canceller.cancel();
+    });
+
+    let res = task_state.step().await;
+    assert!(matches!(res, Err(crate::Error::Cancelled)));
+    assert_eq!(*queries.lock().await, 1);
+    assert!(control.is_cancelled());
+}
+
+#[tokio::test]
+async fn tasks_time_out() {
+    let max_duration = std::time::Duration::from_millis(20);
+    let (task_state, _) = stuck_task(Some(max_duration), ControlHandle::new()).await;
+
+    let res = task_state.step().await;
+    assert!(matches!(res, Err(crate::Error::TimedOut(d)) if d == max_duration));
+}
+
+#[tokio::test]
+async fn paused_tasks_wait_to_be_resumed() {
+    let control = ControlHandle::new();
+    control.pause();
+    let (task_state, queries) = stuck_task(None, control.clone()).await;
+
+    let step = tokio::spawn(task_state.step());
+
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert_eq!(*queries.lock().await, 0);
+
+    control.resume();
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert_eq!(*queries.lock().await, 1);
+
+    // a paused task can be cancelled
+    control.pause();
+    control.cancel();
+    assert!(matches!(step.await.unwrap(), Err(crate::Error::Cancelled)));
+}
+
+/// Always invokes the same inexistent tool
+struct InCirclesAgent {}
+
+#[async_trait::async_trait]
+impl Agent for InCirclesAgent {
+    type Error = ();
+
+    async fn act(&self, _context: &Context) -> Result<Message, ()> {
+        Ok(Message::Action {
+            content: indoc! {r#"
+            ```yaml
+            tool_name: Inexistent
+            parameters:
+                something: "else"
+            ```
+            "#
+            }
+            .to_string(),
+            usage: None,
+        })
+    }
+}
+
+/// Records the detected loops
+#[derive(Default)]
+struct LoopRecorder {
+    loops: Vec<loops::Loop>,
+}
+
+#[async_trait::async_trait]
+impl crate::RuntimeObserver for LoopRecorder {
+    async fn on_loop(&mut self, event: LoopNotification) {
+        self.loops.push(event.detected);
+    }
+}
+
+/// Run 3 steps going in circles - the result of the last one
+async fn go_in_circles(
+    response: loops::LoopResponse,
+) -> (
+    Runtime,
+    Toolbox,
+    Vec<loops::Loop>,
+    Result<Vec<TerminationMessage>, Error>,
+) {
+    let toolbox = Toolbox::default();
+    toolbox
+        .add_terminal_tool(ConcludeTool::default())
+        .await
+        .unwrap();
+
+    let recorder = crate::wrap_observer(LoopRecorder::default());
+    let observer = Arc::downgrade(&recorder);
+
+    let scheduler = Box::new(schedulers::SingleAgentScheduler::new(
+        10,
+        Box::new(InCirclesAgent {}),
+        observer.clone(),
+    ));
+    let mut runtime = Runtime::new(toolbox.clone(), scheduler, observer)
+        .await
+        .unwrap()
+        .with_loop_detection(Some(loops::LoopDetectionConfig {
+            response,
+            ..loops::LoopDetectionConfig::default()
+        }));
+
+    for _ in 0..2 {
+        runtime.step().await.unwrap();
+    }
+    assert!(recorder.lock().await.loops.is_empty());
+
+    let res = runtime.step().await;
+    let loops = recorder.lock().await.loops.clone();
+
+    (runtime, toolbox, loops, res)
+}
+
+/// The warnings about the loops in the result of the last Action
+fn loop_warnings(runtime: &Runtime) -> Vec<Option<String>> {
+    let Some(Message::ActionResult { warnings, .. }) = runtime.context.messages.last() else {
+        panic!("No Action result");
+    };
+
+    warnings
+        .iter()
+        .filter_map(|w| match w {
+            Warning::Repeated { guidance, .. } => Some(guidance.clone()),
+            Warning::Invocation(_) => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn loops_are_corrected() {
+    let (runtime, toolbox, loops, res) = go_in_circles(loops::LoopResponse::Correct).await;
+
+    assert!(res.unwrap().is_empty());
+    assert_eq!(loops.len(), 1);
+    assert_eq!(loops[0].repeats, 3);
+    assert_eq!(loop_warnings(&runtime), vec![None]);
+    assert_eq!(toolbox.take_question().await, None);
+}
+
+#[tokio::test]
+async fn the_user_is_asked_about_the_loops() {
+    let (mut runtime, toolbox, loops, res) = go_in_circles(loops::LoopResponse::AskUser).await;
+
+    assert!(res.unwrap().is_empty());
+    assert_eq!(loops.len(), 1);
+    assert!(toolbox.take_question().await.is_some());
+
+    runtime.answer("Conclude");
+    assert_eq!(loop_warnings(&runtime), vec![Some("Conclude".to_string())]);
+}
+
+#[tokio::test]
+async fn loops_abort_the_task() {
+    let (_, _, loops, res) = go_in_circles(loops::LoopResponse::Abort).await;
+
+    assert_eq!(loops.len(), 1);
+    assert!(matches!(res, Err(Error::LoopDetected(detected)) if detected == loops[0]));
+}
+
+#[test]
+fn long_results_are_given_to_the_model() {
+    // the artifacts bound them - see `ArtifactPolicy::max_result_chars`
+    let result = "a".repeat(3000);
+
+    let msg = agents::format_outcome_only(
+        1,
+        &Some("Wikipedia".to_string()),
+        &Outcome::Success {
+            result: result.clone(),
+        },
+        &[] as &[Warning],
+    );
+
+    assert!(msg.contains(&result));
+}
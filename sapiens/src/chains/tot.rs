@@ -0,0 +1,273 @@
+//! Tree of Thoughts - 2305.10601
+//!
+//! At each step, `candidates` next thoughts are proposed for each explored
+//! branch and scored by a model-based evaluator. The `breadth` most promising
+//! branches are continued, down to `depth` thoughts.
+//!
+//! The tools have side effects: the branches only reason. The Action of the
+//! most promising branch is committed - and run once by the [`Runtime`].
+use std::cmp::Reverse;
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::chains::agents::tot::{Evaluator, Proposer, MAX_SCORE};
+use crate::chains::agents::Error as AgentError;
+use crate::chains::schedulers::{CriticStage, SingleAgentScheduler};
+use crate::chains::{Chain, Context, Error, Message, Runtime};
+use crate::context::ContextDump;
+use crate::models::{ModelResponse, Usage};
+use crate::tools::toolbox::Toolbox;
+use crate::tools::{invocation, TerminationMessage};
+use crate::{chains, SapiensConfig, WeakRuntimeObserver};
+
+/// Parameters of the search of a [`TreeOfThoughtsChain`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeOfThoughtsConfig {
+    /// Number of next thoughts proposed for each explored branch
+    pub candidates: usize,
+    /// Number of branches explored further at each level
+    pub breadth: usize,
+    /// Maximum number of thoughts before an Action must be committed
+    pub depth: usize,
+    /// Branches scored below are pruned - from 0 to 10
+    pub prune_below: u8,
+}
+
+impl Default for TreeOfThoughtsConfig {
+    fn default() -> Self {
+        Self {
+            candidates: 3,
+            breadth: 2,
+            depth: 3,
+            prune_below: 3,
+        }
+    }
+}
+
+/// A branch of the search
+#[derive(Debug)]
+struct Branch {
+    /// The thoughts - the last one may have an Action
+    thoughts: Vec<String>,
+    /// The score of the thoughts
+    score: u8,
+    /// Whether the last thought has an Action
+    acts: bool,
+}
+
+/// Add the `usage` to the `total`
+fn add_usage(total: &mut Option<Usage>, usage: Option<&Usage>) {
+    let Some(usage) = usage else {
+        return;
+    };
+
+    let total = total.get_or_insert(Usage {
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        total_tokens: 0,
+    });
+    total.prompt_tokens += usage.prompt_tokens;
+    total.completion_tokens += usage.completion_tokens;
+    total.total_tokens += usage.total_tokens;
+}
+
+/// Searches the tree of thoughts for the next Action
+pub struct Searcher {
+    proposer: Proposer,
+    evaluator: Evaluator,
+    config: TreeOfThoughtsConfig,
+    observer: WeakRuntimeObserver,
+}
+
+impl Searcher {
+    /// Create a new [`Searcher`].
+    #[must_use]
+    pub fn new(config: SapiensConfig, toolbox: Toolbox, observer: WeakRuntimeObserver) -> Self {
+        Self {
+            proposer: Proposer::new(config.clone(), toolbox),
+            config: config.tree_of_thoughts,
+            evaluator: Evaluator::new(config),
+            observer,
+        }
+    }
+
+    /// Propose and score the next thoughts of a branch
+    async fn expand(
+        &self,
+        context: &Context,
+        thoughts: &[String],
+        must_act: bool,
+        usage: &mut Option<Usage>,
+    ) -> Result<Vec<Branch>, AgentError> {
+        let mut proposed: Vec<String> = Vec::new();
+        let mut branches = Vec::new();
+
+        for _ in 0..self.config.candidates.max(1) {
+            let res = self
+                .proposer
+                .propose(context, thoughts, &proposed, must_act)
+                .await?;
+            add_usage(usage, res.usage.as_ref());
+
+            let thought = res.msg.trim().to_string();
+            if proposed.contains(&thought) {
+                continue;
+            }
+            proposed.push(thought.clone());
+
+            let mut branch = thoughts.to_vec();
+            branch.push(thought);
+
+            let (score, res) = self.evaluator.evaluate(context, &branch).await?;
+            add_usage(usage, res.usage.as_ref());
+
+            debug!(depth = branch.len(), score, "New thought");
+
+            branches.push(Branch {
+                acts: invocation::find_all(branch.last().unwrap()).is_ok(),
+                thoughts: branch,
+                score,
+            });
+        }
+
+        Ok(branches)
+    }
+
+    /// Search the next Action
+    ///
+    /// Returns the thoughts of the most promising branch with an Action - or
+    /// of the most promising one when the maximum depth is reached without an
+    /// Action.
+    async fn search(
+        &self,
+        context: &Context,
+        usage: &mut Option<Usage>,
+    ) -> Result<Vec<String>, AgentError> {
+        let mut frontier = vec![Vec::new()];
+
+        for level in 0..=self.config.depth {
+            let must_act = level == self.config.depth;
+
+            let mut branches = Vec::new();
+            for thoughts in &frontier {
+                branches.extend(self.expand(context, thoughts, must_act, usage).await?);
+            }
+
+            // stable: the first proposed first among the equally scored ones
+            branches.sort_by_key(|b| Reverse(b.score));
+
+            let Some(best) = branches.first() else {
+                break;
+            };
+
+            if best.acts || must_act {
+                debug!(depth = best.thoughts.len(), score = best.score, "Committed");
+                return Ok(branches.swap_remove(0).thoughts);
+            }
+
+            let best = best.thoughts.clone();
+
+            // the branches with an Action are leaves - only the Action of the
+            // best branch can be committed
+            frontier = branches
+                .into_iter()
+                .filter(|b| !b.acts && b.score >= self.config.prune_below.min(MAX_SCORE))
+                .take(self.config.breadth.max(1))
+                .map(|b| b.thoughts)
+                .collect();
+
+            // everything was pruned: keep exploring the best branch
+            if frontier.is_empty() {
+                frontier.push(best);
+            }
+        }
+
+        Ok(frontier.swap_remove(0))
+    }
+}
+
+#[async_trait::async_trait]
+impl chains::Agent for Searcher {
+    type Error = AgentError;
+
+    async fn act(&self, context: &Context) -> Result<Message, AgentError> {
+        let mut usage = None;
+        let thoughts = self.search(context, &mut usage).await?;
+
+        let res = ModelResponse {
+            msg: thoughts.join("\n"),
+            usage,
+            finish_reason: None,
+        };
+
+        // Show the committed thoughts
+        if let Some(observer) = self.observer.upgrade() {
+            observer
+                .lock()
+                .await
+                .on_model_update(res.clone().into())
+                .await;
+        }
+
+        // The thoughts and the Action - the Observation is added by the runtime
+        Ok(Message::Action {
+            content: res.msg,
+            usage: res.usage,
+        })
+    }
+}
+
+/// Tree of Thoughts chain - see [`TreeOfThoughtsConfig`] for the parameters
+/// of the search
+pub struct TreeOfThoughtsChain {
+    runtime: Runtime,
+}
+
+impl TreeOfThoughtsChain {
+    /// Create a new [`TreeOfThoughtsChain`]
+    pub async fn new(
+        config: SapiensConfig,
+        toolbox: Toolbox,
+        observer: WeakRuntimeObserver,
+    ) -> Result<Self, Error> {
+        let agent = Searcher::new(config.clone(), toolbox.clone(), observer.clone());
+
+        let scheduler = SingleAgentScheduler::<AgentError>::new(
+            config.max_steps,
+            Box::new(agent),
+            observer.clone(),
+        )
+        .with_critic(CriticStage::from_config(&config, toolbox.clone()));
+        Ok(Self {
+            runtime: Runtime::new(toolbox, Box::new(scheduler), observer)
+                .await?
+                .with_loop_detection(config.loop_detection),
+        })
+    }
+
+    /// Add a new task to the Tree of Thoughts chain
+    #[must_use]
+    pub fn with_task(mut self, task: String) -> Self {
+        self.runtime
+            .context
+            .messages
+            .push(Message::Task { content: task });
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Chain for TreeOfThoughtsChain {
+    fn dump(&self) -> ContextDump {
+        self.runtime.context.dump()
+    }
+
+    fn answer(&mut self, answer: &str) {
+        self.runtime.answer(answer);
+    }
+
+    async fn step(&mut self) -> Result<Vec<TerminationMessage>, Error> {
+        self.runtime.step().await
+    }
+}
@@ -0,0 +1,100 @@
+//! Control of a running task - cancel, pause and resume
+//!
+//! A [`ControlHandle`] is given to [`crate::TaskState::with_control`] and kept
+//! by whoever controls the task - e.g. the Discord bot. The task checks it
+//! between the steps; the cancellation also interrupts the in-flight model and
+//! tool calls - see [`crate::tools::toolbox::Toolbox::cancel_invocations`].
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// A handle to cancel, pause and resume a task - shared by its clones
+#[derive(Debug, Clone, Default)]
+pub struct ControlHandle {
+    /// Cancelled when the task is cancelled - shared with the toolbox of the
+    /// task
+    cancellation: CancellationToken,
+    /// Whether the task is paused
+    paused: Arc<AtomicBool>,
+    /// Notified when the task is resumed
+    resumed: Arc<Notify>,
+}
+
+impl ControlHandle {
+    /// Create a new [`ControlHandle`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A handle cancelled with the `cancellation` token - e.g. a child of the
+    /// one of a parent task
+    pub(crate) fn with_cancellation(cancellation: CancellationToken) -> Self {
+        Self {
+            cancellation,
+            ..Self::default()
+        }
+    }
+
+    /// The cancellation token of the task
+    pub(crate) fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Cancel the task
+    ///
+    /// The in-flight step is interrupted and the task fails with
+    /// [`crate::Error::Cancelled`].
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+        // a paused task is cancelled too
+        self.resumed.notify_waiters();
+    }
+
+    /// Check if the task has been cancelled
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Pause the task before its next step
+    ///
+    /// The in-flight step is completed.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resume a paused task
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.resumed.notify_waiters();
+    }
+
+    /// Check if the task is paused
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the task is resumed - or cancelled
+    pub(crate) async fn wait_while_paused(&self) {
+        loop {
+            // registered before the check: a resumption in between is not
+            // missed
+            let resumed = self.resumed.notified();
+
+            if !self.is_paused() || self.is_cancelled() {
+                return;
+            }
+
+            resumed.await;
+        }
+    }
+
+    /// Wait until the task is cancelled
+    pub(crate) async fn cancelled(&self) {
+        self.cancellation.cancelled().await;
+    }
+}
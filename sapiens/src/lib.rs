@@ -28,18 +28,30 @@ pub mod tools;
 /// Language models
 pub mod models;
 
+pub mod rate_limit;
+
 pub mod chains;
 
+pub mod control;
+
 use std::fmt::Debug;
 use std::str::FromStr;
 use std::sync::{Arc, Weak};
+use std::time::Duration;
 
 use clap::builder::PossibleValue;
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-use crate::chains::{Chain, Message, MultiStepOODAChain, SingleStepOODAChain};
+use crate::chains::agents::critic::CriticConfig;
+use crate::chains::loops::{Loop, LoopDetectionConfig, LoopResponse};
+use crate::chains::reflexion::{MemoryReflections, ReflectionStore};
+use crate::chains::registry::{ChainRegistry, RegistryError};
+use crate::chains::schedulers::Scheduling;
+use crate::chains::tot::TreeOfThoughtsConfig;
+use crate::chains::{Chain, Message};
 use crate::context::{ChatEntry, ContextDump};
+use crate::control::ControlHandle;
 use crate::models::openai::OpenAI;
 use crate::models::{ModelRef, ModelResponse, Role, Usage};
 use crate::tools::toolbox::{InvokeResult, Toolbox};
@@ -63,9 +75,22 @@ pub enum Error {
     /// Error in the chain
     #[error("Chain error: {0}")]
     ChainError(#[from] chains::Error),
+    /// The chain is not registered - see [`SapiensConfig::chains`]
+    #[error("Chain registry error: {0}")]
+    ChainRegistryError(#[from] RegistryError),
+    /// The task is waiting for the answer of the user - see
+    /// [`TaskState::AwaitingInput`]
+    #[error("The task is waiting for the answer to: {0}")]
+    AwaitingInput(String),
+    /// The task has been cancelled - see [`ControlHandle::cancel`]
+    #[error("The task has been cancelled")]
+    Cancelled,
+    /// The task ran longer than [`SapiensConfig::max_duration`]
+    #[error("The task timed out after {0:?}")]
+    TimedOut(Duration),
 }
 
-/// Type of chain to use
+/// The built-in chains - registered by default in the [`ChainRegistry`]
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ChainType {
     /// OODA single step chain
@@ -73,31 +98,125 @@ pub enum ChainType {
     SingleStepOODA,
     /// OODA multi step chain
     MultiStepOODA,
+    /// `ReAct` chain - Thought, Action, Observation
+    ReAct,
+    /// Reflexion chain - `ReAct` with self-reflections on the failed attempts
+    Reflexion,
+    /// Tree of Thoughts chain - search of the most promising thoughts
+    TreeOfThoughts,
+    /// Plan-and-execute chain - a planner maintains the sub-goals worked
+    /// through by a `ReAct` executor
+    PlanAndExecute,
+}
+
+impl ChainType {
+    /// The built-in chains
+    pub const ALL: [Self; 6] = [
+        Self::SingleStepOODA,
+        Self::MultiStepOODA,
+        Self::ReAct,
+        Self::Reflexion,
+        Self::TreeOfThoughts,
+        Self::PlanAndExecute,
+    ];
+
+    /// The name of the chain in the [`ChainRegistry`]
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::SingleStepOODA => "single-step-ooda",
+            Self::MultiStepOODA => "multi-step-ooda",
+            Self::ReAct => "react",
+            Self::Reflexion => "reflexion",
+            Self::TreeOfThoughts => "tree-of-thoughts",
+            Self::PlanAndExecute => "plan-and-execute",
+        }
+    }
 }
 
 impl FromStr for ChainType {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "single-step-ooda" => Ok(Self::SingleStepOODA),
-            "multi-step-ooda" => Ok(Self::MultiStepOODA),
-            _ => Err(format!("Unknown chain type: {s}")),
-        }
+        Self::ALL
+            .into_iter()
+            .find(|chain_type| chain_type.name() == s)
+            .ok_or_else(|| format!("Unknown chain type: {s}"))
     }
 }
 
 #[cfg(feature = "clap")]
 impl clap::ValueEnum for ChainType {
     fn value_variants<'a>() -> &'a [Self] {
-        &[Self::SingleStepOODA, Self::MultiStepOODA]
+        &Self::ALL
     }
 
     fn to_possible_value(&self) -> Option<PossibleValue> {
-        match self {
-            Self::SingleStepOODA => Some(PossibleValue::new("single-step-ooda")),
-            Self::MultiStepOODA => Some(PossibleValue::new("multi-step-ooda")),
-        }
+        Some(PossibleValue::new(self.name()))
+    }
+}
+
+/// Name of a chain in the [`ChainRegistry`] - e.g. `react`
+///
+/// The built-in chains can also be named as in the serialized [`ChainType`] -
+/// e.g. `ReAct`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct ChainName(String);
+
+impl ChainName {
+    /// The name as a string
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for ChainName {
+    fn default() -> Self {
+        ChainType::default().into()
+    }
+}
+
+impl From<ChainType> for ChainName {
+    fn from(chain_type: ChainType) -> Self {
+        Self(chain_type.name().to_string())
+    }
+}
+
+impl From<&str> for ChainName {
+    fn from(name: &str) -> Self {
+        // the serialized built-in chains have the names of the variants
+        ChainType::ALL
+            .into_iter()
+            .find(|chain_type| format!("{chain_type:?}") == name)
+            .map_or_else(|| Self(name.to_string()), Self::from)
+    }
+}
+
+impl From<String> for ChainName {
+    fn from(name: String) -> Self {
+        Self::from(name.as_str())
+    }
+}
+
+impl From<ChainName> for String {
+    fn from(name: ChainName) -> Self {
+        name.0
+    }
+}
+
+impl FromStr for ChainName {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from(s))
+    }
+}
+
+impl std::fmt::Display for ChainName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
@@ -108,12 +227,31 @@ pub struct SapiensConfig {
     pub model: ModelRef,
     /// The maximum number of steps
     pub max_steps: usize,
-    /// The type of chain to use
-    pub chain_type: ChainType,
+    /// How long a task can run - wall-clock time, including the pauses and
+    /// the waits for the answers of the user. No limit if not set.
+    pub max_duration: Option<Duration>,
+    /// The name of the chain to use in [`SapiensConfig::chains`]
+    pub chain: ChainName,
+    /// The chains by name - the built-in ones by default. Shared by the clones
+    /// of the configuration.
+    pub chains: Arc<ChainRegistry>,
     /// The minimum number of tokens that need to be available for completion
     pub min_tokens_for_completion: usize,
     /// Maximum number of tokens for the model to generate
     pub max_tokens: Option<usize>,
+    /// Where the self-reflections of [`ChainType::Reflexion`] are kept -
+    /// shared by the clones of the configuration
+    pub reflections: Arc<dyn ReflectionStore>,
+    /// The parameters of the search of [`ChainType::TreeOfThoughts`]
+    pub tree_of_thoughts: TreeOfThoughtsConfig,
+    /// The critic reviewing the Actions before they are executed - none if
+    /// not set
+    pub critic: Option<CriticConfig>,
+    /// How the agents of [`ChainType::MultiStepOODA`] are scheduled
+    pub scheduling: Scheduling,
+    /// How the loops of the tasks are detected and handled - not detected if
+    /// not set
+    pub loop_detection: Option<LoopDetectionConfig>,
 }
 
 #[allow(clippy::missing_fields_in_debug)]
@@ -121,7 +259,7 @@ impl Debug for SapiensConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Config")
             .field("max_steps", &self.max_steps)
-            .field("chain_type", &self.chain_type)
+            .field("chain", &self.chain)
             .field("min_tokens_for_completion", &self.min_tokens_for_completion)
             .field("max_tokens", &self.max_tokens)
             .finish()
@@ -133,9 +271,16 @@ impl Default for SapiensConfig {
         Self {
             model: Arc::new(Box::<OpenAI>::default()),
             max_steps: 10,
-            chain_type: ChainType::SingleStepOODA,
+            max_duration: None,
+            chain: ChainName::default(),
+            chains: Arc::new(ChainRegistry::default()),
             min_tokens_for_completion: 256,
             max_tokens: None,
+            reflections: Arc::new(MemoryReflections::default()),
+            tree_of_thoughts: TreeOfThoughtsConfig::default(),
+            critic: None,
+            scheduling: Scheduling::default(),
+            loop_detection: None,
         }
     }
 }
@@ -182,6 +327,8 @@ pub enum InvocationResultNotification {
     InvocationFailure(InvocationFailureNotification),
     /// Invalid invocation notification
     InvalidInvocation(InvalidInvocationNotification),
+    /// Several invocations notification
+    MultipleInvocations(MultipleInvocationsNotification),
 }
 
 impl From<InvokeResult> for InvocationResultNotification {
@@ -205,23 +352,34 @@ impl From<InvokeResult> for InvocationResultNotification {
                 tool_name,
                 extracted_input,
                 result,
+                cached,
+                ..
             } => Self::InvocationSuccess(InvocationSuccessNotification {
                 invocation_count,
                 tool_name,
                 extracted_input,
                 result,
+                cached,
             }),
             InvokeResult::Error {
                 invocation_count,
                 tool_name,
                 extracted_input,
                 e,
+                ..
             } => Self::InvocationFailure(InvocationFailureNotification {
                 invocation_count,
                 tool_name,
                 extracted_input,
                 e,
             }),
+            InvokeResult::Multiple {
+                invocation_count,
+                results,
+            } => Self::MultipleInvocations(MultipleInvocationsNotification {
+                invocation_count,
+                results: results.into_iter().map(Into::into).collect(),
+            }),
         }
     }
 }
@@ -236,6 +394,8 @@ pub struct InvocationSuccessNotification {
     pub extracted_input: String,
     /// The result
     pub result: String,
+    /// The result was answered from the cache - the tool was not invoked
+    pub cached: bool,
 }
 
 /// Invocation failure notification
@@ -258,12 +418,55 @@ pub struct InvalidInvocationNotification {
     pub invocation_count: usize,
 }
 
+/// Several invocations notification
+pub struct MultipleInvocationsNotification {
+    /// Number of invocation blocks in the message
+    pub invocation_count: usize,
+    /// The results of the executed invocations - in order
+    pub results: Vec<InvocationResultNotification>,
+}
+
 /// Termination notification
 pub struct TerminationNotification {
     /// The messages
     pub messages: Vec<TerminationMessage>,
 }
 
+/// A loop detected in a task - see [`SapiensConfig::loop_detection`]
+#[derive(Debug, Clone)]
+pub struct LoopNotification {
+    /// What is repeated
+    pub detected: Loop,
+    /// How the runtime responds to it
+    pub response: LoopResponse,
+}
+
+/// What happened in a sub-agent - see [`SubAgentObserver`]
+pub enum SubAgentEvent {
+    /// The task was submitted
+    Task(String),
+    /// The task started
+    Start(ContextDump),
+    /// The model returned something
+    ModelUpdate(ModelNotification),
+    /// The scheduler selected a message
+    Message(MessageNotification),
+    /// A tool was invoked
+    InvocationResult(InvocationResultNotification),
+    /// The task is done
+    Termination(TerminationNotification),
+    /// The task is going in circles
+    Loop(LoopNotification),
+}
+
+/// Progress of a sub-agent
+pub struct SubAgentNotification {
+    /// How deep the sub-agent is nested - 1 for a sub-agent of the main task
+    pub depth: usize,
+    /// What happened
+    pub event: SubAgentEvent,
+}
+
 /// Observer for the step progresses
 #[async_trait::async_trait]
 pub trait RuntimeObserver: Send {
@@ -284,6 +487,13 @@ pub trait RuntimeObserver: Send {
 
     /// Called when the task is done
     async fn on_termination(&mut self, _event: TerminationNotification) {}
+
+    /// Called when a sub-agent progresses - see [`SubAgentObserver`]
+    async fn on_sub_agent(&mut self, _event: SubAgentNotification) {}
+
+    /// Called when the task is going in circles - see
+    /// [`SapiensConfig::loop_detection`]
+    async fn on_loop(&mut self, _event: LoopNotification) {}
 }
 
 /// Wrap an observer into the a [`StrongRuntimeObserver<O>`] = [`Arc<Mutex<O>>`]
@@ -311,18 +521,108 @@ pub(crate) fn void_observer() -> StrongRuntimeObserver<VoidTaskProgressUpdateObs
 #[async_trait::async_trait]
 impl RuntimeObserver for VoidTaskProgressUpdateObserver {}
 
+/// Forwards the progress of a sub-agent to the observer of its parent - as
+/// [`RuntimeObserver::on_sub_agent`] with its depth
+pub struct SubAgentObserver {
+    /// The observer of the parent
+    parent: WeakRuntimeObserver,
+    /// How deep the sub-agent is nested
+    depth: usize,
+}
+
+impl SubAgentObserver {
+    /// Create a new [`SubAgentObserver`] for a sub-agent nested `depth` deep
+    #[must_use]
+    pub const fn new(parent: WeakRuntimeObserver, depth: usize) -> Self {
+        Self { parent, depth }
+    }
+
+    async fn forward(&self, event: SubAgentEvent) {
+        if let Some(parent) = self.parent.upgrade() {
+            parent
+                .lock()
+                .await
+                .on_sub_agent(SubAgentNotification {
+                    depth: self.depth,
+                    event,
+                })
+                .await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RuntimeObserver for SubAgentObserver {
+    async fn on_task(&mut self, task: &str) {
+        self.forward(SubAgentEvent::Task(task.to_string())).await;
+    }
+
+    async fn on_start(&mut self, context: ContextDump) {
+        self.forward(SubAgentEvent::Start(context)).await;
+    }
+
+    async fn on_model_update(&mut self, event: ModelNotification) {
+        self.forward(SubAgentEvent::ModelUpdate(event)).await;
+    }
+
+    async fn on_message(&mut self, event: MessageNotification) {
+        self.forward(SubAgentEvent::Message(event)).await;
+    }
+
+    async fn on_invocation_result(&mut self, event: InvocationResultNotification) {
+        self.forward(SubAgentEvent::InvocationResult(event)).await;
+    }
+
+    async fn on_termination(&mut self, event: TerminationNotification) {
+        self.forward(SubAgentEvent::Termination(event)).await;
+    }
+
+    async fn on_loop(&mut self, event: LoopNotification) {
+        self.forward(SubAgentEvent::Loop(event)).await;
+    }
+
+    /// The deeper sub-agents keep their depth
+    async fn on_sub_agent(&mut self, event: SubAgentNotification) {
+        if let Some(parent) = self.parent.upgrade() {
+            parent.lock().await.on_sub_agent(event).await;
+        }
+    }
+}
+
 /// A step in the task
 pub struct Step {
     /// The actual task chain
     task_chain: Box<dyn Chain>,
+    /// The toolbox of the task - for the questions to the user
+    toolbox: Toolbox,
     /// The observer
     observer: WeakRuntimeObserver,
+    /// Cancels, pauses and resumes the task
+    control: ControlHandle,
+    /// When the task times out - and its maximum duration
+    deadline: Option<(tokio::time::Instant, Duration)>,
 }
 
 impl Step {
     /// Run the task for a single step
+    ///
+    /// Waits while the task is paused. The step is interrupted if the task is
+    /// cancelled or times out.
     async fn step(mut self) -> Result<TaskState, Error> {
-        let termination_messages = self.task_chain.step().await?;
+        let control = self.control.clone();
+        let deadline = self.deadline;
+
+        let step = async {
+            control.wait_while_paused().await;
+            self.task_chain.step().await
+        };
+
+        let termination_messages = tokio::select! {
+            biased;
+            // the in-flight tool invocations are dropped with the step
+            e = interruption(&control, deadline) => return Err(e),
+            termination_messages = step => termination_messages?,
+        };
 
         // check if the task is done
         if !termination_messages.is_empty() {
@@ -339,6 +639,17 @@ impl Step {
             return Ok(TaskState::Stop {
                 stop: Stop {
                     termination_messages,
+                    context: self.task_chain.dump(),
+                },
+            });
+        }
+
+        // wait for the answer to the question asked by a tool
+        if let Some(question) = self.toolbox.take_question().await {
+            return Ok(TaskState::AwaitingInput {
+                awaiting: AwaitingInput {
+                    question,
+                    step: self,
                 },
             });
         }
@@ -347,10 +658,74 @@ impl Step {
     }
 }
 
+/// Resolves with the error interrupting a task once it is cancelled or times
+/// out
+async fn interruption(
+    control: &ControlHandle,
+    deadline: Option<(tokio::time::Instant, Duration)>,
+) -> Error {
+    let timeout = async {
+        match deadline {
+            Some((deadline, _)) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        biased;
+        () = control.cancelled() => Error::Cancelled,
+        () = timeout => Error::TimedOut(deadline.map(|(_, d)| d).unwrap_or_default()),
+    }
+}
+
+/// The task is waiting for the answer of the user to a question - see
+/// [`Toolbox::ask_user`]
+///
+/// The task keeps running while it waits: it still times out after
+/// [`SapiensConfig::max_duration`] - see [`AwaitingInput::wait_for_answer`].
+pub struct AwaitingInput {
+    /// The question
+    pub question: String,
+    /// The step to resume
+    step: Step,
+}
+
+impl AwaitingInput {
+    /// Resume the task with the `answer` to the question
+    #[must_use]
+    pub fn answer(mut self, answer: &str) -> TaskState {
+        self.step.task_chain.answer(answer);
+
+        TaskState::Step { step: self.step }
+    }
+
+    /// Wait for the `answer` to the question and resume the task with it
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Cancelled`] or [`Error::TimedOut`] if the task is cancelled or
+    /// times out before the answer.
+    pub async fn wait_for_answer(
+        self,
+        answer: impl std::future::Future<Output = String> + Send,
+    ) -> Result<TaskState, Error> {
+        let answer = tokio::select! {
+            biased;
+            e = interruption(&self.step.control, self.step.deadline) => return Err(e),
+            answer = answer => answer,
+        };
+
+        Ok(self.answer(&answer))
+    }
+}
+
 /// The task is done
 pub struct Stop {
     /// The termination messages
     pub termination_messages: Vec<TerminationMessage>,
+    /// The context of the task when it was done - e.g. to reflect on it with
+    /// [`chains::reflexion::reflect`]
+    pub context: ContextDump,
 }
 
 /// The state machine of a task
@@ -360,6 +735,11 @@ pub enum TaskState {
         /// The actual step task
         step: Step,
     },
+    /// The task is waiting for the answer of the user
+    AwaitingInput {
+        /// The question and the task to resume
+        awaiting: AwaitingInput,
+    },
     /// The task is done
     Stop {
         /// the actual stopped task
@@ -383,6 +763,9 @@ impl TaskState {
     /// completed - either successfully or not. The `observer` will be called
     /// with the latest chat history element. It is also called on error.
     ///
+    /// The task is cancelled with the invocations of the `toolbox` - see
+    /// [`Toolbox::cancel_invocations`].
+    ///
     /// # Errors
     ///
     /// If the chain cannot be created, an error is returned.
@@ -392,24 +775,46 @@ impl TaskState {
         task: String,
         observer: WeakRuntimeObserver,
     ) -> Result<Self, Error> {
+        let control = ControlHandle::with_cancellation(toolbox.cancellation().child_token());
+
+        Self::with_control(config, toolbox, task, observer, control).await
+    }
+
+    /// Create a new [`TaskState`] for a `task` controlled with `control`
+    ///
+    /// See [`TaskState::with_observer`]. The `control` is checked between the
+    /// steps; cancelling it interrupts the in-flight step - including the
+    /// model and the tool calls - and the task fails with
+    /// [`Error::Cancelled`]. The task fails with [`Error::TimedOut`] once
+    /// [`SapiensConfig::max_duration`] has elapsed.
+    ///
+    /// # Errors
+    ///
+    /// If the chain cannot be created, an error is returned.
+    pub async fn with_control(
+        config: SapiensConfig,
+        toolbox: Toolbox,
+        task: String,
+        observer: WeakRuntimeObserver,
+        control: ControlHandle,
+    ) -> Result<Self, Error> {
+        let factory = config.chains.get(&config.chain)?;
+
         if let Some(observer) = observer.upgrade() {
             observer.lock().await.on_task(&task).await;
         }
 
-        let task_chain = match config.chain_type {
-            ChainType::SingleStepOODA => {
-                let chain = SingleStepOODAChain::new(config, toolbox, observer.clone())
-                    .await?
-                    .with_task(task);
-                Box::new(chain) as Box<dyn Chain>
-            }
-            ChainType::MultiStepOODA => {
-                let chain = MultiStepOODAChain::new(config, toolbox, observer.clone())
-                    .await?
-                    .with_task(task);
-                Box::new(chain) as Box<dyn Chain>
-            }
-        };
+        let deadline = config
+            .max_duration
+            .map(|d| (tokio::time::Instant::now() + d, d));
+
+        // the tools are cancelled with the task - and its artifacts are its own
+        let toolbox = toolbox.scoped(control.cancellation());
+        let step_toolbox = toolbox.clone();
+
+        let task_chain = factory
+            .create(config, toolbox, observer.clone(), task)
+            .await?;
 
         // call the observer
         if let Some(observer) = observer.upgrade() {
@@ -419,18 +824,27 @@ impl TaskState {
         Ok(Self::Step {
             step: Step {
                 task_chain,
+                toolbox: step_toolbox,
                 observer,
+                control,
+                deadline,
             },
         })
     }
 
     /// Run the task until it is done
+    ///
+    /// Fails with [`Error::AwaitingInput`] if a question is asked to the user
+    /// - use [`TaskState::step`] to answer it.
     pub async fn run(mut self) -> Result<Stop, Error> {
         loop {
             match self {
                 Self::Step { step } => {
                     self = step.step().await?;
                 }
+                Self::AwaitingInput { awaiting } => {
+                    return Err(Error::AwaitingInput(awaiting.question));
+                }
                 Self::Stop { stop } => {
                     return Ok(stop);
                 }
@@ -439,9 +853,13 @@ impl TaskState {
     }
 
     /// Run the task for a single step
+    ///
+    /// A task waiting for the answer of the user stays as it is - see
+    /// [`AwaitingInput::answer`].
     pub async fn step(self) -> Result<Self, Error> {
         match self {
             Self::Step { step } => step.step().await,
+            Self::AwaitingInput { awaiting } => Ok(Self::AwaitingInput { awaiting }),
             Self::Stop { stop } => Ok(Self::Stop { stop }),
         }
     }
@@ -450,10 +868,19 @@ impl TaskState {
     #[must_use]
     pub fn is_done(&self) -> Option<Vec<TerminationMessage>> {
         match self {
-            Self::Step { step: _ } => None,
+            Self::Step { step: _ } | Self::AwaitingInput { awaiting: _ } => None,
             Self::Stop { stop } => Some(stop.termination_messages.clone()),
         }
     }
+
+    /// The question the task is waiting for the answer to - if any
+    #[must_use]
+    pub fn question(&self) -> Option<&str> {
+        match self {
+            Self::AwaitingInput { awaiting } => Some(&awaiting.question),
+            _ => None,
+        }
+    }
 }
 
 /// Run until the task is done or the maximum number of steps is reached
@@ -5,10 +5,12 @@ pub mod vertex_ai;
 use std::fmt::{Debug, Display};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
 use crate::context::ChatEntry;
+use crate::rate_limit::{RateLimit, TokenBucket};
 
 /// A model reference
 pub type ModelRef = Arc<Box<dyn Model>>;
@@ -34,6 +36,9 @@ pub enum Error {
     /// Ollama error
     #[error("Ollama error: {0}")]
     OllamaError(#[from] ollama_rs::error::OllamaError),
+    /// The rate limit of the model is exhausted
+    #[error("Rate limit exhausted - retry in {}s", .0.as_secs_f32())]
+    RateLimited(Duration),
 }
 
 /// Roles in the conversation
@@ -101,6 +106,47 @@ pub trait Model: ChatEntryTokenNumber + Send + Sync {
     ) -> Result<ModelResponse, Error>;
 }
 
+/// A model whose queries are limited by a [`TokenBucket`]
+struct RateLimitedModel {
+    model: ModelRef,
+    bucket: TokenBucket,
+}
+
+#[async_trait::async_trait]
+impl ChatEntryTokenNumber for RateLimitedModel {
+    async fn num_tokens(&self, input: ChatInput) -> usize {
+        self.model.num_tokens(input).await
+    }
+
+    async fn context_size(&self) -> usize {
+        self.model.context_size().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Model for RateLimitedModel {
+    async fn query(
+        &self,
+        input: ChatInput,
+        max_tokens: Option<usize>,
+    ) -> Result<ModelResponse, Error> {
+        self.bucket.acquire().await.map_err(Error::RateLimited)?;
+        self.model.query(input, max_tokens).await
+    }
+}
+
+/// Limit the queries to a model
+///
+/// The limit is shared by all the users of the returned [`ModelRef`] - e.g.
+/// the concurrent tasks of a bot.
+#[must_use]
+pub fn rate_limited(model: ModelRef, limit: RateLimit) -> ModelRef {
+    Arc::new(Box::new(RateLimitedModel {
+        model,
+        bucket: TokenBucket::new(limit),
+    }))
+}
+
 /// Response from a language model
 #[derive(Clone)]
 pub struct ModelResponse {
@@ -1,12 +1,21 @@
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 
+use crate::chains::plan::Plan;
 use crate::context::{ChatEntry, ChatHistory};
 use crate::models::Role;
 use crate::tools::invocation::Error;
-use crate::tools::toolbox::Toolbox;
+use crate::tools::toolbox::{InvocationMode, Toolbox};
 use crate::tools::{ToolDescription, ToolUseError};
 
+const MULTIPLE_ACTIONS_NOTE: &str = r"
+# Several Actions at once
+
+Notes:
+- Instead of a single Action, you can give a YAML list of Actions - each with its `tool_name` and `parameters` fields - in the same YAML block.
+- All the Actions are executed in order and you will get the response of each of them.
+";
+
 // FUTURE(ssoudan) prompt a-la: "below are a series of dialogues between..." for
 // non-instruct models
 /// Prompt manager
@@ -58,16 +67,50 @@ impl Manager {
         prefix + &tool_desc
     }
 
+    /// Create the prompt describing how several actions are handled - if
+    /// enabled
+    async fn create_invocation_mode_note(&self) -> String {
+        match self.toolbox.invocation_mode().await {
+            InvocationMode::FirstOnly => String::new(),
+            InvocationMode::All {
+                concurrent,
+                stop_on_error,
+            } => {
+                let mut note = MULTIPLE_ACTIONS_NOTE.to_string();
+                if concurrent {
+                    note.push_str("- Actions using Tools without side effects might be executed concurrently. Do not rely on their order.\n");
+                }
+                if stop_on_error {
+                    note.push_str("- The execution stops at the first Action that fails.\n");
+                }
+                note
+            }
+        }
+    }
+
     /// Create the prompt describing the tools and how to use them
     async fn create_tool_warm_up(&self) -> String {
         let tool_prompt = self.create_tool_description().await;
+        let invocation_mode_note = self.create_invocation_mode_note().await;
 
-        format!("{}{}{}", self.prefix, self.response_format, tool_prompt)
+        format!(
+            "{}{}{}{}",
+            self.prefix, self.response_format, invocation_mode_note, tool_prompt
+        )
     }
 
     /// Create the prompt for the task
     pub(crate) fn build_task_prompt(&self, task: &str) -> Task {
-        let prompt = format!("# Your turn\nOriginal question: {}\n{}", task, self.prompt,);
+        self.build_task_prompt_with_notes(task, "")
+    }
+
+    /// Create the prompt for the task - with `notes` to repeat at each step,
+    /// e.g. the reflections on the previous attempts
+    pub(crate) fn build_task_prompt_with_notes(&self, task: &str, notes: &str) -> Task {
+        let prompt = format!(
+            "# Your turn\nOriginal question: {}\n{}{}",
+            task, notes, self.prompt,
+        );
         Task {
             task: task.to_string(),
             prompt,
@@ -126,6 +169,67 @@ impl Task {
         self.prompt.clone()
     }
 
+    /// Create the notes recalling the reflections on the previous attempts
+    pub(crate) fn reflections_notes(reflections: &[String]) -> String {
+        if reflections.is_empty() {
+            return String::new();
+        }
+
+        let reflections = reflections
+            .iter()
+            .map(|r| format!("- {}", r.trim()))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        format!("# Reflections on your previous attempts at this task:\n{reflections}\n")
+    }
+
+    /// Create the notes repeating the plan of the task
+    pub(crate) fn plan_notes(plan: &Plan) -> String {
+        format!("# Your plan - work on the first sub-goal neither done nor failed:\n{plan}")
+    }
+
+    /// Create the notes of a Tree of Thoughts search - the thoughts of the
+    /// explored branch and the candidates already proposed for its next step
+    pub(crate) fn thoughts_notes(
+        thoughts: &[String],
+        proposed: &[String],
+        must_act: bool,
+    ) -> String {
+        let mut notes = String::new();
+
+        if !thoughts.is_empty() {
+            notes.push_str("# Your thoughts so far:\n");
+            for thought in thoughts {
+                notes.push_str(thought.trim());
+                notes.push('\n');
+            }
+        }
+
+        if !proposed.is_empty() {
+            notes.push_str("# Already proposed - propose something different:\n");
+            for candidate in proposed {
+                notes.push_str("- ");
+                notes.push_str(candidate.trim());
+                notes.push('\n');
+            }
+        }
+
+        if must_act {
+            notes.push_str("# You must now give an Action.\n");
+        }
+
+        notes
+    }
+
+    /// Create the prompt to revise a rejected action
+    pub(crate) fn critique_prompt(critique: &str) -> String {
+        format!(
+            "# Your Action was not approved by the critic:\n{}\nRevise it.",
+            critique.trim()
+        )
+    }
+
     /// Create the prompt to react to an action failure
     pub(crate) fn action_failed_prompt(tool_name: impl AsRef<str>, e: &ToolUseError) -> String {
         format!(
@@ -162,6 +266,31 @@ impl Task {
             )
         }
     }
+
+    /// Create the prompt to warn about the violations of the invocation policy
+    pub(crate) fn invocation_warnings_prompt(warnings: &[impl fmt::Display]) -> String {
+        let warnings = warnings
+            .iter()
+            .map(|w| format!("- {w}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        format!("# Warnings:\n{warnings}\nFix this in your next response.")
+    }
+
+    /// Create the prompt introducing the responses of several actions
+    pub(crate) fn multiple_actions_prompt(
+        executed_invocation_count: usize,
+        available_invocation_count: usize,
+    ) -> String {
+        if executed_invocation_count == available_invocation_count {
+            format!("# Responses of the {available_invocation_count} Actions:")
+        } else {
+            format!(
+                "# Responses of the Actions:\nOnly {executed_invocation_count} of the {available_invocation_count} Actions were executed. The execution stopped at the first failure."
+            )
+        }
+    }
 }
 
 impl fmt::Display for Task {
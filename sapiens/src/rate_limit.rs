@@ -0,0 +1,276 @@
+//! Token-bucket rate limits
+//!
+//! Used to limit the invocations of the tools - see
+//! [`crate::tools::toolbox::Toolbox::set_rate_limit`] - and the queries to the
+//! models - see [`crate::models::rate_limited`].
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+/// Invalid [`RateLimit`]
+#[derive(Debug, thiserror::Error, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The capacity is zero
+    #[error("The capacity must be positive")]
+    ZeroCapacity,
+    /// The period is zero
+    #[error("The period must be positive")]
+    ZeroPeriod,
+}
+
+/// What to do when a [`RateLimit`] is exhausted
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnExhausted {
+    /// Wait for a token
+    #[default]
+    Wait,
+    /// Fail immediately
+    Fail,
+}
+
+/// At most `capacity` calls per `period_secs` - in bursts of up to `capacity`
+/// calls
+///
+/// In YAML: `{capacity: 100, period_secs: 86400, on_exhausted: fail}`. As a
+/// string: `100/86400` or `100/86400:fail`. See [`RateLimit::validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawRateLimit")]
+pub struct RateLimit {
+    /// Number of calls per period - and size of the bursts
+    pub capacity: u32,
+    /// Duration of the period - in seconds
+    pub period_secs: u64,
+    /// What to do when the limit is exhausted
+    pub on_exhausted: OnExhausted,
+}
+
+/// A [`RateLimit`] before its validation
+#[derive(Deserialize)]
+struct RawRateLimit {
+    capacity: u32,
+    period_secs: u64,
+    #[serde(default)]
+    on_exhausted: OnExhausted,
+}
+
+impl TryFrom<RawRateLimit> for RateLimit {
+    type Error = Error;
+
+    fn try_from(raw: RawRateLimit) -> Result<Self, Self::Error> {
+        let limit = Self {
+            capacity: raw.capacity,
+            period_secs: raw.period_secs,
+            on_exhausted: raw.on_exhausted,
+        };
+        limit.validate()?;
+        Ok(limit)
+    }
+}
+
+impl RateLimit {
+    /// Check that the capacity and the period are positive
+    ///
+    /// # Errors
+    ///
+    /// If one of them is zero.
+    pub const fn validate(&self) -> Result<(), Error> {
+        if self.capacity == 0 {
+            return Err(Error::ZeroCapacity);
+        }
+        if self.period_secs == 0 {
+            return Err(Error::ZeroPeriod);
+        }
+        Ok(())
+    }
+
+    /// Number of tokens added per second
+    fn rate(&self) -> f64 {
+        f64::from(self.capacity) / self.period_secs as f64
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (limit, on_exhausted) = match s.split_once(':') {
+            Some((limit, "wait")) => (limit, OnExhausted::Wait),
+            Some((limit, "fail")) => (limit, OnExhausted::Fail),
+            Some((_, other)) => return Err(format!("Unknown behavior when exhausted: {other}")),
+            None => (s, OnExhausted::Wait),
+        };
+
+        let (capacity, period_secs) = limit
+            .split_once('/')
+            .ok_or_else(|| format!("Expected <capacity>/<period_secs>: {s}"))?;
+
+        let capacity = capacity
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid capacity: {e}"))?;
+        let period_secs = period_secs
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid period: {e}"))?;
+
+        let limit = Self {
+            capacity,
+            period_secs,
+            on_exhausted,
+        };
+        limit.validate().map_err(|e| e.to_string())?;
+        Ok(limit)
+    }
+}
+
+/// The tokens available in a [`TokenBucket`]
+#[derive(Debug)]
+struct Tokens {
+    /// Negative when tokens have been promised to waiting callers
+    available: f64,
+    /// When `available` was last updated
+    updated_at: Instant,
+}
+
+/// Enforces a [`RateLimit`]
+///
+/// Share it - e.g. in an `Arc` - to share the limit between concurrent
+/// callers.
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: Mutex<Tokens>,
+}
+
+impl TokenBucket {
+    /// Create a full bucket
+    #[must_use]
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: Mutex::new(Tokens {
+                available: f64::from(limit.capacity),
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// The limit enforced by the bucket
+    #[must_use]
+    pub const fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Take a token - waiting for it if the limit is exhausted, unless it is
+    /// configured to fail
+    ///
+    /// Fails with how long to wait for the next token.
+    pub async fn acquire(&self) -> Result<(), Duration> {
+        let wait = {
+            let mut tokens = self.tokens.lock().await;
+
+            let now = Instant::now();
+            let elapsed = now.duration_since(tokens.updated_at).as_secs_f64();
+            tokens.available = self
+                .limit
+                .rate()
+                .mul_add(elapsed, tokens.available)
+                .min(f64::from(self.limit.capacity));
+            tokens.updated_at = now;
+
+            if tokens.available >= 1. {
+                tokens.available -= 1.;
+                return Ok(());
+            }
+
+            let wait = Duration::from_secs_f64((1. - tokens.available) / self.limit.rate());
+            if self.limit.on_exhausted == OnExhausted::Fail {
+                return Err(wait);
+            }
+
+            // the token is promised - the next callers wait longer
+            tokens.available -= 1.;
+            wait
+        };
+
+        tokio::time::sleep(wait).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_are_parsed() {
+        assert_eq!(
+            "100/86400".parse(),
+            Ok(RateLimit {
+                capacity: 100,
+                period_secs: 86400,
+                on_exhausted: OnExhausted::Wait,
+            })
+        );
+        assert_eq!(
+            "5/1:fail".parse(),
+            Ok(RateLimit {
+                capacity: 5,
+                period_secs: 1,
+                on_exhausted: OnExhausted::Fail,
+            })
+        );
+        assert!("5".parse::<RateLimit>().is_err());
+        assert!("0/1".parse::<RateLimit>().is_err());
+        assert!("5/0".parse::<RateLimit>().is_err());
+        assert!("5/1:retry".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn invalid_limits_are_not_deserialized() {
+        assert_eq!(
+            serde_yaml::from_str::<RateLimit>("{capacity: 5, period_secs: 1}").unwrap(),
+            RateLimit {
+                capacity: 5,
+                period_secs: 1,
+                on_exhausted: OnExhausted::Wait,
+            }
+        );
+        assert!(serde_yaml::from_str::<RateLimit>("{capacity: 0, period_secs: 1}").is_err());
+        assert!(serde_yaml::from_str::<RateLimit>("{capacity: 5, period_secs: 0}").is_err());
+    }
+
+    #[tokio::test]
+    async fn exhausted_buckets_fail_fast() {
+        let bucket = TokenBucket::new("2/100:fail".parse().unwrap());
+
+        assert_eq!(bucket.acquire().await, Ok(()));
+        assert_eq!(bucket.acquire().await, Ok(()));
+
+        let retry_after = bucket.acquire().await.unwrap_err();
+        assert!(retry_after > Duration::from_secs(40));
+        assert!(retry_after <= Duration::from_secs(50));
+    }
+
+    #[tokio::test]
+    async fn exhausted_buckets_wait_for_a_token() {
+        // a token every 50ms
+        let bucket = TokenBucket::new(RateLimit {
+            capacity: 20,
+            period_secs: 1,
+            on_exhausted: OnExhausted::Wait,
+        });
+
+        for _ in 0..20 {
+            bucket.acquire().await.unwrap();
+        }
+
+        let started = Instant::now();
+        bucket.acquire().await.unwrap();
+        bucket.acquire().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+/// An invocation waiting for an approval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    /// The name of the tool
+    pub tool_name: String,
+    /// The parameters of the invocation
+    pub parameters: serde_yaml::Value,
+}
+
+/// The decision of an [`ApprovalHandler`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Approval {
+    /// The invocation can proceed
+    Approved,
+    /// The invocation must not proceed - the reason is fed back to the model
+    Rejected {
+        /// Why the invocation was rejected
+        reason: String,
+    },
+    /// The invocation can proceed with other parameters
+    Edited {
+        /// The parameters to use instead
+        parameters: serde_yaml::Value,
+    },
+}
+
+/// Decides if the invocations of the tools requiring an approval can proceed
+///
+/// See [`crate::tools::toolbox::Toolbox::set_approval_handler`].
+#[async_trait::async_trait]
+pub trait ApprovalHandler: Send + Sync {
+    /// Review an invocation before it is executed
+    async fn review(&self, request: ApprovalRequest) -> Approval;
+}
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+/// Name of the tool expected to read the artifacts - see
+/// `sapiens_tools::artifact::ReadArtifactTool`
+pub const READER_TOOL_NAME: &str = "ReadArtifact";
+
+/// Error while reading an artifact
+#[derive(Debug, thiserror::Error, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Error {
+    /// The artifact does not exist
+    #[error("Artifact not found: {0}")]
+    NotFound(String),
+    /// The page does not exist
+    #[error("Page {page} of artifact {artifact_id} does not exist. It has {page_count} pages.")]
+    PageOutOfRange {
+        /// The artifact
+        artifact_id: String,
+        /// The requested page - 1-based
+        page: usize,
+        /// The number of pages of the artifact
+        page_count: usize,
+    },
+}
+
+/// When the results of the tools become artifacts and how they are paged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactPolicy {
+    /// Results longer than this - in chars - are stored as artifacts
+    pub max_result_chars: usize,
+    /// Number of chars of the result kept in the preview
+    pub preview_chars: usize,
+    /// Number of chars in a page of an artifact
+    pub page_chars: usize,
+}
+
+impl Default for ArtifactPolicy {
+    fn default() -> Self {
+        Self {
+            max_result_chars: 1536,
+            preview_chars: 512,
+            page_chars: 1024,
+        }
+    }
+}
+
+/// A tool result stored out of the chat history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    /// The handle of the artifact
+    pub id: String,
+    /// The tool that produced it
+    pub tool_name: String,
+    /// The full result
+    pub content: String,
+}
+
+/// A part of an [`Artifact`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ArtifactPage {
+    /// The handle of the artifact
+    pub artifact_id: String,
+    /// Offset of the first char of the page
+    pub start: usize,
+    /// Number of chars of the artifact
+    pub total_chars: usize,
+    /// The content of the page
+    pub content: String,
+}
+
+/// The artifacts of a task
+///
+/// Cloning an [`ArtifactStore`] gives another handle to the same artifacts.
+/// Each task gets its own artifacts with [`ArtifactStore::for_task`].
+#[derive(Debug, Default, Clone)]
+pub struct ArtifactStore {
+    artifacts: Arc<RwLock<Vec<Artifact>>>,
+    /// Shared by the stores of all the tasks
+    policy: Arc<RwLock<ArtifactPolicy>>,
+}
+
+impl ArtifactStore {
+    /// Get an empty store sharing the [`ArtifactPolicy`] of this one - for a
+    /// new task
+    #[must_use]
+    pub fn for_task(&self) -> Self {
+        Self {
+            artifacts: Arc::default(),
+            policy: self.policy.clone(),
+        }
+    }
+
+    /// Set the [`ArtifactPolicy`] - of this store and of the ones of the
+    /// tasks
+    pub async fn set_policy(&self, policy: ArtifactPolicy) {
+        *self.policy.write().await = policy;
+    }
+
+    /// Get the [`ArtifactPolicy`]
+    pub async fn policy(&self) -> ArtifactPolicy {
+        *self.policy.read().await
+    }
+
+    /// Store `content` produced by `tool_name` and return the handle of the
+    /// new artifact
+    pub async fn add(&self, tool_name: impl Into<String>, content: impl Into<String>) -> String {
+        let mut artifacts = self.artifacts.write().await;
+        let id = format!("artifact-{}", artifacts.len() + 1);
+        artifacts.push(Artifact {
+            id: id.clone(),
+            tool_name: tool_name.into(),
+            content: content.into(),
+        });
+        id
+    }
+
+    /// Get an artifact
+    pub async fn get(&self, artifact_id: &str) -> Option<Artifact> {
+        self.artifacts
+            .read()
+            .await
+            .iter()
+            .find(|a| a.id == artifact_id)
+            .cloned()
+    }
+
+    /// Number of artifacts
+    pub async fn len(&self) -> usize {
+        self.artifacts.read().await.len()
+    }
+
+    /// Check if there is no artifact
+    pub async fn is_empty(&self) -> bool {
+        self.artifacts.read().await.is_empty()
+    }
+
+    /// Read `length` chars of an artifact starting at the char `start`
+    ///
+    /// `length` is capped to the size of a page.
+    ///
+    /// # Errors
+    ///
+    /// If the artifact does not exist.
+    pub async fn read_range(
+        &self,
+        artifact_id: &str,
+        start: usize,
+        length: usize,
+    ) -> Result<ArtifactPage, Error> {
+        let page_chars = self.policy().await.page_chars.max(1);
+        let artifact = self
+            .get(artifact_id)
+            .await
+            .ok_or_else(|| Error::NotFound(artifact_id.to_string()))?;
+
+        Ok(ArtifactPage {
+            artifact_id: artifact.id,
+            start,
+            total_chars: artifact.content.chars().count(),
+            content: artifact
+                .content
+                .chars()
+                .skip(start)
+                .take(length.min(page_chars))
+                .collect(),
+        })
+    }
+
+    /// Read the `page` of an artifact - 1-based
+    ///
+    /// # Errors
+    ///
+    /// If the artifact or the page does not exist.
+    pub async fn read_page(&self, artifact_id: &str, page: usize) -> Result<ArtifactPage, Error> {
+        let page_chars = self.policy().await.page_chars.max(1);
+        let page_count = self.page_count(artifact_id).await?;
+
+        if page == 0 || page > page_count {
+            return Err(Error::PageOutOfRange {
+                artifact_id: artifact_id.to_string(),
+                page,
+                page_count,
+            });
+        }
+
+        self.read_range(artifact_id, (page - 1) * page_chars, page_chars)
+            .await
+    }
+
+    /// Number of pages of an artifact
+    ///
+    /// # Errors
+    ///
+    /// If the artifact does not exist.
+    pub async fn page_count(&self, artifact_id: &str) -> Result<usize, Error> {
+        let page_chars = self.policy().await.page_chars.max(1);
+        let artifact = self
+            .get(artifact_id)
+            .await
+            .ok_or_else(|| Error::NotFound(artifact_id.to_string()))?;
+
+        Ok(artifact.content.chars().count().div_ceil(page_chars).max(1))
+    }
+
+    /// Store the `result` of `tool_name` as an artifact if it is too long
+    /// and replace it with a preview and the handle of the artifact
+    pub async fn shrink(&self, tool_name: &str, result: String) -> String {
+        let policy = self.policy().await;
+
+        let total_chars = result.chars().count();
+        if total_chars <= policy.max_result_chars {
+            return result;
+        }
+
+        let preview = result
+            .chars()
+            .take(policy.preview_chars)
+            .collect::<String>();
+        let artifact_id = self.add(tool_name, result).await;
+        let page_chars = policy.page_chars.max(1);
+        let page_count = total_chars.div_ceil(page_chars);
+
+        format!(
+            "# The response is too long ({total_chars} chars). It is stored as the artifact `{artifact_id}` ({page_count} pages of {page_chars} chars).\n# Use the {READER_TOOL_NAME} Tool - also available from SandboxedPython - to read it.\n# Preview:\n{preview}\n# [...]\n"
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_policy() -> ArtifactPolicy {
+        ArtifactPolicy {
+            max_result_chars: 10,
+            preview_chars: 4,
+            page_chars: 6,
+        }
+    }
+
+    #[tokio::test]
+    async fn short_results_are_kept() {
+        let store = ArtifactStore::default();
+        store.set_policy(small_policy()).await;
+
+        let result = store.shrink("Wikipedia", "short".to_string()).await;
+
+        assert_eq!(result, "short");
+        assert!(store.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn long_results_become_artifacts() {
+        let store = ArtifactStore::default();
+        store.set_policy(small_policy()).await;
+
+        let result = store
+            .shrink("Wikipedia", "abcdefghijklmnopq".to_string())
+            .await;
+
+        insta::assert_snapshot!(result);
+
+        let artifact = store.get("artifact-1").await.unwrap();
+        assert_eq!(artifact.tool_name, "Wikipedia");
+        assert_eq!(artifact.content, "abcdefghijklmnopq");
+    }
+
+    #[tokio::test]
+    async fn artifacts_are_paged() {
+        let store = ArtifactStore::default();
+        store.set_policy(small_policy()).await;
+        let id = store.add("Wikidata", "abcdefghijklmnopq").await;
+
+        assert_eq!(store.page_count(&id).await, Ok(3));
+        assert_eq!(store.read_page(&id, 1).await.unwrap().content, "abcdef");
+        assert_eq!(store.read_page(&id, 3).await.unwrap().content, "mnopq");
+        assert_eq!(
+            store.read_page(&id, 4).await,
+            Err(Error::PageOutOfRange {
+                artifact_id: id.clone(),
+                page: 4,
+                page_count: 3,
+            })
+        );
+
+        // ranges are capped to a page
+        let range = store.read_range(&id, 2, 100).await.unwrap();
+        assert_eq!(range.content, "cdefgh");
+        assert_eq!(range.total_chars, 17);
+
+        assert_eq!(
+            store.read_page("artifact-42", 1).await,
+            Err(Error::NotFound("artifact-42".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_pages_are_one_char_long() {
+        let store = ArtifactStore::default();
+        store
+            .set_policy(ArtifactPolicy {
+                page_chars: 0,
+                ..small_policy()
+            })
+            .await;
+        let id = store.add("Wikidata", "abc").await;
+
+        assert_eq!(store.page_count(&id).await, Ok(3));
+        assert_eq!(store.read_page(&id, 2).await.unwrap().content, "b");
+        assert_eq!(store.read_range(&id, 0, 10).await.unwrap().content, "a");
+    }
+
+    #[tokio::test]
+    async fn tasks_have_their_own_artifacts() {
+        let store = ArtifactStore::default();
+        let task_1 = store.for_task();
+        let task_2 = store.for_task();
+
+        let id = task_1.add("Wikipedia", "abc").await;
+        assert_eq!(task_1.len().await, 1);
+        assert!(task_2.get(&id).await.is_none());
+        assert!(store.is_empty().await);
+
+        // the policy is shared
+        store.set_policy(small_policy()).await;
+        assert_eq!(task_2.policy().await, small_policy());
+    }
+}
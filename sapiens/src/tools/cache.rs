@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+use crate::tools::{Tool, ToolDescription, ToolUseError};
+
+/// Error while caching a tool
+#[derive(Debug, thiserror::Error, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Error {
+    /// The tool does not exist
+    #[error("Tool not found: {0}")]
+    NotFound(String),
+    /// The tool has side effects - a cached invocation would not execute them
+    #[error("Tool {0} has side effects and cannot be cached")]
+    SideEffects(String),
+}
+
+/// A result stored in a [`CacheBackend`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResult {
+    /// The result of the invocation
+    pub value: serde_yaml::Value,
+    /// When the result was stored - in milliseconds since the Unix epoch
+    pub stored_at: u64,
+    /// When the result expires - in milliseconds since the Unix epoch, never
+    /// if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl CachedResult {
+    /// A result stored now
+    #[must_use]
+    pub fn new(value: serde_yaml::Value) -> Self {
+        Self {
+            value,
+            stored_at: now(),
+            expires_at: None,
+        }
+    }
+
+    /// The same result expiring after `ttl`
+    #[must_use]
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            expires_at: Some(
+                self.stored_at
+                    .saturating_add(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX)),
+            ),
+            ..self
+        }
+    }
+
+    /// Check if the result has expired - see [`CachedResult::with_ttl`]
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| now() >= expires_at)
+    }
+}
+
+/// Whether an invocation was answered from the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheStatus {
+    /// The result was in the cache - the tool was not invoked
+    Hit,
+    /// The tool was invoked
+    Miss,
+}
+
+/// Milliseconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Where the results of the tools are cached
+///
+/// See [`crate::tools::toolbox::Toolbox::set_cache`].
+#[async_trait::async_trait]
+pub trait CacheBackend: Send + Sync {
+    /// Get the result stored for `key` - the expired results are ignored by
+    /// the callers
+    async fn get(&self, key: &str) -> Option<CachedResult>;
+
+    /// Store the result for `key`
+    async fn put(&self, key: &str, result: CachedResult);
+}
+
+/// The key of an invocation - the tool name and the parameters with the
+/// mappings sorted by key
+#[must_use]
+pub fn cache_key(tool_name: &str, input: &serde_yaml::Value) -> String {
+    let input = serde_yaml::to_string(&canonicalize(input)).unwrap_or_default();
+    format!("{tool_name}\n{input}")
+}
+
+/// The same value with the mappings sorted by key
+fn canonicalize(value: &serde_yaml::Value) -> serde_yaml::Value {
+    match value {
+        serde_yaml::Value::Mapping(m) => {
+            let mut entries = m
+                .iter()
+                .map(|(k, v)| {
+                    let sort_key = serde_yaml::to_string(k).unwrap_or_default();
+                    (sort_key, k.clone(), canonicalize(v))
+                })
+                .collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+            serde_yaml::Value::Mapping(entries.into_iter().map(|(_, k, v)| (k, v)).collect())
+        }
+        serde_yaml::Value::Sequence(items) => {
+            serde_yaml::Value::Sequence(items.iter().map(canonicalize).collect())
+        }
+        v => v.clone(),
+    }
+}
+
+/// Caches the results of the invocations of the tools in a [`CacheBackend`]
+/// for a `ttl`
+#[derive(Clone)]
+pub struct ResultCache {
+    backend: Arc<dyn CacheBackend>,
+    ttl: Duration,
+}
+
+impl ResultCache {
+    /// Create a new [`ResultCache`]
+    #[must_use]
+    pub fn new(backend: Arc<dyn CacheBackend>, ttl: Duration) -> Self {
+        Self { backend, ttl }
+    }
+
+    /// Get the result of an invocation of `tool_name` with `input` from the
+    /// cache - or from the `invocation` if it is not there or too old
+    ///
+    /// Only the successful results are cached. The `invocation` is not
+    /// awaited on a [`CacheStatus::Hit`].
+    pub async fn get_or_invoke(
+        &self,
+        tool_name: &str,
+        input: &serde_yaml::Value,
+        invocation: impl Future<Output = Result<serde_yaml::Value, ToolUseError>> + Send,
+    ) -> (Result<serde_yaml::Value, ToolUseError>, CacheStatus) {
+        let key = cache_key(tool_name, input);
+
+        let hit = self
+            .backend
+            .get(&key)
+            .await
+            .filter(|cached| !cached.is_expired());
+        if let Some(cached) = hit {
+            debug!(tool_name, "Cache hit");
+            return (Ok(cached.value), CacheStatus::Hit);
+        }
+        debug!(tool_name, "Cache miss");
+
+        let result = invocation.await;
+        if let Ok(value) = &result {
+            self.backend
+                .put(&key, CachedResult::new(value.clone()).with_ttl(self.ttl))
+                .await;
+        }
+
+        (result, CacheStatus::Miss)
+    }
+}
+
+/// A [`Tool`] whose results are cached - wraps any tool without side effects
+///
+/// The invocations answered from the cache do not reach the wrapped tool.
+/// Within a [`crate::tools::toolbox::Toolbox`], the tools can also be cached
+/// with [`crate::tools::toolbox::Toolbox::cache_tool`] - the hits and the
+/// misses are then counted in its [`crate::tools::toolbox::Stats`].
+pub struct CachedTool<T> {
+    tool: T,
+    cache: ResultCache,
+}
+
+impl<T: Tool> CachedTool<T> {
+    /// Cache the results of `tool` in `backend` for `ttl`
+    #[must_use]
+    pub fn new(tool: T, backend: Arc<dyn CacheBackend>, ttl: Duration) -> Self {
+        Self {
+            tool,
+            cache: ResultCache::new(backend, ttl),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: Tool> Tool for CachedTool<T> {
+    fn description(&self) -> ToolDescription {
+        self.tool.description()
+    }
+
+    fn is_side_effect_free(&self) -> bool {
+        self.tool.is_side_effect_free()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.tool.timeout()
+    }
+
+    fn requires_approval(&self) -> bool {
+        self.tool.requires_approval()
+    }
+
+    /// Already cached
+    fn cache_ttl(&self) -> Option<Duration> {
+        None
+    }
+
+    async fn invoke(&self, input: serde_yaml::Value) -> Result<serde_yaml::Value, ToolUseError> {
+        let tool_name = self.tool.description().name;
+        let (result, _) = self
+            .cache
+            .get_or_invoke(&tool_name, &input, self.tool.invoke(input.clone()))
+            .await;
+        result
+    }
+}
+
+/// A [`CacheBackend`] in memory - lost when the process stops
+///
+/// The expired results are evicted when a result is stored.
+#[derive(Default)]
+pub struct MemoryCache {
+    results: RwLock<HashMap<String, CachedResult>>,
+}
+
+impl MemoryCache {
+    /// Number of results stored
+    pub async fn len(&self) -> usize {
+        self.results.read().await.len()
+    }
+
+    /// Check if no result is stored
+    pub async fn is_empty(&self) -> bool {
+        self.results.read().await.is_empty()
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for MemoryCache {
+    async fn get(&self, key: &str) -> Option<CachedResult> {
+        self.results
+            .read()
+            .await
+            .get(key)
+            .filter(|result| !result.is_expired())
+            .cloned()
+    }
+
+    async fn put(&self, key: &str, result: CachedResult) {
+        let mut results = self.results.write().await;
+        results.retain(|_, result| !result.is_expired());
+        results.insert(key.to_string(), result);
+    }
+}
+
+/// A [`CacheBackend`] storing a YAML file per result in a directory - shared
+/// across the runs
+///
+/// As for [`MemoryCache`], the files of the expired results are removed when a
+/// result is stored.
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+/// The content of a file of a [`DiskCache`]
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    key: String,
+    #[serde(flatten)]
+    result: CachedResult,
+}
+
+impl DiskCache {
+    /// Create a cache in `dir` - created if needed
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// The file of a key
+    fn path(&self, key: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.dir.join(format!("{:016x}.yaml", hasher.finish()))
+    }
+
+    /// Remove the files of the expired results
+    async fn prune(&self) -> std::io::Result<()> {
+        let mut files = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            let path = file.path();
+            if path.extension().is_none_or(|ext| ext != "yaml") {
+                continue;
+            }
+
+            let Ok(content) = tokio::fs::read_to_string(&path).await else {
+                continue;
+            };
+            let expired = serde_yaml::from_str::<DiskEntry>(&content)
+                .is_ok_and(|entry| entry.result.is_expired());
+            if expired {
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for DiskCache {
+    async fn get(&self, key: &str) -> Option<CachedResult> {
+        let content = tokio::fs::read_to_string(self.path(key)).await.ok()?;
+        let entry: DiskEntry = serde_yaml::from_str(&content).ok()?;
+
+        // the hashes of different keys can collide
+        (entry.key == key && !entry.result.is_expired()).then_some(entry.result)
+    }
+
+    async fn put(&self, key: &str, result: CachedResult) {
+        if let Err(e) = self.prune().await {
+            warn!("Failed to prune the cache in {}: {}", self.dir.display(), e);
+        }
+
+        let entry = DiskEntry {
+            key: key.to_string(),
+            result,
+        };
+
+        let written = match serde_yaml::to_string(&entry) {
+            Ok(content) => tokio::fs::write(self.path(key), content)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        if let Err(e) = written {
+            warn!("Failed to cache a result in {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_do_not_depend_on_the_order_of_the_parameters() {
+        let a = serde_yaml::from_str("{query: rust, options: {limit: 3, sort: date}}").unwrap();
+        let b = serde_yaml::from_str("{options: {sort: date, limit: 3}, query: rust}").unwrap();
+        let c = serde_yaml::from_str("{options: {sort: date, limit: 4}, query: rust}").unwrap();
+
+        assert_eq!(cache_key("Arxiv", &a), cache_key("Arxiv", &b));
+        assert_ne!(cache_key("Arxiv", &a), cache_key("Arxiv", &c));
+        assert_ne!(cache_key("Arxiv", &a), cache_key("Wikipedia", &a));
+    }
+
+    #[test]
+    fn results_expire() {
+        let result = CachedResult::new(serde_yaml::Value::Null);
+        assert!(!result.is_expired());
+
+        assert!(!result.clone().with_ttl(Duration::from_mins(1)).is_expired());
+        assert!(!result
+            .clone()
+            .with_ttl(Duration::from_millis(500))
+            .is_expired());
+        assert!(result.clone().with_ttl(Duration::ZERO).is_expired());
+
+        let old = CachedResult {
+            stored_at: result.stored_at - 120_000,
+            ..result
+        };
+        assert!(old.with_ttl(Duration::from_mins(1)).is_expired());
+    }
+
+    #[tokio::test]
+    async fn expired_results_are_evicted_from_memory() {
+        let cache = MemoryCache::default();
+        let result = CachedResult::new(serde_yaml::Value::Null);
+
+        cache
+            .put("old", result.clone().with_ttl(Duration::ZERO))
+            .await;
+        assert_eq!(cache.get("old").await, None);
+        assert_eq!(cache.len().await, 1);
+
+        cache
+            .put("new", result.clone().with_ttl(Duration::from_mins(1)))
+            .await;
+        assert_eq!(cache.len().await, 1);
+        assert!(cache.get("new").await.is_some());
+    }
+
+    /// Counts its invocations
+    #[derive(Default)]
+    struct CountingTool {
+        invocations: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Tool for CountingTool {
+        fn description(&self) -> ToolDescription {
+            ToolDescription {
+                name: "Counting".to_string(),
+                description: "Count the invocations".to_string(),
+                parameters: crate::tools::Format::default(),
+                responses_content: crate::tools::Format::default(),
+            }
+        }
+
+        async fn invoke(
+            &self,
+            _input: serde_yaml::Value,
+        ) -> Result<serde_yaml::Value, ToolUseError> {
+            let n = self
+                .invocations
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(serde_yaml::Value::from(n))
+        }
+    }
+
+    #[tokio::test]
+    async fn any_tool_can_be_cached() {
+        let tool = CachedTool::new(
+            CountingTool::default(),
+            Arc::new(MemoryCache::default()),
+            Duration::from_mins(1),
+        );
+
+        let input = serde_yaml::from_str("{query: rust}").unwrap();
+        let first = tool.invoke(input).await.unwrap();
+        let input = serde_yaml::from_str("{query: rust}").unwrap();
+        assert_eq!(tool.invoke(input).await.unwrap(), first);
+        assert_eq!(
+            tool.tool
+                .invocations
+                .load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+        assert_eq!(tool.description().name, "Counting");
+    }
+
+    #[tokio::test]
+    async fn results_are_stored_on_disk() {
+        let dir = std::env::temp_dir().join(format!("sapiens-cache-{}", std::process::id()));
+        let key = cache_key("Arxiv", &serde_yaml::from_str("{query: rust}").unwrap());
+        let result = CachedResult::new(serde_yaml::from_str("{papers: []}").unwrap());
+
+        DiskCache::new(&dir)
+            .unwrap()
+            .put(&key, result.clone())
+            .await;
+
+        // from another instance
+        let cache = DiskCache::new(&dir).unwrap();
+        assert_eq!(cache.get(&key).await, Some(result));
+        assert_eq!(cache.get("Arxiv\nquery: go\n").await, None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_results_are_removed_from_disk() {
+        let dir = std::env::temp_dir().join(format!("sapiens-prune-{}", std::process::id()));
+        let cache = DiskCache::new(&dir).unwrap();
+        let result = CachedResult::new(serde_yaml::Value::Null);
+
+        cache
+            .put("old", result.clone().with_ttl(Duration::ZERO))
+            .await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        cache
+            .put("new", result.with_ttl(Duration::from_mins(1)))
+            .await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(cache.get("new").await.is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
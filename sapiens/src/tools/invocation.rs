@@ -17,8 +17,114 @@ pub enum Error {
     #[error("No valid Action found: {0}")]
     NoValidInvocationFound(String),
     /// Too many yaml blocks
-    #[error("Too many ({0}) yaml blocks. Only one is expected.")]
-    TooManyYamlBlocks(usize),
+    #[error("Too many ({count}) yaml blocks. At most {max} expected.")]
+    TooManyYamlBlocks {
+        /// Number of yaml blocks found
+        count: usize,
+        /// Maximum number of yaml blocks allowed
+        max: usize,
+    },
+    /// The invocation has unexpected top-level fields
+    #[error("The Action for {tool_name} cannot have fields: {}. Only `tool_name` and `parameters` are allowed.", fields.join(", "))]
+    UnexpectedFields {
+        /// The name of the tool
+        tool_name: String,
+        /// The unexpected fields
+        fields: Vec<String>,
+    },
+    /// The invoked tool does not exist
+    #[error("Tool not found: {tool_name}.{}", format_suggestions(suggestions))]
+    UnknownTool {
+        /// The name of the tool
+        tool_name: String,
+        /// The names of the closest existing tools
+        suggestions: Vec<String>,
+    },
+}
+
+fn format_suggestions(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(" Did you mean: {}?", suggestions.join(", "))
+    }
+}
+
+/// What to do with the unexpected top-level fields of an invocation
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExtraFieldsPolicy {
+    /// The invocation is rejected
+    Error,
+    /// The fields are dropped and a warning is fed back to the model
+    Warn,
+    /// The fields are silently dropped
+    #[default]
+    Ignore,
+}
+
+/// How strictly the invocations found in a message are checked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvocationPolicy {
+    /// Maximum number of yaml blocks in a message
+    pub max_yaml_blocks: usize,
+    /// What to do with the unexpected top-level fields
+    pub extra_fields: ExtraFieldsPolicy,
+    /// Reject the invocations of unknown tools with suggestions of existing
+    /// tools with a close name
+    pub suggest_tool_names: bool,
+}
+
+impl Default for InvocationPolicy {
+    fn default() -> Self {
+        Self {
+            max_yaml_blocks: 1,
+            extra_fields: ExtraFieldsPolicy::default(),
+            suggest_tool_names: false,
+        }
+    }
+}
+
+/// Find the names in `candidates` that are close to `name` - closest first.
+pub(crate) fn suggest_names<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a String>,
+) -> Vec<String> {
+    /// Maximum number of suggestions
+    const MAX_SUGGESTIONS: usize = 3;
+
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(2);
+
+    let mut suggestions = candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect::<Vec<_>>();
+
+    suggestions.sort();
+
+    suggestions
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.clone())
+        .collect()
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
 }
 
 /// One of several T
@@ -362,4 +468,26 @@ mod tests {
 
         assert_snapshot!(tool_invocations.err().unwrap());
     }
+
+    #[test]
+    fn test_suggest_names() {
+        let candidates = ["Search", "SandboxedPython", "Conclude", "Wikipedia"]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            super::suggest_names("search", &candidates),
+            vec!["Search".to_string()]
+        );
+        assert_eq!(
+            super::suggest_names("SandboxPython", &candidates),
+            vec!["SandboxedPython".to_string()]
+        );
+        assert_eq!(
+            super::suggest_names("Conclued", &candidates),
+            vec!["Conclude".to_string()]
+        );
+        assert!(super::suggest_names("Calculator", &candidates).is_empty());
+    }
 }
@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::toolbox::Stats;
+
+/// Upper bounds of the buckets of the latency histograms - in seconds
+pub const LATENCY_BUCKETS: [f64; 11] = [0.01, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10., 30., 60.];
+
+/// Upper bounds of the buckets of the size histograms - in bytes
+pub const SIZE_BUCKETS: [f64; 8] = [
+    64., 256., 1024., 4096., 16384., 65536., 262_144., 1_048_576.,
+];
+
+/// Distribution of observed values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    /// Upper bounds of the buckets - sorted
+    pub bounds: Vec<f64>,
+    /// Number of observations per bucket - the last one counts the values
+    /// above all the bounds
+    pub counts: Vec<u64>,
+    /// Sum of the observed values
+    pub sum: f64,
+    /// Number of observations
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Create an empty histogram with buckets bounded by `bounds`
+    #[must_use]
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.,
+            count: 0,
+        }
+    }
+
+    /// Record a value
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Number of observations less than or equal to each bound - the last
+    /// one is for `+Inf`
+    #[must_use]
+    pub fn cumulative_counts(&self) -> Vec<u64> {
+        self.counts
+            .iter()
+            .scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            })
+            .collect()
+    }
+}
+
+impl Stats {
+    /// Render the statistics in the Prometheus text exposition format
+    #[must_use]
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        write_counters(
+            &mut out,
+            "sapiens_tool_invocations_total",
+            "Number of invocations per tool and outcome",
+            "outcome",
+            &[
+                ("success", &self.success_count),
+                ("error", &self.error_count),
+                ("inexistent", &self.inexistent_count),
+            ],
+        );
+
+        let errors = sorted(&self.error_kinds)
+            .flat_map(|(tool_name, kinds)| {
+                sorted(kinds).map(move |(kind, count)| {
+                    (
+                        labels(&[("tool", tool_name), ("kind", kind)]),
+                        count.to_string(),
+                    )
+                })
+            })
+            .collect();
+        write_metric(
+            &mut out,
+            "sapiens_tool_errors_total",
+            "counter",
+            "Number of failed invocations per tool and kind of error",
+            errors,
+        );
+
+        write_counters(
+            &mut out,
+            "sapiens_tool_cache_total",
+            "Number of invocations of the cached tools per tool and cache status",
+            "status",
+            &[("hit", &self.cache_hits), ("miss", &self.cache_misses)],
+        );
+
+        for (name, help, histograms) in [
+            (
+                "sapiens_tool_latency_seconds",
+                "Duration of the invocations per tool",
+                &self.latency,
+            ),
+            (
+                "sapiens_tool_input_bytes",
+                "Size of the parameters of the invocations per tool",
+                &self.input_size,
+            ),
+            (
+                "sapiens_tool_output_bytes",
+                "Size of the results of the invocations per tool",
+                &self.output_size,
+            ),
+        ] {
+            write_histograms(&mut out, name, help, histograms);
+        }
+
+        let mut task_invocations = Vec::new();
+        let mut task_durations = Vec::new();
+        for (task, stats) in sorted(&self.tasks) {
+            for (outcome, counts) in [
+                ("success", &stats.success_count),
+                ("error", &stats.error_count),
+            ] {
+                for (tool_name, count) in sorted(counts) {
+                    task_invocations.push((
+                        labels(&[("task", task), ("tool", tool_name), ("outcome", outcome)]),
+                        count.to_string(),
+                    ));
+                }
+            }
+            task_durations.push((labels(&[("task", task)]), stats.duration.to_string()));
+        }
+        write_metric(
+            &mut out,
+            "sapiens_task_invocations_total",
+            "counter",
+            "Number of invocations per task, tool and outcome",
+            task_invocations,
+        );
+        write_metric(
+            &mut out,
+            "sapiens_task_tool_seconds_total",
+            "counter",
+            "Time spent in the tools per task",
+            task_durations,
+        );
+
+        out
+    }
+}
+
+/// The entries of `map` sorted by key - for a stable output
+fn sorted<V>(map: &HashMap<String, V>) -> impl Iterator<Item = (&str, &V)> {
+    map.iter()
+        .map(|(k, v)| (k.as_str(), v))
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+}
+
+/// Format a set of labels - e.g. `{tool="Search",outcome="success"}`
+fn labels(labels: &[(&str, &str)]) -> String {
+    let labels = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', r"\\")
+                .replace('"', "\\\"")
+                .replace('\n', r"\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect::<Vec<_>>();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Write a metric and its samples - nothing if there are no samples
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    samples: Vec<(String, String)>,
+) {
+    if samples.is_empty() {
+        return;
+    }
+
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {metric_type}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+/// Write a counter per tool and value of the label `label` - e.g. the outcome
+fn write_counters(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    counts: &[(&str, &HashMap<String, usize>)],
+) {
+    let mut samples = Vec::new();
+    for (value, counts) in counts {
+        for (tool_name, count) in sorted(counts) {
+            samples.push((
+                labels(&[("tool", tool_name), (label, value)]),
+                count.to_string(),
+            ));
+        }
+    }
+
+    write_metric(out, name, "counter", help, samples);
+}
+
+/// Write the histograms of a metric - one per tool
+fn write_histograms(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histograms: &HashMap<String, Histogram>,
+) {
+    let mut samples = Vec::new();
+    for (tool_name, histogram) in sorted(histograms) {
+        let bounds = histogram
+            .bounds
+            .iter()
+            .map(ToString::to_string)
+            .chain(std::iter::once("+Inf".to_string()));
+
+        for (le, count) in bounds.zip(histogram.cumulative_counts()) {
+            samples.push((
+                format!("_bucket{}", labels(&[("tool", tool_name), ("le", &le)])),
+                count.to_string(),
+            ));
+        }
+        samples.push((
+            format!("_sum{}", labels(&[("tool", tool_name)])),
+            histogram.sum.to_string(),
+        ));
+        samples.push((
+            format!("_count{}", labels(&[("tool", tool_name)])),
+            histogram.count.to_string(),
+        ));
+    }
+
+    write_metric(out, name, "histogram", help, samples);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::toolbox::TaskStats;
+
+    #[test]
+    fn observations_go_in_the_right_bucket() {
+        let mut histogram = Histogram::new(&[1., 10.]);
+
+        histogram.observe(0.5);
+        histogram.observe(1.);
+        histogram.observe(5.);
+        histogram.observe(100.);
+
+        assert_eq!(histogram.counts, vec![2, 1, 1]);
+        assert_eq!(histogram.cumulative_counts(), vec![2, 3, 4]);
+        assert_eq!(histogram.count, 4);
+        assert!((histogram.sum - 106.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn stats_are_rendered_for_prometheus() {
+        let mut latency = Histogram::new(&[0.1, 1.]);
+        latency.observe(0.05);
+        latency.observe(0.5);
+        let mut input_size = Histogram::new(&[64.]);
+        input_size.observe(12.);
+        input_size.observe(100.);
+        let mut output_size = Histogram::new(&[64.]);
+        output_size.observe(32.);
+
+        let stats = Stats {
+            success_count: HashMap::from([("Search".to_string(), 1)]),
+            error_count: HashMap::from([("Search".to_string(), 1)]),
+            inexistent_count: HashMap::from([("Sear\"ch".to_string(), 1)]),
+            latency: HashMap::from([("Search".to_string(), latency)]),
+            input_size: HashMap::from([("Search".to_string(), input_size)]),
+            output_size: HashMap::from([("Search".to_string(), output_size)]),
+            error_kinds: HashMap::from([(
+                "Search".to_string(),
+                HashMap::from([("Timeout".to_string(), 1)]),
+            )]),
+            tasks: HashMap::from([(
+                "job-1".to_string(),
+                TaskStats {
+                    success_count: HashMap::from([("Search".to_string(), 1)]),
+                    error_count: HashMap::from([("Search".to_string(), 1)]),
+                    duration: 0.55,
+                },
+            )]),
+            cache_hits: HashMap::from([("Search".to_string(), 3)]),
+            cache_misses: HashMap::from([("Search".to_string(), 2)]),
+        };
+
+        insta::assert_snapshot!(stats.to_prometheus());
+    }
+}
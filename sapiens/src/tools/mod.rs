@@ -1,12 +1,34 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::time::Duration;
 
 use serde::ser::SerializeMap;
 use serde::{Deserialize, Serialize, Serializer};
 use toolbox::Toolbox;
 use tracing::warn;
 
-use crate::tools::invocation::{Error, ExtractedInvocations};
+use crate::tools::invocation::{Error, ExtraFieldsPolicy, ExtractedInvocations, InvocationPolicy};
+
+/// Human-in-the-loop approval of the invocations
+pub mod approval;
+
+/// Storage of the results too long for the chat history
+pub mod artifacts;
+
+/// Reuse of the results of the deterministic tools
+pub mod cache;
+
+/// Latency and size distributions of the invocations
+pub mod metrics;
+
+/// Nested invocations of the advanced tools
+pub mod nesting;
+
+/// What the invocations of a task are permitted to do
+pub mod permissions;
+
+/// Registration of the tools by name
+pub mod registry;
 
 /// Tools to extract Tool invocations from a messages
 pub mod invocation;
@@ -15,15 +37,17 @@ pub mod invocation;
 pub mod toolbox;
 
 /// Part of a [`Format`]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FieldFormat {
     /// Name of the field
     pub name: String,
     /// Type of the field
     pub r#type: String,
     /// True if the field is optional
+    #[serde(default)]
     pub optional: bool,
     /// Description of the field
+    #[serde(default)]
     pub description: String,
 }
 
@@ -56,6 +80,177 @@ impl Serialize for Format {
     }
 }
 
+impl Format {
+    /// Check the `input` parameters against the format.
+    ///
+    /// Returns the list of the missing, unknown and mistyped fields. Only the
+    /// basic types are checked. Nothing is checked if the format has no
+    /// fields.
+    pub fn validate(&self, input: &serde_yaml::Value) -> Result<(), Vec<ParameterViolation>> {
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+
+        let empty = serde_yaml::Mapping::new();
+        let input = match input {
+            serde_yaml::Value::Mapping(m) => m,
+            serde_yaml::Value::Null => &empty,
+            _ => {
+                return Err(vec![ParameterViolation::Mistyped {
+                    name: "parameters".to_string(),
+                    expected_type: "dict".to_string(),
+                    description: "the parameters of the Tool".to_string(),
+                    found: value_type_name(input).to_string(),
+                }])
+            }
+        };
+
+        let mut violations = vec![];
+
+        for field in &self.fields {
+            match input.get(field.name.as_str()) {
+                None | Some(serde_yaml::Value::Null) if !field.optional => {
+                    violations.push(ParameterViolation::Missing {
+                        name: field.name.clone(),
+                        expected_type: field.r#type.clone(),
+                        description: field.description.clone(),
+                    });
+                }
+                Some(value) if !type_matches(&field.r#type, value) => {
+                    violations.push(ParameterViolation::Mistyped {
+                        name: field.name.clone(),
+                        expected_type: field.r#type.clone(),
+                        description: field.description.clone(),
+                        found: value_type_name(value).to_string(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        for name in input.keys() {
+            let name = name
+                .as_str()
+                .map_or_else(|| format!("{name:?}"), ToString::to_string);
+            if !self.fields.iter().any(|f| f.name == name) {
+                violations.push(ParameterViolation::Unknown { name });
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Check if `value` is compatible with the (python-ified) type `ty` of a
+/// [`FieldFormat`].
+///
+/// Unsupported types are assumed to match.
+fn type_matches(ty: &str, value: &serde_yaml::Value) -> bool {
+    use serde_yaml::Value;
+
+    let ty = ty.trim();
+
+    if let Some(inner) = ty
+        .strip_prefix("Optional[")
+        .and_then(|t| t.strip_suffix(']'))
+    {
+        return value.is_null() || type_matches(inner, value);
+    }
+
+    match (ty, value) {
+        (_, Value::Tagged(_)) | ("Any", _) => true,
+        ("str" | "char", v) => v.is_string(),
+        ("bool", v) => v.is_bool(),
+        ("float" | "f32" | "f64", v) => v.is_number(),
+        (
+            "int" | "usize" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128" | "i8" | "i16"
+            | "i32" | "i64" | "i128",
+            v,
+        ) => v.is_i64() || v.is_u64(),
+        (t, v) if t.starts_with("list[") => v.is_sequence(),
+        (t, v) if t.starts_with("dict[") => v.is_mapping(),
+        _ => true,
+    }
+}
+
+/// Name of the type of `value` - for the error messages
+const fn value_type_name(value: &serde_yaml::Value) -> &'static str {
+    match value {
+        serde_yaml::Value::Null => "null",
+        serde_yaml::Value::Bool(_) => "bool",
+        serde_yaml::Value::Number(_) => "number",
+        serde_yaml::Value::String(_) => "str",
+        serde_yaml::Value::Sequence(_) => "list",
+        serde_yaml::Value::Mapping(_) => "dict",
+        serde_yaml::Value::Tagged(_) => "tagged value",
+    }
+}
+
+/// A parameter that does not match the [`Format`] of a tool
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ParameterViolation {
+    /// A required field is missing
+    Missing {
+        /// Name of the field
+        name: String,
+        /// Expected type of the field
+        expected_type: String,
+        /// Description of the field
+        description: String,
+    },
+    /// A field is not part of the format
+    Unknown {
+        /// Name of the field
+        name: String,
+    },
+    /// A field does not have the expected type
+    Mistyped {
+        /// Name of the field
+        name: String,
+        /// Expected type of the field
+        expected_type: String,
+        /// Description of the field
+        description: String,
+        /// Type of the value that was given
+        found: String,
+    },
+}
+
+impl std::fmt::Display for ParameterViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing {
+                name,
+                expected_type,
+                description,
+            } => write!(f, "missing field `{name}` <{expected_type}>: {description}"),
+            Self::Unknown { name } => write!(f, "unknown field `{name}`"),
+            Self::Mistyped {
+                name,
+                expected_type,
+                description,
+                found,
+            } => write!(
+                f,
+                "field `{name}` should be <{expected_type}> but is <{found}>: {description}"
+            ),
+        }
+    }
+}
+
+/// Format a list of [`ParameterViolation`]
+fn format_violations(violations: &[ParameterViolation]) -> String {
+    violations
+        .iter()
+        .map(|v| format!("- {v}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 impl From<Vec<FieldFormat>> for Format {
     fn from(fields: Vec<FieldFormat>) -> Self {
         Self { fields }
@@ -99,6 +294,9 @@ pub enum ToolUseError {
     /// Tool not found
     #[error("Tool not found: {0}")]
     ToolNotFound(String),
+    /// Tool disabled
+    #[error("Tool is disabled: {0}")]
+    ToolDisabled(String),
     /// Tool invocation failed
     #[error("Tool invocation failed: {0}")]
     InvocationFailed(String),
@@ -108,6 +306,67 @@ pub enum ToolUseError {
     /// Failed to deserialize the input
     #[error("Failed to deserialize the parameters: {0}")]
     InvalidInput(String),
+    /// The parameters do not match the format of the tool
+    #[error("Invalid parameters:\n{}", format_violations(.0))]
+    InvalidParameters(Vec<ParameterViolation>),
+    /// The tool did not complete in time
+    #[error("Tool {tool_name} timed out after {}s", timeout.as_secs_f32())]
+    Timeout {
+        /// Name of the tool
+        tool_name: String,
+        /// The timeout that was exceeded
+        timeout: Duration,
+    },
+    /// The invocation was cancelled
+    #[error("Invocation of {0} was cancelled")]
+    Cancelled(String),
+    /// The rate limit of the tool is exhausted
+    #[error("Rate limit of {tool_name} exhausted - retry in {}s", retry_after.as_secs_f32())]
+    RateLimited {
+        /// Name of the tool
+        tool_name: String,
+        /// How long to wait for the next invocation
+        retry_after: Duration,
+    },
+    /// The invocation was rejected during its review
+    #[error("The Action was rejected: {0}")]
+    Rejected(String),
+    /// The invocation is not permitted by the policy of the task
+    #[error("The Action is not permitted: {0}")]
+    PermissionDenied(#[from] permissions::PermissionViolation),
+    /// An advanced tool was invoked while it is already being invoked
+    #[error("Invocation cycle: {}", .0.join(" -> "))]
+    InvocationCycle(Vec<String>),
+    /// Too many advanced tools are nested
+    #[error("Too many nested invocations (max {max_depth}): {}", chain.join(" -> "))]
+    MaxDepthExceeded {
+        /// The advanced tools being invoked - the outermost first
+        chain: Vec<String>,
+        /// The maximum depth
+        max_depth: usize,
+    },
+}
+
+impl ToolUseError {
+    /// The kind of error - the name of the variant
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::ToolNotFound(_) => "ToolNotFound",
+            Self::ToolDisabled(_) => "ToolDisabled",
+            Self::InvocationFailed(_) => "InvocationFailed",
+            Self::InvalidOutput(_) => "InvalidOutput",
+            Self::InvalidInput(_) => "InvalidInput",
+            Self::InvalidParameters(_) => "InvalidParameters",
+            Self::Timeout { .. } => "Timeout",
+            Self::Cancelled(_) => "Cancelled",
+            Self::RateLimited { .. } => "RateLimited",
+            Self::Rejected(_) => "Rejected",
+            Self::PermissionDenied(_) => "PermissionDenied",
+            Self::InvocationCycle(_) => "InvocationCycle",
+            Self::MaxDepthExceeded { .. } => "MaxDepthExceeded",
+        }
+    }
 }
 
 /// A tool invocation input
@@ -128,6 +387,30 @@ pub(crate) struct ToolInvocationInput {
 pub trait ProtoToolDescribe {
     /// the description of the tool
     fn description(&self) -> ToolDescription;
+
+    /// true if the tool has no side effect and can be invoked concurrently
+    /// with other side-effect-free tools
+    fn is_side_effect_free(&self) -> bool {
+        false
+    }
+
+    /// how long the tool can run - the default timeout of the [`Toolbox`]
+    /// applies if `None`
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// true if the invocations of the tool must be approved before being
+    /// executed - see [`approval::ApprovalHandler`]
+    fn requires_approval(&self) -> bool {
+        false
+    }
+
+    /// how long the results of the tool can be reused for the same
+    /// parameters - not cached if `None`, see [`cache::CacheBackend`]
+    fn cache_ttl(&self) -> Option<Duration> {
+        None
+    }
 }
 
 /// Something meant to become a [`Tool`] - invocation
@@ -144,6 +427,30 @@ pub trait Tool: Sync + Send {
     /// the description of the tool
     fn description(&self) -> ToolDescription;
 
+    /// true if the tool has no side effect and can be invoked concurrently
+    /// with other side-effect-free tools
+    fn is_side_effect_free(&self) -> bool {
+        false
+    }
+
+    /// how long the tool can run - the default timeout of the [`Toolbox`]
+    /// applies if `None`
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// true if the invocations of the tool must be approved before being
+    /// executed - see [`approval::ApprovalHandler`]
+    fn requires_approval(&self) -> bool {
+        false
+    }
+
+    /// how long the results of the tool can be reused for the same
+    /// parameters - not cached if `None`, see [`cache::CacheBackend`]
+    fn cache_ttl(&self) -> Option<Duration> {
+        None
+    }
+
     /// Invoke the tool
     // FUTURE(ssoudan) Box<Deserialize>?
     async fn invoke(&self, input: serde_yaml::Value) -> Result<serde_yaml::Value, ToolUseError>;
@@ -158,6 +465,22 @@ where
         self.description()
     }
 
+    fn is_side_effect_free(&self) -> bool {
+        ProtoToolDescribe::is_side_effect_free(self)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        ProtoToolDescribe::timeout(self)
+    }
+
+    fn requires_approval(&self) -> bool {
+        ProtoToolDescribe::requires_approval(self)
+    }
+
+    fn cache_ttl(&self) -> Option<Duration> {
+        ProtoToolDescribe::cache_ttl(self)
+    }
+
     async fn invoke(&self, input: serde_yaml::Value) -> Result<serde_yaml::Value, ToolUseError> {
         self.invoke(input).await
     }
@@ -200,52 +523,118 @@ pub trait AdvancedTool: Tool {
     ) -> Result<serde_yaml::Value, ToolUseError>;
 }
 
-fn choose_invocation(tool_invocations: ExtractedInvocations) -> Result<ToolInvocationInput, Error> {
-    // TODO(ssoudan) customizable level of strictness
-    if tool_invocations.yaml_block_count > 1 {
-        return Err(Error::TooManyYamlBlocks(tool_invocations.yaml_block_count));
-    }
+/// Invocations that passed the checks of an [`InvocationPolicy`]
+pub(crate) struct CheckedInvocation {
+    /// The invocation
+    pub(crate) invocation: ToolInvocationInput,
+    /// The violations of the [`InvocationPolicy`] that are only warnings
+    pub(crate) warnings: Vec<Error>,
+}
+
+/// Pick the first invocation and check it against the `policy`.
+///
+/// `tool_names` are the names of the existing tools - used for the
+/// suggestions.
+fn choose_invocation(
+    tool_invocations: ExtractedInvocations,
+    policy: &InvocationPolicy,
+    tool_names: &[String],
+) -> Result<CheckedInvocation, Error> {
+    check_yaml_block_count(&tool_invocations, policy)?;
+
+    // We just take the first one
+    let invocation = tool_invocations
+        .invocations
+        .into_iter()
+        .next()
+        .ok_or(Error::NoInvocationFound)?;
+
+    check_invocation(invocation, policy, tool_names)
+}
+
+/// Same as [`choose_invocation`] but keep all the invocations - in order.
+fn choose_all_invocations(
+    tool_invocations: ExtractedInvocations,
+    policy: &InvocationPolicy,
+    tool_names: &[String],
+) -> Result<Vec<CheckedInvocation>, Error> {
+    check_yaml_block_count(&tool_invocations, policy)?;
 
-    // if no tool_invocations are found, we return an error
     if tool_invocations.invocations.is_empty() {
         return Err(Error::NoInvocationFound);
     }
 
-    // We just take the first one
-    let mut invocation = tool_invocations.invocations.into_iter().next().unwrap();
+    tool_invocations
+        .invocations
+        .into_iter()
+        .map(|invocation| check_invocation(invocation, policy, tool_names))
+        .collect()
+}
 
-    // FUTURE(ssoudan) clean up the object and return this one instead
-    // if any tool_invocations have an 'output' field, we return an error
+const fn check_yaml_block_count(
+    tool_invocations: &ExtractedInvocations,
+    policy: &InvocationPolicy,
+) -> Result<(), Error> {
+    if tool_invocations.yaml_block_count > policy.max_yaml_blocks {
+        return Err(Error::TooManyYamlBlocks {
+            count: tool_invocations.yaml_block_count,
+            max: policy.max_yaml_blocks,
+        });
+    }
+
+    Ok(())
+}
+
+fn check_invocation(
+    mut invocation: ToolInvocationInput,
+    policy: &InvocationPolicy,
+    tool_names: &[String],
+) -> Result<CheckedInvocation, Error> {
+    let mut warnings = vec![];
+
+    if policy.suggest_tool_names && !tool_names.contains(&invocation.tool_name) {
+        return Err(Error::UnknownTool {
+            suggestions: invocation::suggest_names(&invocation.tool_name, tool_names),
+            tool_name: invocation.tool_name,
+        });
+    }
+
+    // if any tool_invocations have an 'output' field for example
     if !invocation.junk.is_empty() {
-        let junk_keys = invocation
-            .junk
-            .keys()
-            .cloned()
-            .collect::<Vec<String>>()
-            .join(", ");
-
-        warn!(
-            ?junk_keys,
-            "The Action should not have fields: {}.", junk_keys
-        );
+        let mut fields = invocation.junk.keys().cloned().collect::<Vec<String>>();
+        fields.sort();
 
-        // We just remove them for now
-        invocation.junk.clear();
+        warn!(?fields, "The Action should not have fields: {:?}.", fields);
+
+        let violation = Error::UnexpectedFields {
+            tool_name: invocation.tool_name.clone(),
+            fields,
+        };
+
+        match policy.extra_fields {
+            ExtraFieldsPolicy::Error => return Err(violation),
+            ExtraFieldsPolicy::Warn => warnings.push(violation),
+            ExtraFieldsPolicy::Ignore => {}
+        }
 
-        // return Err(InvocationError::NoValidInvocationFound(format!(
-        //     "The Action cannot have fields: {}. Only `command` and `input`
-        // are allowed.",     junk_keys
-        // )));
+        invocation.junk.clear();
     }
 
-    Ok(invocation)
+    Ok(CheckedInvocation {
+        invocation,
+        warnings,
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use indoc::indoc;
     use insta::assert_snapshot;
     use serde::{Deserialize, Serialize};
 
+    use crate::tools::invocation;
+    use crate::tools::invocation::{Error, ExtraFieldsPolicy, InvocationPolicy};
+
     #[derive(Debug, Serialize, Deserialize)]
     struct FakeToolInput {
         q: String,
@@ -288,4 +677,168 @@ mod tests {
 
         assert_snapshot!(serialized);
     }
+
+    const DATA_WITH_OUTPUT: &str = indoc! {r"
+    ```yaml
+    tool_name: Serch
+    parameters:
+      q: Marcel Deneuve
+    output:
+      items: []
+    ```
+    "};
+
+    fn choose(policy: &InvocationPolicy) -> Result<super::CheckedInvocation, Error> {
+        let tool_invocations = invocation::find_all(DATA_WITH_OUTPUT).unwrap();
+        let tool_names = vec!["Search".to_string(), "Conclude".to_string()];
+
+        super::choose_invocation(tool_invocations, policy, &tool_names)
+    }
+
+    #[test]
+    fn test_extra_fields_policy() {
+        let checked = choose(&InvocationPolicy::default()).unwrap();
+        assert!(checked.warnings.is_empty());
+        assert!(checked.invocation.junk.is_empty());
+
+        let checked = choose(&InvocationPolicy {
+            extra_fields: ExtraFieldsPolicy::Warn,
+            ..InvocationPolicy::default()
+        })
+        .unwrap();
+        assert!(checked.invocation.junk.is_empty());
+        assert!(matches!(
+            checked.warnings.as_slice(),
+            [Error::UnexpectedFields { fields, .. }] if fields == &["output".to_string()]
+        ));
+
+        let e = choose(&InvocationPolicy {
+            extra_fields: ExtraFieldsPolicy::Error,
+            ..InvocationPolicy::default()
+        })
+        .err()
+        .unwrap();
+        assert!(matches!(e, Error::UnexpectedFields { .. }));
+    }
+
+    #[test]
+    fn test_unknown_tool_suggestions() {
+        let e = choose(&InvocationPolicy {
+            suggest_tool_names: true,
+            ..InvocationPolicy::default()
+        })
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            e.to_string(),
+            "Tool not found: Serch. Did you mean: Search?"
+        );
+    }
+
+    #[test]
+    fn test_yaml_block_count_policy() {
+        let data = format!("{DATA_WITH_OUTPUT}\n{DATA_WITH_OUTPUT}");
+        let tool_invocations = invocation::find_all(&data).unwrap();
+
+        let e = super::choose_invocation(tool_invocations, &InvocationPolicy::default(), &[])
+            .err()
+            .unwrap();
+        assert!(matches!(e, Error::TooManyYamlBlocks { count: 2, max: 1 }));
+
+        let tool_invocations = invocation::find_all(&data).unwrap();
+        let policy = InvocationPolicy {
+            max_yaml_blocks: 2,
+            ..InvocationPolicy::default()
+        };
+        assert!(super::choose_invocation(tool_invocations, &policy, &[]).is_ok());
+    }
+
+    fn fake_tool_format() -> super::Format {
+        let field = |name: &str, r#type: &str, optional: bool| super::FieldFormat {
+            name: name.to_string(),
+            r#type: r#type.to_string(),
+            optional,
+            description: format!("The {name}"),
+        };
+
+        vec![
+            field("q", "str", false),
+            field("excluded_terms", "Optional[str]", true),
+            field("num_results", "Optional[u32]", true),
+        ]
+        .into()
+    }
+
+    #[test]
+    fn test_validate_parameters() {
+        let format = fake_tool_format();
+
+        let input = serde_yaml::from_str("q: Marcel Deneuve\nnum_results: 10").unwrap();
+        assert!(format.validate(&input).is_ok());
+
+        let input = serde_yaml::from_str("num_results: ten\nquery: Marcel Deneuve").unwrap();
+        let violations = format.validate(&input).err().unwrap();
+        assert_eq!(
+            violations,
+            vec![
+                super::ParameterViolation::Missing {
+                    name: "q".to_string(),
+                    expected_type: "str".to_string(),
+                    description: "The q".to_string(),
+                },
+                super::ParameterViolation::Mistyped {
+                    name: "num_results".to_string(),
+                    expected_type: "Optional[u32]".to_string(),
+                    description: "The num_results".to_string(),
+                    found: "str".to_string(),
+                },
+                super::ParameterViolation::Unknown {
+                    name: "query".to_string(),
+                },
+            ]
+        );
+
+        assert_snapshot!(super::ToolUseError::InvalidParameters(violations).to_string());
+    }
+
+    #[test]
+    fn test_validate_without_parameters() {
+        let format = fake_tool_format();
+
+        let violations = format.validate(&serde_yaml::Value::Null).err().unwrap();
+        assert_eq!(violations.len(), 1);
+
+        let input = serde_yaml::Value::String("Marcel Deneuve".to_string());
+        let violations = format.validate(&input).err().unwrap();
+        assert!(matches!(
+            &violations[..],
+            [super::ParameterViolation::Mistyped { name, .. }] if name == "parameters"
+        ));
+
+        // nothing to validate against
+        assert!(super::Format::default().validate(&input).is_ok());
+    }
+
+    #[test]
+    fn test_type_matches() {
+        let int = serde_yaml::Value::from(42);
+        let float = serde_yaml::Value::from(4.2);
+        let text = serde_yaml::Value::from("abc");
+
+        for ty in [
+            "int", "usize", "isize", "u8", "u16", "u32", "u64", "u128", "i8", "i16", "i32", "i64",
+            "i128",
+        ] {
+            assert!(super::type_matches(ty, &int), "{ty}");
+            assert!(!super::type_matches(ty, &float), "{ty}");
+            assert!(!super::type_matches(ty, &text), "{ty}");
+        }
+
+        for ty in ["float", "f32", "f64"] {
+            assert!(super::type_matches(ty, &float), "{ty}");
+            assert!(super::type_matches(ty, &int), "{ty}");
+            assert!(!super::type_matches(ty, &text), "{ty}");
+        }
+    }
 }
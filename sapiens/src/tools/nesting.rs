@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tools::ToolUseError;
+
+/// How deep the advanced tools can be nested by default
+pub const DEFAULT_MAX_DEPTH: usize = 3;
+
+/// The advanced tools an invocation is nested in
+///
+/// Each [`crate::tools::AdvancedTool`] receives a
+/// [`crate::tools::toolbox::Toolbox`] carrying the context of its own
+/// invocation - see [`crate::tools::toolbox::Toolbox::invocation_context`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvocationContext {
+    /// The advanced tools being invoked - the outermost first
+    pub chain: Vec<String>,
+    /// How many advanced tools can be nested
+    pub max_depth: usize,
+}
+
+impl Default for InvocationContext {
+    fn default() -> Self {
+        Self {
+            chain: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl InvocationContext {
+    /// How many advanced tools are being invoked
+    #[must_use]
+    pub const fn depth(&self) -> usize {
+        self.chain.len()
+    }
+
+    /// The context of an invocation of the advanced tool `tool_name` from
+    /// this one
+    ///
+    /// Fails if `tool_name` is already being invoked or if it would exceed
+    /// the maximum depth.
+    pub fn enter(&self, tool_name: &str) -> Result<Self, ToolUseError> {
+        let mut chain = self.chain.clone();
+        chain.push(tool_name.to_string());
+
+        if self.chain.iter().any(|name| name == tool_name) {
+            return Err(ToolUseError::InvocationCycle(chain));
+        }
+
+        if self.depth() >= self.max_depth {
+            return Err(ToolUseError::MaxDepthExceeded {
+                chain,
+                max_depth: self.max_depth,
+            });
+        }
+
+        Ok(Self {
+            chain,
+            max_depth: self.max_depth,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_invocations_are_limited() {
+        let context = InvocationContext {
+            chain: Vec::new(),
+            max_depth: 2,
+        };
+
+        let context = context.enter("SandboxedPython").unwrap();
+        let context = context.enter("SubAgent").unwrap();
+        assert_eq!(context.depth(), 2);
+
+        let e = context.enter("Summarizer").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Too many nested invocations (max 2): SandboxedPython -> SubAgent -> Summarizer"
+        );
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        let context = InvocationContext::default()
+            .enter("SandboxedPython")
+            .unwrap()
+            .enter("SubAgent")
+            .unwrap();
+
+        let e = context.enter("SandboxedPython").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Invocation cycle: SandboxedPython -> SubAgent -> SandboxedPython"
+        );
+    }
+}
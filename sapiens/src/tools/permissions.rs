@@ -0,0 +1,269 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+/// Violation of a [`PermissionPolicy`]
+#[derive(Debug, thiserror::Error, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PermissionViolation {
+    /// The tool is not allowed for this task
+    #[error("Tool {tool_name} is not allowed for this task")]
+    NotAllowed {
+        /// The name of the tool
+        tool_name: String,
+    },
+    /// The tool was invoked too many times
+    #[error("Tool {tool_name} cannot be invoked more than {quota} times for this task")]
+    QuotaExceeded {
+        /// The name of the tool
+        tool_name: String,
+        /// The maximum number of invocations
+        quota: usize,
+    },
+    /// A parameter has a forbidden value
+    #[error("Parameter `{path}` of {tool_name} cannot be {value}. Allowed: {}", allowed.join(", "))]
+    ForbiddenValue {
+        /// The name of the tool
+        tool_name: String,
+        /// The path of the parameter
+        path: String,
+        /// The forbidden value
+        value: String,
+        /// The allowed values
+        allowed: Vec<String>,
+    },
+}
+
+/// A constraint on the parameters of an invocation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArgumentConstraint {
+    /// The values at `path` must be one of `values`
+    ///
+    /// `path` is a list of field names separated by `.`. A field name ending
+    /// with `[]` designates each item of a list - e.g. `lights[].id`. Missing
+    /// values are not constrained.
+    OneOf {
+        /// The path of the parameter
+        path: String,
+        /// The allowed values
+        values: Vec<serde_yaml::Value>,
+    },
+}
+
+impl ArgumentConstraint {
+    /// Check the parameters of an invocation of `tool_name`
+    fn check(&self, tool_name: &str, input: &serde_yaml::Value) -> Result<(), PermissionViolation> {
+        match self {
+            Self::OneOf { path, values } => {
+                let found = select(input, path);
+
+                match found.into_iter().find(|v| !values.contains(v)) {
+                    Some(value) => Err(PermissionViolation::ForbiddenValue {
+                        tool_name: tool_name.to_string(),
+                        path: path.clone(),
+                        value: to_string(value),
+                        allowed: values.iter().map(to_string).collect(),
+                    }),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Compact representation of a value - for the error messages
+fn to_string(value: &serde_yaml::Value) -> String {
+    serde_yaml::to_string(value)
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// Get the values at `path` in `value` - see [`ArgumentConstraint::OneOf`]
+fn select<'a>(value: &'a serde_yaml::Value, path: &str) -> Vec<&'a serde_yaml::Value> {
+    let mut values = vec![value];
+
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (name, each) = segment
+            .strip_suffix("[]")
+            .map_or((segment, false), |name| (name, true));
+
+        values = values
+            .into_iter()
+            .filter_map(|v| v.get(name))
+            .filter(|v| !v.is_null())
+            .flat_map(|v| match v {
+                serde_yaml::Value::Sequence(items) if each => items.iter().collect(),
+                v => vec![v],
+            })
+            .collect();
+    }
+
+    values
+}
+
+/// What the invocations of a task are permitted to do
+///
+/// Attach it to a [`crate::tools::toolbox::Toolbox`] with
+/// [`crate::tools::toolbox::Toolbox::with_permissions`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionPolicy {
+    /// The only tools that can be invoked - all of them if `None`
+    pub allow: Option<HashSet<String>>,
+    /// The tools that cannot be invoked
+    pub deny: HashSet<String>,
+    /// The maximum number of invocations per tool
+    pub quotas: HashMap<String, usize>,
+    /// The constraints on the parameters per tool
+    pub constraints: HashMap<String, Vec<ArgumentConstraint>>,
+}
+
+impl PermissionPolicy {
+    /// Check if a tool can be invoked at all
+    #[must_use]
+    pub fn is_allowed(&self, tool_name: &str) -> bool {
+        !self.deny.contains(tool_name)
+            && self
+                .allow
+                .as_ref()
+                .is_none_or(|allow| allow.contains(tool_name))
+    }
+}
+
+/// A [`PermissionPolicy`] and the invocations it has let through
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Permissions {
+    /// The policy
+    pub(crate) policy: PermissionPolicy,
+    /// The number of invocations per tool
+    calls: HashMap<String, usize>,
+}
+
+impl Permissions {
+    pub(crate) fn new(policy: PermissionPolicy) -> Self {
+        Self {
+            policy,
+            calls: HashMap::new(),
+        }
+    }
+
+    /// Check an invocation without counting it
+    pub(crate) fn validate(
+        &self,
+        tool_name: &str,
+        input: &serde_yaml::Value,
+    ) -> Result<(), PermissionViolation> {
+        if !self.policy.is_allowed(tool_name) {
+            return Err(PermissionViolation::NotAllowed {
+                tool_name: tool_name.to_string(),
+            });
+        }
+
+        for constraint in self.policy.constraints.get(tool_name).into_iter().flatten() {
+            constraint.check(tool_name, input)?;
+        }
+
+        let calls = self.calls.get(tool_name).copied().unwrap_or_default();
+        if let Some(&quota) = self.policy.quotas.get(tool_name) {
+            if calls >= quota {
+                return Err(PermissionViolation::QuotaExceeded {
+                    tool_name: tool_name.to_string(),
+                    quota,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check an invocation and count it if it is permitted
+    pub(crate) fn check(
+        &mut self,
+        tool_name: &str,
+        input: &serde_yaml::Value,
+    ) -> Result<(), PermissionViolation> {
+        self.validate(tool_name, input)?;
+        *self.calls.entry(tool_name.to_string()).or_default() += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    fn policy() -> PermissionPolicy {
+        serde_yaml::from_str(indoc! {r#"
+            deny: [SandboxedPython]
+            quotas:
+              Search: 2
+            constraints:
+              SetLightStatus:
+                - !OneOf
+                  path: lights[].id
+                  values: ["1", "2"]
+        "#})
+        .unwrap()
+    }
+
+    #[test]
+    fn denied_tools_are_not_allowed() {
+        let mut permissions = Permissions::new(policy());
+
+        assert_eq!(
+            permissions.check("SandboxedPython", &serde_yaml::Value::Null),
+            Err(PermissionViolation::NotAllowed {
+                tool_name: "SandboxedPython".to_string()
+            })
+        );
+        assert!(permissions
+            .check("Wikipedia", &serde_yaml::Value::Null)
+            .is_ok());
+
+        permissions.policy.allow = Some(HashSet::from(["Search".to_string()]));
+        assert!(permissions
+            .check("Wikipedia", &serde_yaml::Value::Null)
+            .is_err());
+    }
+
+    #[test]
+    fn quotas_are_enforced() {
+        let mut permissions = Permissions::new(policy());
+        let input = serde_yaml::Value::Null;
+
+        assert!(permissions.check("Search", &input).is_ok());
+        // not counted
+        assert!(permissions.validate("Search", &input).is_ok());
+        assert!(permissions.check("Search", &input).is_ok());
+        assert!(permissions.validate("Search", &input).is_err());
+        assert_eq!(
+            permissions.check("Search", &input),
+            Err(PermissionViolation::QuotaExceeded {
+                tool_name: "Search".to_string(),
+                quota: 2
+            })
+        );
+    }
+
+    #[test]
+    fn argument_constraints_are_enforced() {
+        let mut permissions = Permissions::new(policy());
+
+        let input = serde_yaml::from_str("lights: [{id: '1', on: true}, {id: '2'}]").unwrap();
+        assert!(permissions.check("SetLightStatus", &input).is_ok());
+
+        let input = serde_yaml::from_str("lights: [{id: '1'}, {id: '3', on: false}]").unwrap();
+        let e = permissions.check("SetLightStatus", &input).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Parameter `lights[].id` of SetLightStatus cannot be '3'. Allowed: '1', '2'"
+        );
+
+        // nothing to constrain
+        let input = serde_yaml::from_str("lights: null").unwrap();
+        assert!(permissions.check("SetLightStatus", &input).is_ok());
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::{AdvancedTool, TerminalTool, Tool};
+
+/// Separator between the namespace and the name of a tool - see
+/// [`qualified_name`]
+pub const NAMESPACE_SEPARATOR: char = '.';
+
+/// Name of a tool registered in a namespace - e.g. `hue.SetStatus`
+#[must_use]
+pub fn qualified_name(namespace: &str, name: &str) -> String {
+    format!("{namespace}{NAMESPACE_SEPARATOR}{name}")
+}
+
+/// Error while registering or looking up a tool
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistryError {
+    /// A tool or an alias already uses the name
+    #[error("A tool named {0} is already registered")]
+    AlreadyRegistered(String),
+    /// No tool or alias has the name
+    #[error("No tool named {0} is registered")]
+    NotFound(String),
+}
+
+/// The kind of a registered tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToolKind {
+    /// A [`Tool`]
+    Plain,
+    /// A [`TerminalTool`] - can terminate a chain of exchanges
+    Terminal,
+    /// An [`AdvancedTool`] - can invoke other tools
+    Advanced,
+}
+
+/// A tool and what it can do
+///
+/// Cloning it is cheap - it is a handle on the tool.
+#[derive(Clone)]
+pub enum RegisteredTool {
+    /// A [`Tool`]
+    Plain(Arc<dyn Tool>),
+    /// A [`TerminalTool`]
+    Terminal(Arc<dyn TerminalTool>),
+    /// An [`AdvancedTool`]
+    Advanced(Arc<dyn AdvancedTool>),
+}
+
+impl RegisteredTool {
+    /// Wrap a [`Tool`]
+    pub fn plain(tool: impl Tool + 'static) -> Self {
+        Self::Plain(Arc::new(tool))
+    }
+
+    /// Wrap a [`TerminalTool`]
+    pub fn terminal(tool: impl TerminalTool + 'static) -> Self {
+        Self::Terminal(Arc::new(tool))
+    }
+
+    /// Wrap an [`AdvancedTool`]
+    pub fn advanced(tool: impl AdvancedTool + 'static) -> Self {
+        Self::Advanced(Arc::new(tool))
+    }
+
+    /// The kind of the tool
+    #[must_use]
+    pub const fn kind(&self) -> ToolKind {
+        match self {
+            Self::Plain(_) => ToolKind::Plain,
+            Self::Terminal(_) => ToolKind::Terminal,
+            Self::Advanced(_) => ToolKind::Advanced,
+        }
+    }
+
+    /// The tool itself
+    #[must_use]
+    pub fn as_tool(&self) -> &dyn Tool {
+        match self {
+            Self::Plain(tool) => tool.as_ref(),
+            Self::Terminal(tool) => tool.as_ref(),
+            Self::Advanced(tool) => tool.as_ref(),
+        }
+    }
+}
+
+/// A registered tool and its state
+pub(crate) struct Entry {
+    /// The tool
+    pub(crate) tool: RegisteredTool,
+    /// Whether the tool can be described and invoked
+    pub(crate) enabled: bool,
+}
+
+/// The tools of a [`crate::tools::toolbox::Toolbox`] by name
+///
+/// A name designates a single tool whatever its kind. Aliases are other names
+/// for a registered tool.
+#[derive(Default)]
+pub(crate) struct Registry {
+    /// The tools by name
+    entries: HashMap<String, Entry>,
+    /// The name of the tool by alias
+    aliases: HashMap<String, String>,
+}
+
+impl Registry {
+    /// Register a tool under `name`
+    pub(crate) fn register(
+        &mut self,
+        name: String,
+        tool: RegisteredTool,
+    ) -> Result<(), RegistryError> {
+        if self.resolve(&name).is_some() {
+            return Err(RegistryError::AlreadyRegistered(name));
+        }
+
+        self.entries.insert(
+            name,
+            Entry {
+                tool,
+                enabled: true,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Remove a tool - and its aliases
+    pub(crate) fn remove(&mut self, name: &str) -> Result<RegisteredTool, RegistryError> {
+        let name = self
+            .resolve(name)
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?
+            .to_string();
+
+        self.aliases.retain(|_, target| *target != name);
+
+        self.entries
+            .remove(&name)
+            .map(|entry| entry.tool)
+            .ok_or(RegistryError::NotFound(name))
+    }
+
+    /// Add another name for a registered tool
+    pub(crate) fn add_alias(&mut self, alias: String, name: &str) -> Result<(), RegistryError> {
+        let name = self
+            .resolve(name)
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?
+            .to_string();
+
+        if self.resolve(&alias).is_some() {
+            return Err(RegistryError::AlreadyRegistered(alias));
+        }
+
+        self.aliases.insert(alias, name);
+
+        Ok(())
+    }
+
+    /// Enable or disable a tool
+    pub(crate) fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), RegistryError> {
+        let name = self
+            .resolve(name)
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?
+            .to_string();
+
+        if let Some(entry) = self.entries.get_mut(&name) {
+            entry.enabled = enabled;
+        }
+
+        Ok(())
+    }
+
+    /// The name of the tool designated by `name` - itself or an alias
+    pub(crate) fn resolve<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        if self.entries.contains_key(name) {
+            return Some(name);
+        }
+
+        self.aliases.get(name).map(String::as_str)
+    }
+
+    /// Get a tool by name or alias - disabled or not
+    pub(crate) fn get(&self, name: &str) -> Option<&Entry> {
+        self.entries.get(self.resolve(name)?)
+    }
+
+    /// The enabled tools by name
+    pub(crate) fn enabled(&self) -> impl Iterator<Item = (&String, &RegisteredTool)> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.enabled)
+            .map(|(name, entry)| (name, &entry.tool))
+    }
+}
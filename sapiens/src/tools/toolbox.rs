@@ -1,15 +1,28 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
+use crate::rate_limit::{self, RateLimit, TokenBucket};
 use crate::tools;
-use crate::tools::invocation::Error;
+use crate::tools::approval::{Approval, ApprovalHandler, ApprovalRequest};
+use crate::tools::artifacts::ArtifactStore;
+use crate::tools::cache::{self, CacheBackend, CacheStatus, ResultCache};
+use crate::tools::invocation::{Error, InvocationPolicy};
+use crate::tools::metrics::{Histogram, LATENCY_BUCKETS, SIZE_BUCKETS};
+use crate::tools::nesting::InvocationContext;
+use crate::tools::permissions::{PermissionPolicy, Permissions};
+use crate::tools::registry::{RegisteredTool, Registry, RegistryError, ToolKind};
 use crate::tools::{
-    AdvancedTool, TerminalTool, TerminationMessage, Tool, ToolDescription, ToolUseError,
+    AdvancedTool, CheckedInvocation, TerminalTool, TerminationMessage, Tool, ToolDescription,
+    ToolUseError,
 };
 
 /// Tool usage statistics
@@ -21,26 +34,120 @@ pub struct Stats {
     pub error_count: HashMap<String, usize>,
     /// Number of times an inexistent tool has been invoked
     pub inexistent_count: HashMap<String, usize>,
+    /// Duration of the invocations per tool - in seconds
+    #[serde(default)]
+    pub latency: HashMap<String, Histogram>,
+    /// Size of the parameters of the invocations per tool - in bytes
+    #[serde(default)]
+    pub input_size: HashMap<String, Histogram>,
+    /// Size of the results of the invocations per tool - in bytes
+    #[serde(default)]
+    pub output_size: HashMap<String, Histogram>,
+    /// Number of failed invocations per tool and kind of error - see
+    /// [`ToolUseError::kind`]
+    #[serde(default)]
+    pub error_kinds: HashMap<String, HashMap<String, usize>>,
+    /// Statistics per task - see [`Toolbox::with_task`]
+    #[serde(default)]
+    pub tasks: HashMap<String, TaskStats>,
+    /// Number of invocations answered from the cache per tool - see
+    /// [`Toolbox::set_cache`]
+    #[serde(default)]
+    pub cache_hits: HashMap<String, usize>,
+    /// Number of invocations of cached tools that had to be executed per tool
+    #[serde(default)]
+    pub cache_misses: HashMap<String, usize>,
 }
 
+/// Tool usage statistics of a task
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct TaskStats {
+    /// Number of times the tool has been invoked successfully
+    pub success_count: HashMap<String, usize>,
+    /// Number of times the tool has been invoked with an error
+    pub error_count: HashMap<String, usize>,
+    /// Time spent in the tools - in seconds
+    pub duration: f64,
+}
+
+/// How the invocations found in a message are executed by [`invoke_tool`]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InvocationMode {
+    /// Only the first invocation is executed
+    #[default]
+    FirstOnly,
+    /// All the invocations are executed - in order
+    All {
+        /// Consecutive invocations of side-effect-free tools are executed
+        /// concurrently
+        concurrent: bool,
+        /// Stop at the first invocation that fails
+        stop_on_error: bool,
+    },
+}
+
+/// What a tool asking the user a question puts in its result in place of the
+/// answer - see [`Toolbox::ask_user`]
+pub const PENDING_ANSWER: &str = "<waiting for the answer of the user>";
+
 /// Toolbox
 ///
 /// a [`Toolbox`] is a collection of [`Tool`], [`TerminalTool`] and
-/// [`AdvancedTool`].
+/// [`AdvancedTool`]. Each tool has a unique name - possibly in a namespace,
+/// see [`crate::tools::registry::qualified_name`] - and can have aliases.
 #[derive(Default, Clone)]
 pub struct Toolbox {
-    /// The terminal tools - the one that can terminate a chain of exchanges
-    terminal_tools: Arc<RwLock<HashMap<String, Box<dyn TerminalTool>>>>,
-
-    /// The tools - the other tools
-    tools: Arc<RwLock<HashMap<String, Box<dyn Tool>>>>,
-
-    /// The advanced tools - the one that can invoke another tool (not an
-    /// advanced one)
-    advanced_tools: Arc<RwLock<HashMap<String, Box<dyn AdvancedTool>>>>,
+    /// The tools of all kinds by name
+    registry: Arc<RwLock<Registry>>,
 
     /// The tool usage statistics
     stats: Arc<RwLock<Stats>>,
+
+    /// How the invocations are executed
+    invocation_mode: Arc<RwLock<InvocationMode>>,
+
+    /// How strictly the invocations are checked
+    invocation_policy: Arc<RwLock<InvocationPolicy>>,
+
+    /// How long a tool can run when it does not declare its own timeout
+    default_timeout: Arc<RwLock<Option<Duration>>>,
+
+    /// Cancelled when the task using the toolbox is cancelled
+    cancellation: CancellationToken,
+
+    /// The results too long for the chat history - of the task, see
+    /// [`Toolbox::with_task`]
+    artifacts: ArtifactStore,
+
+    /// Reviews the invocations of the tools requiring an approval
+    approval_handler: Arc<RwLock<Option<Arc<dyn ApprovalHandler>>>>,
+
+    /// The tools requiring an approval - in addition to the ones declaring it
+    approval_required: Arc<RwLock<HashSet<String>>>,
+
+    /// What the invocations are permitted to do - everything if `None`
+    permissions: Arc<RwLock<Option<Permissions>>>,
+
+    /// Where the results of the cached tools are stored - nothing is cached
+    /// if `None`
+    cache: Arc<RwLock<Option<Arc<dyn CacheBackend>>>>,
+
+    /// How long the results of a tool are cached - in addition to the tools
+    /// declaring it
+    cache_ttls: Arc<RwLock<HashMap<String, Duration>>>,
+
+    /// The rate limits of the tools - shared by all the tasks using the
+    /// toolbox
+    rate_limits: Arc<RwLock<HashMap<String, Arc<TokenBucket>>>>,
+
+    /// The task the invocations are attributed to in the [`Stats`]
+    task: Option<String>,
+
+    /// The advanced tools the invocations are nested in
+    invocation_context: InvocationContext,
+
+    /// The question asked to the user - see [`Toolbox::ask_user`]
+    question: Arc<RwLock<Option<String>>>,
 }
 
 impl Debug for Toolbox {
@@ -51,12 +158,22 @@ impl Debug for Toolbox {
 
 impl Toolbox {
     /// Collect the termination messages
-    #[allow(clippy::significant_drop_tightening)]
-    #[allow(clippy::significant_drop_in_scrutinee)]
     pub async fn termination_messages(&self) -> Vec<TerminationMessage> {
+        // not holding the lock while the tools are awaited
+        let terminal_tools = self
+            .registry
+            .read()
+            .await
+            .enabled()
+            .filter_map(|(_, tool)| match tool {
+                RegisteredTool::Terminal(tool) => Some(tool.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
         let mut messages = Vec::new();
 
-        for tool in self.terminal_tools.read().await.values() {
+        for tool in terminal_tools {
             if let Some(message) = tool.take_done().await {
                 messages.push(message);
             }
@@ -68,57 +185,493 @@ impl Toolbox {
     /// Add a terminal tool
     ///
     /// A [`TerminalTool`] can terminate a chain of exchanges.
-    pub async fn add_terminal_tool(&self, tool: impl TerminalTool + 'static) {
-        let name = tool.description().name;
-        self.terminal_tools
-            .write()
+    pub async fn add_terminal_tool(
+        &self,
+        tool: impl TerminalTool + 'static,
+    ) -> Result<(), RegistryError> {
+        self.register(tool.description().name, RegisteredTool::terminal(tool))
             .await
-            .insert(name, Box::new(tool));
     }
 
     /// Check if the toolbox has at least one terminal tool
     pub async fn has_terminal_tools(&self) -> bool {
-        !self.terminal_tools.read().await.is_empty()
+        self.registry
+            .read()
+            .await
+            .enabled()
+            .any(|(_, tool)| tool.kind() == ToolKind::Terminal)
     }
 
     /// Add a tool
     ///
     /// A [`Tool`] can be invoked by an [`AdvancedTool`].
-    pub async fn add_tool(&self, tool: impl Tool + 'static) {
-        let name = tool.description().name;
-        self.tools.write().await.insert(name, Box::new(tool));
+    pub async fn add_tool(&self, tool: impl Tool + 'static) -> Result<(), RegistryError> {
+        self.register(tool.description().name, RegisteredTool::plain(tool))
+            .await
     }
 
     /// Add an advanced tool
     ///
     /// An [`AdvancedTool`] is a [`Tool`] that can invoke another tool.
-    pub async fn add_advanced_tool(&self, tool: impl AdvancedTool + 'static) {
-        let name = tool.description().name;
-        self.advanced_tools
+    pub async fn add_advanced_tool(
+        &self,
+        tool: impl AdvancedTool + 'static,
+    ) -> Result<(), RegistryError> {
+        self.register(tool.description().name, RegisteredTool::advanced(tool))
+            .await
+    }
+
+    /// Add a tool of any kind under `name` - e.g. a name in a namespace built
+    /// with [`crate::tools::registry::qualified_name`]
+    ///
+    /// Fails if a tool or an alias already uses `name`.
+    pub async fn register(
+        &self,
+        name: impl Into<String>,
+        tool: RegisteredTool,
+    ) -> Result<(), RegistryError> {
+        self.registry.write().await.register(name.into(), tool)
+    }
+
+    /// Remove a tool - and its aliases
+    pub async fn remove_tool(&self, tool_name: &str) -> Result<RegisteredTool, RegistryError> {
+        self.registry.write().await.remove(tool_name)
+    }
+
+    /// Add another name for a tool - e.g. its name in snake case
+    ///
+    /// Fails if a tool or an alias already uses `alias`.
+    pub async fn add_alias(
+        &self,
+        alias: impl Into<String>,
+        tool_name: &str,
+    ) -> Result<(), RegistryError> {
+        self.registry
             .write()
             .await
-            .insert(name, Box::new(tool));
+            .add_alias(alias.into(), tool_name)
+    }
+
+    /// Enable a tool disabled with [`Toolbox::disable`]
+    pub async fn enable(&self, tool_name: &str) -> Result<(), RegistryError> {
+        self.registry.write().await.set_enabled(tool_name, true)
+    }
+
+    /// Disable a tool - it is neither described nor invocable until it is
+    /// enabled again
+    pub async fn disable(&self, tool_name: &str) -> Result<(), RegistryError> {
+        self.registry.write().await.set_enabled(tool_name, false)
+    }
+
+    /// The name a tool is registered under - `tool_name` itself if it is not
+    /// an alias
+    async fn canonical_name(&self, tool_name: &str) -> String {
+        self.registry
+            .read()
+            .await
+            .resolve(tool_name)
+            .unwrap_or(tool_name)
+            .to_string()
+    }
+
+    /// Get a [`Toolbox`] sharing the tools of this one but enforcing its own
+    /// [`PermissionPolicy`] - e.g. for a task
+    ///
+    /// The quotas of the policy start from zero.
+    #[must_use]
+    pub fn with_permissions(&self, policy: PermissionPolicy) -> Self {
+        Self {
+            permissions: Arc::new(RwLock::new(Some(Permissions::new(policy)))),
+            ..self.clone()
+        }
+    }
+
+    /// Get a [`Toolbox`] sharing the tools and the [`Stats`] of this one but
+    /// attributing its invocations to `task` in the [`Stats`]
+    ///
+    /// Its invocations are cancelled on their own - see
+    /// [`Toolbox::cancel_invocations`] - or with the ones of this toolbox. It
+    /// has its own artifacts.
+    #[must_use]
+    pub fn with_task(&self, task: impl Into<String>) -> Self {
+        Self {
+            task: Some(task.into()),
+            ..self.scoped(self.cancellation.child_token())
+        }
+    }
+
+    /// Get a [`Toolbox`] sharing the tools, the settings and the [`Stats`] of
+    /// this one but with the state of a task of its own - its artifacts and
+    /// the question asked to its user - and cancelled with `cancellation`,
+    /// e.g. the one of a [`crate::control::ControlHandle`]
+    pub(crate) fn scoped(&self, cancellation: CancellationToken) -> Self {
+        Self {
+            cancellation,
+            artifacts: self.artifacts.for_task(),
+            question: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Get a [`Toolbox`] sharing everything with this one but allowing
+    /// `max_depth` nested invocations of advanced tools
+    #[must_use]
+    pub fn with_max_invocation_depth(&self, max_depth: usize) -> Self {
+        Self {
+            invocation_context: InvocationContext {
+                max_depth,
+                ..self.invocation_context.clone()
+            },
+            ..self.clone()
+        }
+    }
+
+    /// Get a [`Toolbox`] with only the `tool_names` of this one - e.g. for a
+    /// sub-agent
+    ///
+    /// Everything else is shared with this one - the settings, the [`Stats`]
+    /// and the context of the invocations. The unknown, the disabled and the
+    /// terminal tools are left out: the termination of a task is its own.
+    pub async fn restricted(&self, tool_names: &[String]) -> Self {
+        let mut restricted = Registry::default();
+
+        {
+            let registry = self.registry.read().await;
+            for tool_name in tool_names {
+                let Some(name) = registry.resolve(tool_name) else {
+                    continue;
+                };
+
+                match registry.get(name) {
+                    Some(entry) if entry.enabled && entry.tool.kind() != ToolKind::Terminal => {
+                        // the names are unique in this registry
+                        let _ = restricted.register(name.to_string(), entry.tool.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Self {
+            registry: Arc::new(RwLock::new(restricted)),
+            ..self.clone()
+        }
+    }
+
+    /// Get the advanced tools the invocations from this [`Toolbox`] are
+    /// nested in
+    #[must_use]
+    pub const fn invocation_context(&self) -> &InvocationContext {
+        &self.invocation_context
+    }
+
+    /// Get the task the invocations are attributed to
+    #[must_use]
+    pub fn task(&self) -> Option<&str> {
+        self.task.as_deref()
+    }
+
+    /// Ask the user a question - the task waits for the answer after the
+    /// current step, see [`crate::TaskState::AwaitingInput`]
+    ///
+    /// The tool asking puts [`PENDING_ANSWER`] in its result: it is replaced
+    /// by the answer.
+    pub async fn ask_user(&self, question: impl Into<String>) {
+        *self.question.write().await = Some(question.into());
+    }
+
+    /// Take the question asked to the user - if any
+    pub async fn take_question(&self) -> Option<String> {
+        self.question.write().await.take()
+    }
+
+    /// Set the [`PermissionPolicy`] of this [`Toolbox`] and of its clones
+    ///
+    /// The quotas of the policy start from zero. `None` permits everything.
+    pub async fn set_permissions(&self, policy: Option<PermissionPolicy>) {
+        *self.permissions.write().await = policy.map(Permissions::new);
+    }
+
+    /// Get the [`PermissionPolicy`]
+    pub async fn permissions(&self) -> Option<PermissionPolicy> {
+        self.permissions
+            .read()
+            .await
+            .as_ref()
+            .map(|p| p.policy.clone())
     }
 
-    /// Get the descriptions of the tools
+    /// Check an invocation against the [`PermissionPolicy`] without counting
+    /// it
+    async fn validate_permissions(
+        &self,
+        tool_name: &str,
+        input: &serde_yaml::Value,
+    ) -> Result<(), ToolUseError> {
+        match self.permissions.read().await.as_ref() {
+            Some(permissions) => Ok(permissions.validate(tool_name, input)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Check an invocation against the [`PermissionPolicy`] and count it
+    async fn check_permissions(
+        &self,
+        tool_name: &str,
+        input: &serde_yaml::Value,
+    ) -> Result<(), ToolUseError> {
+        match self.permissions.write().await.as_mut() {
+            Some(permissions) => Ok(permissions.check(tool_name, input)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the descriptions of the tools - by the name they are registered
+    /// under
+    ///
+    /// The disabled tools and the tools not allowed by the
+    /// [`PermissionPolicy`] are left out.
     #[allow(clippy::significant_drop_tightening)]
     #[allow(clippy::significant_drop_in_scrutinee)]
     pub async fn describe(&self) -> HashMap<String, ToolDescription> {
         let mut descriptions = HashMap::new();
 
-        for (name, tool) in self.terminal_tools.read().await.iter() {
-            descriptions.insert(name.clone(), tool.description());
+        for (name, tool) in self.registry.read().await.enabled() {
+            let mut description = tool.as_tool().description();
+            description.name.clone_from(name);
+            descriptions.insert(name.clone(), description);
         }
 
-        for (name, tool) in self.tools.read().await.iter() {
-            descriptions.insert(name.clone(), tool.description());
+        if let Some(permissions) = self.permissions.read().await.as_ref() {
+            descriptions.retain(|name, _| permissions.policy.is_allowed(name));
         }
 
-        for (name, tool) in self.advanced_tools.read().await.iter() {
-            descriptions.insert(name.clone(), tool.description());
+        descriptions
+    }
+
+    /// Check if a tool is side-effect free
+    ///
+    /// Returns `false` if the tool does not exist.
+    #[allow(clippy::significant_drop_tightening)]
+    pub async fn is_side_effect_free(&self, tool_name: &str) -> bool {
+        self.registry
+            .read()
+            .await
+            .get(tool_name)
+            .is_some_and(|entry| entry.tool.as_tool().is_side_effect_free())
+    }
+
+    /// Check if the invocations of a tool must be approved
+    ///
+    /// Either the tool declares it or it was marked with
+    /// [`Toolbox::require_approval`].
+    #[allow(clippy::significant_drop_tightening)]
+    pub async fn requires_approval(&self, tool_name: &str) -> bool {
+        let tool_name = self.canonical_name(tool_name).await;
+
+        if self.approval_required.read().await.contains(&tool_name) {
+            return true;
         }
 
-        descriptions
+        self.registry
+            .read()
+            .await
+            .get(&tool_name)
+            .is_some_and(|entry| entry.tool.as_tool().requires_approval())
+    }
+
+    /// Mark a tool as requiring an approval before each invocation
+    ///
+    /// `tool_name` can be an alias of the tool.
+    pub async fn require_approval(&self, tool_name: impl Into<String>) {
+        let tool_name = self.canonical_name(&tool_name.into()).await;
+        self.approval_required.write().await.insert(tool_name);
+    }
+
+    /// Set the [`ApprovalHandler`] reviewing the invocations of the tools
+    /// requiring an approval
+    ///
+    /// Without handler, the invocations proceed without review.
+    pub async fn set_approval_handler(&self, handler: Option<Arc<dyn ApprovalHandler>>) {
+        *self.approval_handler.write().await = handler;
+    }
+
+    /// Set how the invocations found in a message are executed
+    pub async fn set_invocation_mode(&self, mode: InvocationMode) {
+        *self.invocation_mode.write().await = mode;
+    }
+
+    /// Get how the invocations found in a message are executed
+    pub async fn invocation_mode(&self) -> InvocationMode {
+        *self.invocation_mode.read().await
+    }
+
+    /// Set how strictly the invocations found in a message are checked
+    pub async fn set_invocation_policy(&self, policy: InvocationPolicy) {
+        *self.invocation_policy.write().await = policy;
+    }
+
+    /// Get how strictly the invocations found in a message are checked
+    pub async fn invocation_policy(&self) -> InvocationPolicy {
+        self.invocation_policy.read().await.clone()
+    }
+
+    /// Set how long a tool can run when it does not declare its own timeout
+    ///
+    /// `None` means no limit.
+    pub async fn set_default_timeout(&self, timeout: Option<Duration>) {
+        *self.default_timeout.write().await = timeout;
+    }
+
+    /// Get how long a tool can run when it does not declare its own timeout
+    pub async fn default_timeout(&self) -> Option<Duration> {
+        *self.default_timeout.read().await
+    }
+
+    /// Set where the results of the cached tools are stored
+    ///
+    /// `None` disables the cache. The tools are cached if they declare it -
+    /// see [`Tool::cache_ttl`] - or with [`Toolbox::cache_tool`].
+    pub async fn set_cache(&self, cache: Option<Arc<dyn CacheBackend>>) {
+        *self.cache.write().await = cache;
+    }
+
+    /// Cache the results of a tool for `ttl` - overriding what it declares
+    ///
+    /// `tool_name` can be an alias of the tool.
+    ///
+    /// # Errors
+    ///
+    /// If the tool does not exist or has side effects - a cached invocation is
+    /// not executed. The terminal tools are never cached.
+    pub async fn cache_tool(&self, tool_name: &str, ttl: Duration) -> Result<(), cache::Error> {
+        let tool_name = self.canonical_name(tool_name).await;
+
+        match self.registry.read().await.get(&tool_name) {
+            None => return Err(cache::Error::NotFound(tool_name)),
+            Some(entry) if !is_cacheable(&entry.tool) => {
+                return Err(cache::Error::SideEffects(tool_name));
+            }
+            Some(_) => {}
+        }
+
+        self.cache_ttls.write().await.insert(tool_name, ttl);
+        Ok(())
+    }
+
+    /// Get how long the results of a tool are cached - `None` if they are not
+    pub async fn cache_ttl(&self, tool_name: &str) -> Option<Duration> {
+        let tool_name = self.canonical_name(tool_name).await;
+
+        if let Some(ttl) = self.cache_ttls.read().await.get(&tool_name) {
+            return Some(*ttl);
+        }
+
+        self.registry
+            .read()
+            .await
+            .get(&tool_name)
+            .and_then(|entry| entry.tool.as_tool().cache_ttl())
+    }
+
+    /// Limit the invocations of a tool - `None` removes the limit
+    ///
+    /// The limit is shared by all the tasks using the toolbox. When it is
+    /// exhausted, the invocations wait or fail with
+    /// [`ToolUseError::RateLimited`] - see [`RateLimit::on_exhausted`]. The
+    /// invocations answered from the cache are not limited. `tool_name` can be
+    /// an alias of the tool.
+    ///
+    /// # Errors
+    ///
+    /// If the limit is invalid - see [`RateLimit::validate`].
+    pub async fn set_rate_limit(
+        &self,
+        tool_name: impl Into<String>,
+        limit: Option<RateLimit>,
+    ) -> Result<(), rate_limit::Error> {
+        if let Some(limit) = &limit {
+            limit.validate()?;
+        }
+        let tool_name = self.canonical_name(&tool_name.into()).await;
+
+        let mut rate_limits = self.rate_limits.write().await;
+        match limit {
+            Some(limit) => {
+                rate_limits.insert(tool_name, Arc::new(TokenBucket::new(limit)));
+            }
+            None => {
+                rate_limits.remove(&tool_name);
+            }
+        }
+        drop(rate_limits);
+
+        Ok(())
+    }
+
+    /// Get the rate limit of a tool
+    pub async fn rate_limit(&self, tool_name: &str) -> Option<RateLimit> {
+        let tool_name = self.canonical_name(tool_name).await;
+        self.rate_limits
+            .read()
+            .await
+            .get(&tool_name)
+            .map(|bucket| bucket.limit())
+    }
+
+    /// Wait for the rate limit of a tool - if any
+    async fn throttle(&self, tool_name: &str) -> Result<(), ToolUseError> {
+        let Some(bucket) = self.rate_limits.read().await.get(tool_name).cloned() else {
+            return Ok(());
+        };
+
+        tokio::select! {
+            biased;
+            () = self.cancellation.cancelled() => Err(ToolUseError::Cancelled(tool_name.to_string())),
+            acquired = bucket.acquire() => acquired.map_err(|retry_after| {
+                debug!(tool_name, ?retry_after, "Rate limited");
+                ToolUseError::RateLimited {
+                    tool_name: tool_name.to_string(),
+                    retry_after,
+                }
+            }),
+        }
+    }
+
+    /// Cancel the in-flight invocations
+    ///
+    /// To be called when the task using this toolbox is cancelled. The
+    /// invocations that follow fail with [`ToolUseError::Cancelled`] - for
+    /// good: the other tasks get their own toolbox with
+    /// [`Toolbox::with_task`], cancelled with this one but not the other way
+    /// around.
+    pub fn cancel_invocations(&self) {
+        self.cancellation.cancel();
+    }
+
+    /// Check if the invocations have been cancelled
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// The token cancelling the invocations
+    pub(crate) fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Get the artifacts of the task - the tool results too long for the chat
+    /// history
+    #[must_use]
+    pub fn artifacts(&self) -> ArtifactStore {
+        self.artifacts.clone()
+    }
+
+    /// Get the names of the tools - sorted
+    pub async fn tool_names(&self) -> Vec<String> {
+        let mut names = self.describe().await.into_keys().collect::<Vec<_>>();
+        names.sort();
+        names
     }
 
     /// Reset stats
@@ -139,16 +692,99 @@ impl Toolbox {
             .entry(tool_name.to_string())
             .and_modify(|c| *c += 1)
             .or_insert(1);
+
+        if let Some(task) = &self.task {
+            *stats
+                .tasks
+                .entry(task.clone())
+                .or_default()
+                .success_count
+                .entry(tool_name.to_string())
+                .or_default() += 1;
+        }
     }
 
     /// Report a failed invocation
-    pub async fn report_error(&self, tool_name: &str) {
+    pub async fn report_error(&self, tool_name: &str, error: &ToolUseError) {
         let mut stats = self.stats.write().await;
         stats
             .error_count
             .entry(tool_name.to_string())
             .and_modify(|c| *c += 1)
             .or_insert(1);
+
+        *stats
+            .error_kinds
+            .entry(tool_name.to_string())
+            .or_default()
+            .entry(error.kind().to_string())
+            .or_default() += 1;
+
+        if let Some(task) = &self.task {
+            *stats
+                .tasks
+                .entry(task.clone())
+                .or_default()
+                .error_count
+                .entry(tool_name.to_string())
+                .or_default() += 1;
+        }
+    }
+
+    /// Report the outcome of an invocation
+    async fn report(&self, tool_name: &str, result: &Result<serde_yaml::Value, ToolUseError>) {
+        match result {
+            Ok(_) => self.report_success(tool_name).await,
+            Err(e) => self.report_error(tool_name, e).await,
+        }
+    }
+
+    /// Report the duration and the sizes of an invocation that was executed
+    async fn report_execution(
+        &self,
+        tool_name: &str,
+        input_size: usize,
+        duration: Duration,
+        result: &Result<serde_yaml::Value, ToolUseError>,
+    ) {
+        let mut stats = self.stats.write().await;
+        stats
+            .latency
+            .entry(tool_name.to_string())
+            .or_insert_with(|| Histogram::new(&LATENCY_BUCKETS))
+            .observe(duration.as_secs_f64());
+
+        #[allow(clippy::cast_precision_loss)]
+        stats
+            .input_size
+            .entry(tool_name.to_string())
+            .or_insert_with(|| Histogram::new(&SIZE_BUCKETS))
+            .observe(input_size as f64);
+
+        if let Ok(output) = result {
+            #[allow(clippy::cast_precision_loss)]
+            stats
+                .output_size
+                .entry(tool_name.to_string())
+                .or_insert_with(|| Histogram::new(&SIZE_BUCKETS))
+                .observe(yaml_size(output) as f64);
+        }
+
+        if let Some(task) = &self.task {
+            stats.tasks.entry(task.clone()).or_default().duration += duration.as_secs_f64();
+        }
+    }
+
+    /// Report whether an invocation of a cached tool was answered from the
+    /// cache
+    async fn report_cache(&self, tool_name: &str, cache_status: CacheStatus) {
+        let mut stats = self.stats.write().await;
+        let counts = match cache_status {
+            CacheStatus::Hit => &mut stats.cache_hits,
+            CacheStatus::Miss => &mut stats.cache_misses,
+        };
+        *counts.entry(tool_name.to_string()).or_default() += 1;
+        drop(stats);
     }
 
     /// Report an inexistent tool invocation
@@ -162,115 +798,310 @@ impl Toolbox {
     }
 }
 
-/// Invoke a [`Tool`] or [`AdvancedTool`] or [`TerminalTool`] from a [`Toolbox`]
-#[allow(clippy::significant_drop_tightening)]
-#[allow(clippy::significant_drop_in_scrutinee)]
-async fn invoke_from_toolbox(
-    toolbox: Toolbox,
+/// Check the parameters of an invocation against the description of the tool
+fn check_parameters(
+    description: &ToolDescription,
+    input: &serde_yaml::Value,
+) -> Result<(), ToolUseError> {
+    description
+        .parameters
+        .validate(input)
+        .map_err(ToolUseError::InvalidParameters)
+}
+
+/// Submit the invocation to the [`ApprovalHandler`] of the `toolbox` if the
+/// tool requires an approval
+///
+/// Returns the parameters to invoke the tool with.
+async fn review_invocation(
+    toolbox: &Toolbox,
     tool_name: &str,
     input: serde_yaml::Value,
 ) -> Result<serde_yaml::Value, ToolUseError> {
-    // test if the tool is an advanced tool
-    if let Some(tool) = toolbox.clone().advanced_tools.read().await.get(tool_name) {
-        let result = tool.invoke_with_toolbox(toolbox.clone(), input).await;
-
-        if result.is_ok() {
-            toolbox.report_success(tool_name).await;
-        } else {
-            toolbox.report_error(tool_name).await;
-        }
-
-        return result;
+    if !toolbox.requires_approval(tool_name).await {
+        return Ok(input);
     }
 
-    // if not, test if the tool is a terminal tool
-    {
-        let guard = toolbox.terminal_tools.read().await;
-        if let Some(tool) = guard.get(tool_name) {
-            let result = tool.invoke(input).await;
-            if result.is_ok() {
-                toolbox.report_success(tool_name).await;
-            } else {
-                toolbox.report_error(tool_name).await;
-            }
+    let Some(handler) = toolbox.approval_handler.read().await.clone() else {
+        return Ok(input);
+    };
+
+    let request = ApprovalRequest {
+        tool_name: tool_name.to_string(),
+        parameters: input.clone(),
+    };
 
-            return result;
+    match handler.review(request).await {
+        Approval::Approved => Ok(input),
+        Approval::Edited { parameters } => {
+            info!(tool_name, "Parameters edited during the review");
+            Ok(parameters)
+        }
+        Approval::Rejected { reason } => {
+            info!(tool_name, reason, "Invocation rejected during the review");
+            Err(ToolUseError::Rejected(reason))
         }
     }
+}
 
-    // otherwise, use the normal tool
-    let guard = toolbox.tools.read().await;
-    let tool = guard.get(tool_name);
+/// Size of a value once serialized - in bytes
+fn yaml_size(value: &serde_yaml::Value) -> usize {
+    serde_yaml::to_string(value).map_or(0, |s| s.len())
+}
 
-    if tool.is_none() {
-        toolbox.report_inexistent(tool_name).await;
-    }
+/// Run an `invocation` of `tool_name` until it completes, exceeds `timeout`
+/// or the invocations of the `toolbox` are cancelled
+///
+/// The duration of the invocation and the sizes of its parameters and of its
+/// result are reported in the [`Stats`].
+async fn guard_invocation(
+    toolbox: &Toolbox,
+    tool_name: &str,
+    input_size: usize,
+    timeout: Option<Duration>,
+    invocation: impl Future<Output = Result<serde_yaml::Value, ToolUseError>> + Send,
+) -> Result<serde_yaml::Value, ToolUseError> {
+    let started = Instant::now();
+
+    let invocation = async {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, invocation)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(ToolUseError::Timeout {
+                        tool_name: tool_name.to_string(),
+                        timeout,
+                    })
+                }),
+            None => invocation.await,
+        }
+    };
 
-    let tool = tool.ok_or_else(|| ToolUseError::ToolNotFound(tool_name.to_string()))?;
+    let result = tokio::select! {
+        biased;
+        () = toolbox.cancellation.cancelled() => Err(ToolUseError::Cancelled(tool_name.to_string())),
+        result = invocation => result,
+    };
+
+    toolbox
+        .report_execution(tool_name, input_size, started.elapsed(), &result)
+        .await;
 
-    let result = tool.invoke(input).await;
-    if result.is_ok() {
-        toolbox.report_success(tool_name).await;
-    } else {
-        toolbox.report_error(tool_name).await;
-    }
     result
 }
 
-/// Invoke a [`Tool`] or [`TerminalTool`] from a [`Toolbox`].
+/// Invoke a [`Tool`] or [`AdvancedTool`] or [`TerminalTool`] from a [`Toolbox`]
 ///
-/// This function is intended to be used by [`AdvancedTool`]s.
-/// It will not invoke another [`AdvancedTool`].
+/// The invocation must be permitted by the [`PermissionPolicy`] of the
+/// [`Toolbox`]. The parameters are validated against the description of the
+/// tool before the invocation. The invocation is limited by the timeout of the
+/// tool - or the default one of the [`Toolbox`] - and can be cancelled with
+/// [`Toolbox::cancel_invocations`].
 ///
-/// If you want to invoke an [`AdvancedTool`], use [`invoke_tool`].
-#[allow(clippy::significant_drop_tightening)]
+/// Like [`invoke_simple_from_toolbox`] otherwise - for the [`AdvancedTool`]s
+/// building the parameters of the tools they invoke, e.g. from templates.
 #[allow(clippy::module_name_repetitions)]
-pub async fn invoke_simple_from_toolbox(
+pub async fn invoke_from_toolbox(
     toolbox: Toolbox,
     tool_name: &str,
     input: serde_yaml::Value,
 ) -> Result<serde_yaml::Value, ToolUseError> {
-    // test if the tool is a terminal tool
-    {
-        let guard = toolbox.terminal_tools.read().await;
-        if let Some(tool) = guard.get(tool_name) {
-            let result = tool.invoke(input).await;
-            if result.is_ok() {
-                toolbox.report_success(tool_name).await;
-            } else {
-                toolbox.report_error(tool_name).await;
-            }
+    let tool_name = toolbox.canonical_name(tool_name).await;
+    let tool_name = tool_name.as_str();
+
+    let (tool, input) = prepare_invocation(&toolbox, tool_name, input, true).await?;
+    run_invocation(&toolbox, tool_name, &tool, input).await.0
+}
+
+/// Check an invocation of `tool_name` - the name it is registered under - and
+/// submit it to the review if the tool requires an approval
+///
+/// The review only happens once the invocation is permitted by the
+/// [`PermissionPolicy`] and - if `validate` is set - its parameters are valid;
+/// the parameters edited during the review are checked again. The invocation
+/// is counted in the quotas once approved. The failures are reported.
+///
+/// Returns the tool and the parameters to invoke it with.
+async fn prepare_invocation(
+    toolbox: &Toolbox,
+    tool_name: &str,
+    input: serde_yaml::Value,
+    validate: bool,
+) -> Result<(RegisteredTool, serde_yaml::Value), ToolUseError> {
+    // the unknown and the disabled tools do not count in the quotas
+    let tool = find_enabled(toolbox, tool_name).await?;
+    let description = &tool.as_tool().description();
 
-            return result;
+    let check = |input: serde_yaml::Value| async move {
+        toolbox.validate_permissions(tool_name, &input).await?;
+        if validate {
+            check_parameters(description, &input)?;
         }
-    }
+        Ok::<_, ToolUseError>(input)
+    };
 
-    // the normal tool only
-    let guard = toolbox.tools.read().await;
-    let tool = guard.get(tool_name);
+    let approved = async {
+        let input = check(input).await?;
+        let reviewed = review_invocation(toolbox, tool_name, input.clone()).await?;
+        let input = if reviewed == input {
+            input
+        } else {
+            check(reviewed).await?
+        };
 
-    if tool.is_none() {
-        toolbox.report_inexistent(tool_name).await;
+        toolbox.check_permissions(tool_name, &input).await?;
+        Ok(input)
+    };
+
+    match approved.await {
+        Ok(input) => Ok((tool, input)),
+        Err(e) => {
+            toolbox.report_error(tool_name, &e).await;
+            Err(e)
+        }
     }
+}
 
-    let tool = tool.ok_or_else(|| ToolUseError::ToolNotFound(tool_name.to_string()))?;
+/// Execute an invocation prepared with [`prepare_invocation`] and report its
+/// outcome
+///
+/// Returns whether the result was answered from the cache too.
+async fn run_invocation(
+    toolbox: &Toolbox,
+    tool_name: &str,
+    tool: &RegisteredTool,
+    input: serde_yaml::Value,
+) -> (Result<serde_yaml::Value, ToolUseError>, bool) {
+    let default_timeout = toolbox.default_timeout().await;
 
-    let result = tool.invoke(input).await;
-    if result.is_ok() {
-        toolbox.report_success(tool_name).await;
-    } else {
-        toolbox.report_error(tool_name).await;
-    }
-    result
+    let (result, cache_status) = execute(toolbox, tool_name, tool, input, default_timeout).await;
+    toolbox.report(tool_name, &result).await;
+    (result, cache_status == Some(CacheStatus::Hit))
 }
 
-/// Result of invoking a tool with [`invoke_tool`].
-#[derive(Debug, Clone)]
-pub enum InvokeResult {
-    /// No invocation found in the message
-    NoInvocationsFound {
-        /// The error that occurred
-        e: Error,
+/// Check if the results of a tool can be cached - a cached invocation is not
+/// executed
+fn is_cacheable(tool: &RegisteredTool) -> bool {
+    tool.kind() != ToolKind::Terminal && tool.as_tool().is_side_effect_free()
+}
+
+/// Execute an invocation of a tool - or answer it from the cache of the
+/// `toolbox` if the tool is cached
+///
+/// Only the successful results are cached. Returns the [`CacheStatus`] of the
+/// invocations of the cached tools.
+async fn execute(
+    toolbox: &Toolbox,
+    tool_name: &str,
+    tool: &RegisteredTool,
+    input: serde_yaml::Value,
+    default_timeout: Option<Duration>,
+) -> (Result<serde_yaml::Value, ToolUseError>, Option<CacheStatus>) {
+    let invocation = async {
+        toolbox.throttle(tool_name).await?;
+
+        let input_size = yaml_size(&input);
+        let timeout = tool.as_tool().timeout().or(default_timeout);
+        let invocation = start_invocation(toolbox, tool_name, tool, input.clone())?;
+        guard_invocation(toolbox, tool_name, input_size, timeout, invocation).await
+    };
+
+    let backend = toolbox.cache.read().await.clone();
+    let cache = match backend {
+        Some(backend) if is_cacheable(tool) => toolbox
+            .cache_ttl(tool_name)
+            .await
+            .map(|ttl| ResultCache::new(backend, ttl)),
+        _ => None,
+    };
+
+    let Some(cache) = cache else {
+        return (invocation.await, None);
+    };
+
+    let (result, status) = cache.get_or_invoke(tool_name, &input, invocation).await;
+    toolbox.report_cache(tool_name, status).await;
+    (result, Some(status))
+}
+
+/// Start an invocation of a tool
+///
+/// An [`AdvancedTool`] gets a [`Toolbox`] carrying the context of its
+/// invocation. It fails if the tool is already being invoked or if there are
+/// too many nested invocations.
+fn start_invocation<'a>(
+    toolbox: &Toolbox,
+    tool_name: &str,
+    tool: &'a RegisteredTool,
+    input: serde_yaml::Value,
+) -> Result<BoxFuture<'a, Result<serde_yaml::Value, ToolUseError>>, ToolUseError> {
+    match tool {
+        RegisteredTool::Advanced(tool) => {
+            let toolbox = Toolbox {
+                invocation_context: toolbox.invocation_context.enter(tool_name)?,
+                ..toolbox.clone()
+            };
+            Ok(tool.invoke_with_toolbox(toolbox, input))
+        }
+        tool => Ok(tool.as_tool().invoke(input)),
+    }
+}
+
+/// Get a handle on an enabled tool of the `toolbox` - the failed lookups are
+/// reported
+///
+/// The registry is not locked during the invocation of the tool - the tools can
+/// be registered or described concurrently.
+async fn find_enabled(toolbox: &Toolbox, tool_name: &str) -> Result<RegisteredTool, ToolUseError> {
+    let entry = toolbox
+        .registry
+        .read()
+        .await
+        .get(tool_name)
+        .map(|entry| (entry.enabled, entry.tool.clone()));
+
+    match entry {
+        Some((true, tool)) => Ok(tool),
+        Some((false, _)) => {
+            let e = ToolUseError::ToolDisabled(tool_name.to_string());
+            toolbox.report_error(tool_name, &e).await;
+            Err(e)
+        }
+        None => {
+            toolbox.report_inexistent(tool_name).await;
+            Err(ToolUseError::ToolNotFound(tool_name.to_string()))
+        }
+    }
+}
+
+/// Invoke a tool from a [`Toolbox`].
+///
+/// This function is intended to be used by [`AdvancedTool`]s with the
+/// [`Toolbox`] they were invoked with. Another [`AdvancedTool`] can be invoked
+/// as long as it is not already being invoked and the maximum depth of the
+/// [`InvocationContext`] is not exceeded.
+///
+/// If you want to invoke tools from a message, use [`invoke_tool`].
+#[allow(clippy::module_name_repetitions)]
+pub async fn invoke_simple_from_toolbox(
+    toolbox: Toolbox,
+    tool_name: &str,
+    input: serde_yaml::Value,
+) -> Result<serde_yaml::Value, ToolUseError> {
+    let tool_name = toolbox.canonical_name(tool_name).await;
+    let tool_name = tool_name.as_str();
+
+    let (tool, input) = prepare_invocation(&toolbox, tool_name, input, false).await?;
+    run_invocation(&toolbox, tool_name, &tool, input).await.0
+}
+
+/// Result of invoking a tool with [`invoke_tool`].
+#[derive(Debug, Clone)]
+pub enum InvokeResult {
+    /// No invocation found in the message
+    NoInvocationsFound {
+        /// The error that occurred
+        e: Error,
     },
     /// No valid invocation found in the message
     NoValidInvocationsFound {
@@ -289,6 +1120,11 @@ pub enum InvokeResult {
         extracted_input: String,
         /// The result of the invocation
         result: String,
+        /// The violations of the [`InvocationPolicy`] that are only warnings
+        warnings: Vec<Error>,
+        /// The result was answered from the cache - see
+        /// [`Toolbox::cache_tool`]
+        cached: bool,
     },
     /// Error during invocation
     Error {
@@ -300,16 +1136,28 @@ pub enum InvokeResult {
         extracted_input: String,
         /// The error that occurred
         e: ToolUseError,
+        /// The violations of the [`InvocationPolicy`] that are only warnings
+        warnings: Vec<Error>,
+    },
+    /// Several invocations were executed - with [`InvocationMode::All`]
+    Multiple {
+        /// The number of invocations found in the message
+        invocation_count: usize,
+        /// The results of the executed invocations - in order. Either
+        /// [`InvokeResult::Success`] or [`InvokeResult::Error`].
+        results: Vec<Self>,
     },
 }
 
 /// Try to find the tool invocation from the chat message and invoke the
 /// corresponding tool.
 ///
-/// If multiple tool invocations are found, only the first one is used.
+/// With [`InvocationMode::FirstOnly`], if multiple tool invocations are found,
+/// only the first one is used. With [`InvocationMode::All`], all of them are
+/// used and [`InvokeResult::Multiple`] is returned.
 #[tracing::instrument(skip(toolbox, data))]
 pub async fn invoke_tool(toolbox: Toolbox, data: &str) -> InvokeResult {
-    let tool_invocations = match tools::invocation::find_all(data) {
+    let mut tool_invocations = match tools::invocation::find_all(data) {
         Ok(invocations) => invocations,
         Err(e) => return InvokeResult::NoInvocationsFound { e },
     };
@@ -319,24 +1167,127 @@ pub async fn invoke_tool(toolbox: Toolbox, data: &str) -> InvokeResult {
         tool_invocations.yaml_block_count, invocation_count
     );
 
-    // FUTURE(ssoudan) feature to control this
-    // if more than one tool_invocations are found, we return an error
-    // if tool_invocations.len() > 1 {
-    //     return Err(ToolUseError::TooManyInvocationFound);
-    // }
+    // the aliases are known names too
+    for invocation in &mut tool_invocations.invocations {
+        invocation.tool_name = toolbox.canonical_name(&invocation.tool_name).await;
+    }
 
-    // FUTURE(ssoudan) invoke corresponding tools one by one. Fail on first error.
-    // FUTURE(ssoudan) document this in the initial prompt
+    let policy = toolbox.invocation_policy().await;
+    let tool_names = toolbox.tool_names().await;
 
-    let invocation = match tools::choose_invocation(tool_invocations) {
-        Ok(invocation) => invocation,
-        Err(e) => {
-            return InvokeResult::NoValidInvocationsFound {
-                e,
+    match toolbox.invocation_mode().await {
+        InvocationMode::FirstOnly => {
+            let invocation = match tools::choose_invocation(tool_invocations, &policy, &tool_names)
+            {
+                Ok(invocation) => invocation,
+                Err(e) => return invalid_invocations(&toolbox, e, invocation_count).await,
+            };
+
+            invoke_one(toolbox, invocation, invocation_count).await
+        }
+        InvocationMode::All {
+            concurrent,
+            stop_on_error,
+        } => {
+            let invocations =
+                match tools::choose_all_invocations(tool_invocations, &policy, &tool_names) {
+                    Ok(invocations) => invocations,
+                    Err(e) => return invalid_invocations(&toolbox, e, invocation_count).await,
+                };
+
+            invoke_all(
+                toolbox,
+                invocations,
                 invocation_count,
+                concurrent,
+                stop_on_error,
+            )
+            .await
+        }
+    }
+}
+
+/// Build the [`InvokeResult`] for invocations rejected with `e`.
+async fn invalid_invocations(toolbox: &Toolbox, e: Error, invocation_count: usize) -> InvokeResult {
+    if let Error::UnknownTool { tool_name, .. } = &e {
+        toolbox.report_inexistent(tool_name).await;
+    }
+
+    InvokeResult::NoValidInvocationsFound {
+        e,
+        invocation_count,
+    }
+}
+
+/// Invoke the tools of the `invocations` in order.
+///
+/// If `concurrent` is set, consecutive invocations of side-effect-free tools
+/// are executed concurrently. If `stop_on_error` is set, the invocations after
+/// the first failed one (or the first failed batch of concurrent ones) are not
+/// executed.
+async fn invoke_all(
+    toolbox: Toolbox,
+    invocations: Vec<CheckedInvocation>,
+    invocation_count: usize,
+    concurrent: bool,
+    stop_on_error: bool,
+) -> InvokeResult {
+    // group the invocations in batches that can be executed concurrently
+    let mut batches: Vec<Vec<CheckedInvocation>> = vec![];
+    let mut batch_is_side_effect_free = false;
+    for invocation in invocations {
+        let side_effect_free = concurrent
+            && toolbox
+                .is_side_effect_free(&invocation.invocation.tool_name)
+                .await;
+
+        match batches.last_mut() {
+            Some(batch) if side_effect_free && batch_is_side_effect_free => {
+                batch.push(invocation);
             }
+            _ => batches.push(vec![invocation]),
         }
-    };
+
+        batch_is_side_effect_free = side_effect_free;
+    }
+
+    let mut results = vec![];
+    for batch in batches {
+        let batch_results = futures::future::join_all(
+            batch
+                .into_iter()
+                .map(|invocation| invoke_one(toolbox.clone(), invocation, invocation_count)),
+        )
+        .await;
+
+        let failed = batch_results
+            .iter()
+            .any(|r| matches!(r, InvokeResult::Error { .. }));
+
+        results.extend(batch_results);
+
+        if failed && stop_on_error {
+            debug!("Stopping at the first error");
+            break;
+        }
+    }
+
+    InvokeResult::Multiple {
+        invocation_count,
+        results,
+    }
+}
+
+/// Invoke the tool of a single `invocation`.
+async fn invoke_one(
+    toolbox: Toolbox,
+    invocation: CheckedInvocation,
+    invocation_count: usize,
+) -> InvokeResult {
+    let CheckedInvocation {
+        invocation,
+        warnings,
+    } = invocation;
 
     // We found an invocation, let's invoke the tool
     debug!(tool_name = invocation.tool_name, "Invocation found");
@@ -344,14 +1295,26 @@ pub async fn invoke_tool(toolbox: Toolbox, data: &str) -> InvokeResult {
     let tool_name = invocation.tool_name.clone();
     let input = invocation.parameters;
 
-    let extracted_input = serde_yaml::to_string(&input).unwrap_or_else(|_| {
-        format!(
-            "Failed to serialize input for tool {}",
-            invocation.tool_name
-        )
-    });
+    let serialize_input = |input: &serde_yaml::Value| {
+        serde_yaml::to_string(input).unwrap_or_else(|_| {
+            format!(
+                "Failed to serialize input for tool {}",
+                invocation.tool_name
+            )
+        })
+    };
+
+    let mut extracted_input = serialize_input(&input);
 
-    let result = invoke_from_toolbox(toolbox, &tool_name, input.clone()).await;
+    let canonical_name = toolbox.canonical_name(&tool_name).await;
+    let (result, cached) = match prepare_invocation(&toolbox, &canonical_name, input, true).await {
+        Ok((tool, input)) => {
+            // the parameters might have been edited during the review
+            extracted_input = serialize_input(&input);
+            run_invocation(&toolbox, &canonical_name, &tool, input).await
+        }
+        Err(e) => (Err(e), false),
+    };
 
     match result {
         Ok(output) => {
@@ -362,11 +1325,16 @@ pub async fn invoke_tool(toolbox: Toolbox, data: &str) -> InvokeResult {
                 )
             });
 
+            // too long results are replaced by a preview
+            let result = toolbox.artifacts.shrink(&tool_name, result).await;
+
             InvokeResult::Success {
                 tool_name,
                 extracted_input,
                 invocation_count,
                 result,
+                warnings,
+                cached,
             }
         }
         Err(e) => InvokeResult::Error {
@@ -374,6 +1342,921 @@ pub async fn invoke_tool(toolbox: Toolbox, data: &str) -> InvokeResult {
             extracted_input,
             invocation_count,
             e,
+            warnings,
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::tools::registry::qualified_name;
+    use crate::tools::Format;
+
+    /// A tool that never completes in time
+    struct SleepyTool {
+        timeout: Option<Duration>,
+    }
+
+    #[async_trait::async_trait]
+    impl Tool for SleepyTool {
+        fn description(&self) -> ToolDescription {
+            ToolDescription {
+                name: "Sleepy".to_string(),
+                description: "Sleep for a long time".to_string(),
+                parameters: Format::default(),
+                responses_content: Format::default(),
+            }
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.timeout
+        }
+
+        async fn invoke(&self, _input: Value) -> Result<Value, ToolUseError> {
+            tokio::time::sleep(Duration::from_secs(100)).await;
+            Ok(Value::Null)
+        }
+    }
+
+    #[tokio::test]
+    async fn tool_timeout_is_enforced() {
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_tool(SleepyTool {
+                timeout: Some(Duration::from_millis(10)),
+            })
+            .await
+            .unwrap();
+
+        let e = invoke_simple_from_toolbox(toolbox.clone(), "Sleepy", Value::Null)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(e, ToolUseError::Timeout { ref tool_name, .. } if tool_name == "Sleepy"));
+        assert_eq!(toolbox.stats().await.error_count.get("Sleepy"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn default_timeout_is_enforced() {
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_tool(SleepyTool { timeout: None })
+            .await
+            .unwrap();
+        toolbox
+            .set_default_timeout(Some(Duration::from_millis(10)))
+            .await;
+
+        let e = invoke_from_toolbox(toolbox, "Sleepy", Value::Null)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(e, ToolUseError::Timeout { timeout, .. } if timeout == Duration::from_millis(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn invocations_are_cancelled() {
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_tool(SleepyTool { timeout: None })
+            .await
+            .unwrap();
+
+        let invocation = tokio::spawn(invoke_from_toolbox(toolbox.clone(), "Sleepy", Value::Null));
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        toolbox.cancel_invocations();
+
+        let e = invocation.await.unwrap().unwrap_err();
+        assert!(matches!(e, ToolUseError::Cancelled(ref tool_name) if tool_name == "Sleepy"));
+        assert!(toolbox.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn tasks_are_cancelled_on_their_own() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+
+        let job_1 = toolbox.with_task("job-1");
+        let job_2 = toolbox.with_task("job-2");
+
+        job_1.cancel_invocations();
+        assert!(matches!(
+            invoke_from_toolbox(job_1, "Echo", Value::Null).await,
+            Err(ToolUseError::Cancelled(_))
+        ));
+        assert!(invoke_from_toolbox(job_2.clone(), "Echo", Value::Null)
+            .await
+            .is_ok());
+        assert!(!toolbox.is_cancelled());
+
+        // a new task is not affected either
+        assert!(
+            invoke_from_toolbox(toolbox.with_task("job-3"), "Echo", Value::Null)
+                .await
+                .is_ok()
+        );
+
+        // the tasks are cancelled with the toolbox they were created from
+        toolbox.cancel_invocations();
+        assert!(job_2.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn tasks_have_their_own_questions() {
+        let toolbox = Toolbox::default();
+        let job_1 = toolbox.with_task("job-1");
+        let job_2 = toolbox.with_task("job-2");
+
+        job_1.ask_user("Which one?").await;
+
+        assert_eq!(job_2.take_question().await, None);
+        assert_eq!(toolbox.take_question().await, None);
+        assert_eq!(job_1.take_question().await.as_deref(), Some("Which one?"));
+    }
+
+    /// A tool that returns its parameters
+    struct EchoTool {}
+
+    #[async_trait::async_trait]
+    impl Tool for EchoTool {
+        fn description(&self) -> ToolDescription {
+            ToolDescription {
+                name: "Echo".to_string(),
+                description: "Return the parameters".to_string(),
+                parameters: Format::default(),
+                responses_content: Format::default(),
+            }
+        }
+
+        fn requires_approval(&self) -> bool {
+            true
+        }
+
+        async fn invoke(&self, input: Value) -> Result<Value, ToolUseError> {
+            Ok(input)
+        }
+    }
+
+    /// Always gives the same decision
+    struct FixedApprovalHandler(Approval);
+
+    #[async_trait::async_trait]
+    impl ApprovalHandler for FixedApprovalHandler {
+        async fn review(&self, request: ApprovalRequest) -> Approval {
+            assert_eq!(request.tool_name, "Echo");
+            self.0.clone()
+        }
+    }
+
+    async fn echo_with_approval(approval: Approval) -> Result<Value, ToolUseError> {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+        toolbox
+            .set_approval_handler(Some(Arc::new(FixedApprovalHandler(approval))))
+            .await;
+
+        invoke_simple_from_toolbox(toolbox, "Echo", Value::String("hello".to_string())).await
+    }
+
+    #[tokio::test]
+    async fn approved_invocations_proceed() {
+        let result = echo_with_approval(Approval::Approved).await.unwrap();
+        assert_eq!(result, Value::String("hello".to_string()));
+    }
+
+    #[tokio::test]
+    async fn edited_invocations_use_the_new_parameters() {
+        let result = echo_with_approval(Approval::Edited {
+            parameters: Value::String("bye".to_string()),
+        })
+        .await
+        .unwrap();
+        assert_eq!(result, Value::String("bye".to_string()));
+    }
+
+    #[tokio::test]
+    async fn rejected_invocations_fail_with_the_reason() {
+        let e = echo_with_approval(Approval::Rejected {
+            reason: "not now".to_string(),
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(e.to_string(), "The Action was rejected: not now");
+    }
+
+    #[tokio::test]
+    async fn tools_can_be_marked_as_requiring_approval() {
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_tool(SleepyTool { timeout: None })
+            .await
+            .unwrap();
+        assert!(!toolbox.requires_approval("Sleepy").await);
+
+        toolbox.require_approval("Sleepy").await;
+        assert!(toolbox.requires_approval("Sleepy").await);
+
+        // by alias too
+        toolbox.add_tool(CountingTool::default()).await.unwrap();
+        toolbox.add_alias("counting", "Counting").await.unwrap();
+        toolbox.require_approval("counting").await;
+        assert!(toolbox.requires_approval("Counting").await);
+    }
+
+    /// A tool requiring an approval and a `text` parameter
+    struct GuardedTool {}
+
+    #[async_trait::async_trait]
+    impl Tool for GuardedTool {
+        fn description(&self) -> ToolDescription {
+            ToolDescription {
+                name: "Guarded".to_string(),
+                description: "Return the parameters".to_string(),
+                parameters: Format {
+                    fields: vec![crate::tools::FieldFormat {
+                        name: "text".to_string(),
+                        r#type: "str".to_string(),
+                        optional: false,
+                        description: "The text".to_string(),
+                    }],
+                },
+                responses_content: Format::default(),
+            }
+        }
+
+        fn requires_approval(&self) -> bool {
+            true
+        }
+
+        async fn invoke(&self, input: Value) -> Result<Value, ToolUseError> {
+            Ok(input)
+        }
+    }
+
+    /// Gives the same decision and counts the reviews
+    #[derive(Default)]
+    struct CountingApprovalHandler {
+        approval: Option<Approval>,
+        reviews: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl ApprovalHandler for CountingApprovalHandler {
+        async fn review(&self, _request: ApprovalRequest) -> Approval {
+            self.reviews
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.approval.clone().unwrap_or(Approval::Approved)
+        }
+    }
+
+    #[tokio::test]
+    async fn only_valid_invocations_are_reviewed() {
+        let handler = Arc::new(CountingApprovalHandler {
+            approval: Some(Approval::Edited {
+                parameters: serde_yaml::from_str("{txt: bye}").unwrap(),
+            }),
+            ..CountingApprovalHandler::default()
+        });
+        let reviews = || handler.reviews.load(std::sync::atomic::Ordering::SeqCst);
+
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(GuardedTool {}).await.unwrap();
+        toolbox.set_approval_handler(Some(handler.clone())).await;
+
+        // invalid parameters
+        let data = "```yaml\ntool_name: Guarded\nparameters:\n  txt: hello\n```";
+        assert!(matches!(
+            invoke_tool(toolbox.clone(), data).await,
+            InvokeResult::Error {
+                e: ToolUseError::InvalidParameters(_),
+                ..
+            }
+        ));
+        assert_eq!(reviews(), 0);
+
+        // not permitted
+        let task_toolbox = toolbox.with_permissions(PermissionPolicy {
+            quotas: HashMap::from([("Guarded".to_string(), 0)]),
+            ..PermissionPolicy::default()
+        });
+        let data = "```yaml\ntool_name: Guarded\nparameters:\n  text: hello\n```";
+        assert!(matches!(
+            invoke_tool(task_toolbox, data).await,
+            InvokeResult::Error {
+                e: ToolUseError::PermissionDenied(_),
+                ..
+            }
+        ));
+        assert_eq!(reviews(), 0);
+
+        // the edited parameters are checked too
+        assert!(matches!(
+            invoke_tool(toolbox.clone(), data).await,
+            InvokeResult::Error {
+                e: ToolUseError::InvalidParameters(_),
+                ..
+            }
+        ));
+        assert_eq!(reviews(), 1);
+        assert_eq!(toolbox.stats().await.success_count.get("Guarded"), None);
+    }
+
+    #[tokio::test]
+    async fn permissions_are_per_task() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+
+        let task_toolbox = toolbox.with_permissions(PermissionPolicy {
+            quotas: HashMap::from([("Echo".to_string(), 1)]),
+            ..PermissionPolicy::default()
+        });
+
+        assert!(
+            invoke_simple_from_toolbox(task_toolbox.clone(), "Echo", Value::Null)
+                .await
+                .is_ok()
+        );
+        let e = invoke_simple_from_toolbox(task_toolbox.clone(), "Echo", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(matches!(e, ToolUseError::PermissionDenied(_)));
+
+        // the invocations of unknown tools are not counted
+        let task_toolbox = toolbox.with_permissions(PermissionPolicy {
+            quotas: HashMap::from([("Echo".to_string(), 1), ("Unknown".to_string(), 1)]),
+            ..PermissionPolicy::default()
+        });
+        for _ in 0..2 {
+            assert!(matches!(
+                invoke_simple_from_toolbox(task_toolbox.clone(), "Unknown", Value::Null).await,
+                Err(ToolUseError::ToolNotFound(_))
+            ));
+        }
+        toolbox.disable("Echo").await.unwrap();
+        assert!(matches!(
+            invoke_simple_from_toolbox(task_toolbox.clone(), "Echo", Value::Null).await,
+            Err(ToolUseError::ToolDisabled(_))
+        ));
+        toolbox.enable("Echo").await.unwrap();
+        assert!(
+            invoke_simple_from_toolbox(task_toolbox.clone(), "Echo", Value::Null)
+                .await
+                .is_ok()
+        );
+
+        // the original toolbox is not constrained
+        assert!(
+            invoke_simple_from_toolbox(toolbox.clone(), "Echo", Value::Null)
+                .await
+                .is_ok()
+        );
+
+        // denied tools are hidden
+        toolbox
+            .set_permissions(Some(PermissionPolicy {
+                deny: HashSet::from(["Echo".to_string()]),
+                ..PermissionPolicy::default()
+            }))
+            .await;
+        assert!(toolbox.tool_names().await.is_empty());
+        assert_eq!(task_toolbox.tool_names().await, vec!["Echo".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn invocations_are_measured_and_attributed_to_tasks() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+        toolbox
+            .add_tool(SleepyTool { timeout: None })
+            .await
+            .unwrap();
+        toolbox
+            .set_default_timeout(Some(Duration::from_millis(10)))
+            .await;
+
+        let task_toolbox = toolbox.with_task("job-1");
+        assert_eq!(task_toolbox.task(), Some("job-1"));
+
+        invoke_simple_from_toolbox(task_toolbox.clone(), "Echo", Value::Null)
+            .await
+            .unwrap();
+        invoke_simple_from_toolbox(task_toolbox.clone(), "Sleepy", Value::Null)
+            .await
+            .unwrap_err();
+        invoke_simple_from_toolbox(toolbox.clone(), "Echo", Value::Null)
+            .await
+            .unwrap();
+
+        let stats = toolbox.stats().await;
+        assert_eq!(stats.success_count["Echo"], 2);
+        assert_eq!(stats.latency["Echo"].count, 2);
+        assert_eq!(stats.input_size["Echo"].count, 2);
+        assert_eq!(stats.output_size["Echo"].count, 2);
+        assert_eq!(stats.latency["Sleepy"].count, 1);
+        assert!(!stats.output_size.contains_key("Sleepy"));
+        assert_eq!(stats.error_kinds["Sleepy"]["Timeout"], 1);
+
+        let task = &stats.tasks["job-1"];
+        assert_eq!(task.success_count["Echo"], 1);
+        assert_eq!(task.error_count["Sleepy"], 1);
+        assert!(task.duration >= 0.01);
+    }
+
+    #[tokio::test]
+    async fn tool_names_are_unique() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+
+        // whatever the kind of tool
+        assert_eq!(
+            toolbox
+                .register("Echo", RegisteredTool::plain(SleepyTool { timeout: None }))
+                .await,
+            Err(RegistryError::AlreadyRegistered("Echo".to_string()))
+        );
+
+        // aliases included
+        toolbox.add_alias("echo", "Echo").await.unwrap();
+        assert_eq!(
+            toolbox
+                .register("echo", RegisteredTool::plain(SleepyTool { timeout: None }))
+                .await,
+            Err(RegistryError::AlreadyRegistered("echo".to_string()))
+        );
+        assert_eq!(
+            toolbox.add_alias("other", "Unknown").await,
+            Err(RegistryError::NotFound("Unknown".to_string()))
+        );
+
+        // namespaced names do not collide
+        toolbox
+            .register(
+                qualified_name("test", "Echo"),
+                RegisteredTool::plain(EchoTool {}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            toolbox.tool_names().await,
+            vec!["Echo".to_string(), "test.Echo".to_string()]
+        );
+        assert_eq!(toolbox.describe().await["test.Echo"].name, "test.Echo");
+    }
+
+    #[tokio::test]
+    async fn tools_are_invoked_by_alias() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+        toolbox.add_alias("echo", "Echo").await.unwrap();
+
+        let input = Value::String("hello".to_string());
+        assert_eq!(
+            invoke_simple_from_toolbox(toolbox.clone(), "echo", input.clone())
+                .await
+                .unwrap(),
+            input
+        );
+        assert_eq!(toolbox.stats().await.success_count["Echo"], 1);
+        assert!(toolbox.requires_approval("echo").await);
+
+        // from the messages too - even when the unknown names are rejected
+        toolbox
+            .set_invocation_policy(InvocationPolicy {
+                suggest_tool_names: true,
+                ..InvocationPolicy::default()
+            })
+            .await;
+        let data = "```yaml\ntool_name: echo\nparameters: hello\n```";
+        let InvokeResult::Success {
+            tool_name, result, ..
+        } = invoke_tool(toolbox.clone(), data).await
+        else {
+            panic!("the alias should be invocable");
+        };
+        assert_eq!(tool_name, "Echo");
+        assert_eq!(result, "hello\n");
+        assert_eq!(toolbox.stats().await.success_count["Echo"], 2);
+
+        // the aliases go with the tool
+        toolbox.remove_tool("echo").await.unwrap();
+        assert!(toolbox.tool_names().await.is_empty());
+        assert!(matches!(
+            invoke_simple_from_toolbox(toolbox.clone(), "echo", input).await,
+            Err(ToolUseError::ToolNotFound(_))
+        ));
+        assert_eq!(
+            toolbox.remove_tool("Echo").await.err(),
+            Some(RegistryError::NotFound("Echo".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_tools_are_hidden_and_not_invocable() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+
+        toolbox.disable("Echo").await.unwrap();
+        assert!(toolbox.describe().await.is_empty());
+        assert!(matches!(
+            invoke_simple_from_toolbox(toolbox.clone(), "Echo", Value::Null).await,
+            Err(ToolUseError::ToolDisabled(_))
+        ));
+
+        toolbox.enable("Echo").await.unwrap();
+        assert_eq!(toolbox.tool_names().await, vec!["Echo".to_string()]);
+        assert!(
+            invoke_simple_from_toolbox(toolbox.clone(), "Echo", Value::Null)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn restricted_toolboxes_only_have_the_given_tools() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+        toolbox.add_alias("echo", "Echo").await.unwrap();
+        toolbox
+            .add_tool(SleepyTool { timeout: None })
+            .await
+            .unwrap();
+
+        let restricted = toolbox
+            .restricted(&["echo".to_string(), "Unknown".to_string()])
+            .await;
+        assert_eq!(restricted.tool_names().await, vec!["Echo".to_string()]);
+
+        // the stats are shared
+        invoke_simple_from_toolbox(restricted, "Echo", Value::Null)
+            .await
+            .unwrap();
+        assert_eq!(toolbox.stats().await.success_count["Echo"], 1);
+
+        toolbox.disable("Echo").await.unwrap();
+        assert!(toolbox
+            .restricted(&["Echo".to_string()])
+            .await
+            .tool_names()
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn tools_can_be_registered_during_an_invocation() {
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_tool(SleepyTool { timeout: None })
+            .await
+            .unwrap();
+
+        let invocation = tokio::spawn(invoke_simple_from_toolbox(
+            toolbox.clone(),
+            "Sleepy",
+            Value::Null,
+        ));
+        // let the invocation start
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!invocation.is_finished());
+
+        let registration = async {
+            toolbox.add_tool(EchoTool {}).await.unwrap();
+            toolbox.describe().await
+        };
+        let descriptions = tokio::time::timeout(Duration::from_secs(1), registration)
+            .await
+            .expect("the registry is locked during the invocation");
+        assert_eq!(descriptions.len(), 2);
+        assert!(!invocation.is_finished());
+
+        toolbox.cancel_invocations();
+        assert!(matches!(
+            invocation.await.unwrap(),
+            Err(ToolUseError::Cancelled(_))
+        ));
+    }
+
+    /// An advanced tool invoking another tool
+    struct CallerTool {
+        name: &'static str,
+        callee: &'static str,
+    }
+
+    #[async_trait::async_trait]
+    impl Tool for CallerTool {
+        fn description(&self) -> ToolDescription {
+            ToolDescription {
+                name: self.name.to_string(),
+                description: format!("Invoke {}", self.callee),
+                parameters: Format::default(),
+                responses_content: Format::default(),
+            }
+        }
+
+        async fn invoke(&self, _input: Value) -> Result<Value, ToolUseError> {
+            Err(ToolUseError::InvocationFailed(
+                "Requires a toolbox".to_string(),
+            ))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AdvancedTool for CallerTool {
+        async fn invoke_with_toolbox(
+            &self,
+            toolbox: Toolbox,
+            input: Value,
+        ) -> Result<Value, ToolUseError> {
+            invoke_simple_from_toolbox(toolbox, self.callee, input).await
+        }
+    }
+
+    async fn nested_toolbox(toolbox: Toolbox, inner_callee: &'static str) -> Toolbox {
+        toolbox
+            .add_advanced_tool(CallerTool {
+                name: "Outer",
+                callee: "Inner",
+            })
+            .await
+            .unwrap();
+        toolbox
+            .add_advanced_tool(CallerTool {
+                name: "Inner",
+                callee: inner_callee,
+            })
+            .await
+            .unwrap();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+        toolbox
+    }
+
+    #[tokio::test]
+    async fn advanced_tools_can_be_nested() {
+        let toolbox = nested_toolbox(Toolbox::default(), "Echo").await;
+
+        let input = Value::String("hello".to_string());
+        assert_eq!(
+            invoke_from_toolbox(toolbox.clone(), "Outer", input.clone())
+                .await
+                .unwrap(),
+            input
+        );
+        assert!(toolbox.invocation_context().chain.is_empty());
+    }
+
+    #[tokio::test]
+    async fn nested_invocations_cannot_cycle() {
+        let toolbox = nested_toolbox(Toolbox::default(), "Outer").await;
+
+        let e = invoke_from_toolbox(toolbox.clone(), "Outer", Value::Null)
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Invocation cycle: Outer -> Inner -> Outer");
+        // the rejected invocation and the one it was nested in
+        assert_eq!(
+            toolbox.stats().await.error_kinds["Outer"]["InvocationCycle"],
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn nested_invocations_are_limited() {
+        let toolbox = nested_toolbox(Toolbox::default().with_max_invocation_depth(1), "Echo").await;
+
+        let e = invoke_from_toolbox(toolbox, "Outer", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e,
+            ToolUseError::MaxDepthExceeded { max_depth: 1, .. }
+        ));
+    }
+
+    /// A tool counting its invocations
+    #[derive(Default)]
+    struct CountingTool {
+        invocations: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Tool for CountingTool {
+        fn description(&self) -> ToolDescription {
+            ToolDescription {
+                name: "Counting".to_string(),
+                description: "Count the invocations".to_string(),
+                parameters: Format::default(),
+                responses_content: Format::default(),
+            }
+        }
+
+        fn is_side_effect_free(&self) -> bool {
+            true
+        }
+
+        async fn invoke(&self, _input: Value) -> Result<Value, ToolUseError> {
+            let n = self
+                .invocations
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(Value::from(n))
+        }
+    }
+
+    #[tokio::test]
+    async fn results_of_cached_tools_are_reused() {
+        let toolbox = Toolbox::default();
+        let tool = CountingTool::default();
+        let invocations = tool.invocations.clone();
+        toolbox.add_tool(tool).await.unwrap();
+        toolbox
+            .set_cache(Some(Arc::new(crate::tools::cache::MemoryCache::default())))
+            .await;
+
+        // not cached until opted in
+        let input: Value = serde_yaml::from_str("{a: 1, b: 2}").unwrap();
+        invoke_simple_from_toolbox(toolbox.clone(), "Counting", input.clone())
+            .await
+            .unwrap();
+        assert_eq!(toolbox.stats().await.cache_misses.get("Counting"), None);
+
+        toolbox
+            .cache_tool("Counting", Duration::from_mins(1))
+            .await
+            .unwrap();
+        let first = invoke_simple_from_toolbox(toolbox.clone(), "Counting", input)
+            .await
+            .unwrap();
+        let reordered = serde_yaml::from_str("{b: 2, a: 1}").unwrap();
+        let second = invoke_simple_from_toolbox(toolbox.clone(), "Counting", reordered)
+            .await
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(invocations.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        let stats = toolbox.stats().await;
+        assert_eq!(stats.cache_hits.get("Counting"), Some(&1));
+        assert_eq!(stats.cache_misses.get("Counting"), Some(&1));
+        assert_eq!(stats.success_count.get("Counting"), Some(&3));
+    }
+
+    #[tokio::test]
+    async fn expired_results_are_not_reused() {
+        let toolbox = Toolbox::default();
+        let tool = CountingTool::default();
+        let invocations = tool.invocations.clone();
+        toolbox.add_tool(tool).await.unwrap();
+        toolbox
+            .set_cache(Some(Arc::new(crate::tools::cache::MemoryCache::default())))
+            .await;
+        toolbox
+            .cache_tool("Counting", Duration::ZERO)
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            invoke_simple_from_toolbox(toolbox.clone(), "Counting", Value::Null)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(invocations.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(toolbox.stats().await.cache_misses.get("Counting"), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn tools_are_cached_by_alias() {
+        let toolbox = Toolbox::default();
+        let tool = CountingTool::default();
+        let invocations = tool.invocations.clone();
+        toolbox.add_tool(tool).await.unwrap();
+        toolbox.add_alias("counting", "Counting").await.unwrap();
+        toolbox
+            .set_cache(Some(Arc::new(crate::tools::cache::MemoryCache::default())))
+            .await;
+
+        toolbox
+            .cache_tool("counting", Duration::from_mins(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            toolbox.cache_ttl("Counting").await,
+            Some(Duration::from_mins(1))
+        );
+
+        let data = "```yaml\ntool_name: Counting\nparameters:\n  a: 1\n```";
+        let mut cached = vec![];
+        for _ in 0..2 {
+            let InvokeResult::Success { cached: hit, .. } =
+                invoke_tool(toolbox.clone(), data).await
+            else {
+                panic!("the tool should be invoked");
+            };
+            cached.push(hit);
+        }
+
+        // the hits are reported
+        assert_eq!(cached, vec![false, true]);
+        assert_eq!(invocations.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn tools_with_side_effects_are_not_cached() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(EchoTool {}).await.unwrap();
+
+        assert_eq!(
+            toolbox.cache_tool("Echo", Duration::from_mins(1)).await,
+            Err(cache::Error::SideEffects("Echo".to_string()))
+        );
+        assert_eq!(
+            toolbox.cache_tool("Unknown", Duration::from_mins(1)).await,
+            Err(cache::Error::NotFound("Unknown".to_string()))
+        );
+        assert_eq!(toolbox.cache_ttl("Echo").await, None);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_are_shared_by_the_tasks() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(CountingTool::default()).await.unwrap();
+        toolbox
+            .set_rate_limit("Counting", Some("2/3600:fail".parse().unwrap()))
+            .await
+            .unwrap();
+
+        for task in ["job-1", "job-2"] {
+            invoke_simple_from_toolbox(toolbox.with_task(task), "Counting", Value::Null)
+                .await
+                .unwrap();
+        }
+
+        let e = invoke_simple_from_toolbox(toolbox.with_task("job-3"), "Counting", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(e, ToolUseError::RateLimited { ref tool_name, .. } if tool_name == "Counting")
+        );
+
+        toolbox.set_rate_limit("Counting", None).await.unwrap();
+        invoke_simple_from_toolbox(toolbox.clone(), "Counting", Value::Null)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rate_limits_are_set_by_alias() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(CountingTool::default()).await.unwrap();
+        toolbox.add_alias("counting", "Counting").await.unwrap();
+        toolbox
+            .set_rate_limit("counting", Some("1/3600:fail".parse().unwrap()))
+            .await
+            .unwrap();
+        assert!(toolbox.rate_limit("Counting").await.is_some());
+
+        invoke_simple_from_toolbox(toolbox.clone(), "Counting", Value::Null)
+            .await
+            .unwrap();
+        let e = invoke_simple_from_toolbox(toolbox.clone(), "counting", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(matches!(e, ToolUseError::RateLimited { .. }));
+
+        toolbox.set_rate_limit("counting", None).await.unwrap();
+        assert!(toolbox.rate_limit("Counting").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_rate_limits_are_rejected() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(CountingTool::default()).await.unwrap();
+
+        let limit = RateLimit {
+            capacity: 0,
+            period_secs: 60,
+            on_exhausted: rate_limit::OnExhausted::Fail,
+        };
+        assert_eq!(
+            toolbox.set_rate_limit("Counting", Some(limit)).await,
+            Err(rate_limit::Error::ZeroCapacity)
+        );
+
+        let limit = RateLimit {
+            capacity: 1,
+            period_secs: 0,
+            ..limit
+        };
+        assert_eq!(
+            toolbox.set_rate_limit("Counting", Some(limit)).await,
+            Err(rate_limit::Error::ZeroPeriod)
+        );
+        assert!(toolbox.rate_limit("Counting").await.is_none());
+    }
+}
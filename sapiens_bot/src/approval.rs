@@ -0,0 +1,208 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sapiens::tools::approval::{Approval, ApprovalHandler, ApprovalRequest};
+use serenity::all::{
+    ButtonStyle, ChannelId, ComponentInteraction, CreateActionRow, CreateButton, CreateInputText,
+    CreateInteractionResponse, CreateMessage, EditMessage, InputTextStyle, Message, UserId,
+};
+use serenity::futures::channel::mpsc;
+use serenity::futures::SinkExt;
+use serenity::prelude::*;
+use serenity::utils::CreateQuickModal;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::runner::JobUpdate;
+
+/// How long the user has to review an invocation
+const REVIEW_TIMEOUT: Duration = Duration::from_mins(10);
+
+/// Forward the approval requests to the thread of the current job
+pub(crate) struct DiscordApprovalHandler {
+    /// Where the updates of the current job go - `None` between jobs
+    job_tx: Arc<Mutex<Option<mpsc::Sender<JobUpdate>>>>,
+}
+
+impl DiscordApprovalHandler {
+    pub(crate) const fn new(job_tx: Arc<Mutex<Option<mpsc::Sender<JobUpdate>>>>) -> Self {
+        Self { job_tx }
+    }
+}
+
+#[async_trait::async_trait]
+impl ApprovalHandler for DiscordApprovalHandler {
+    async fn review(&self, request: ApprovalRequest) -> Approval {
+        let Some(mut job_tx) = self.job_tx.lock().await.clone() else {
+            warn!("No job to ask for an approval");
+            return Approval::Rejected {
+                reason: "Nobody to approve the Action".to_string(),
+            };
+        };
+
+        let (tx, rx) = oneshot::channel();
+
+        if job_tx
+            .send(JobUpdate::ApprovalRequest(request, tx))
+            .await
+            .is_err()
+        {
+            return Approval::Rejected {
+                reason: "Nobody to approve the Action".to_string(),
+            };
+        }
+
+        rx.await.unwrap_or_else(|_| Approval::Rejected {
+            reason: "The review was interrupted".to_string(),
+        })
+    }
+}
+
+/// Ask `user_id` in the thread `channel_id` to approve, reject or edit an
+/// invocation
+///
+/// Only `user_id` can review it. The review is given up - and its buttons
+/// removed - once `abandoned` resolves, e.g. when the task is stopped: `None`
+/// then.
+pub(crate) async fn ask_in_thread(
+    ctx: &Context,
+    channel_id: ChannelId,
+    user_id: UserId,
+    request: ApprovalRequest,
+    abandoned: impl Future<Output = ()> + Send,
+) -> Option<Approval> {
+    let parameters = serde_yaml::to_string(&request.parameters).unwrap_or_default();
+
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new("approve")
+            .label("Approve")
+            .style(ButtonStyle::Success),
+        CreateButton::new("reject")
+            .label("Reject")
+            .style(ButtonStyle::Danger),
+        CreateButton::new("edit")
+            .label("Edit")
+            .style(ButtonStyle::Secondary),
+    ]);
+
+    let content = format!(
+        "The Action **{}** requires your approval:\n```yaml\n{}```",
+        request.tool_name, parameters
+    );
+
+    let Ok(mut msg) = channel_id
+        .send_message(
+            &ctx.http,
+            CreateMessage::new()
+                .content(content.clone())
+                .components(vec![buttons]),
+        )
+        .await
+    else {
+        return Some(Approval::Rejected {
+            reason: "Failed to ask for an approval".to_string(),
+        });
+    };
+
+    let approval = tokio::select! {
+        approval = review(ctx, &msg, user_id, parameters) => Some(approval),
+        () = abandoned => None,
+    };
+
+    // remove the buttons
+    let decision = match &approval {
+        Some(approval) => {
+            info!(?approval, "Review of {}", request.tool_name);
+
+            match approval {
+                Approval::Approved => "Approved".to_string(),
+                Approval::Rejected { reason } => format!("Rejected: {reason}"),
+                Approval::Edited { .. } => "Approved with edited parameters".to_string(),
+            }
+        }
+        None => "The task no longer waits for the review".to_string(),
+    };
+    let _ = msg
+        .edit(
+            &ctx.http,
+            EditMessage::new()
+                .content(format!("{content}\n*{decision}*"))
+                .components(vec![]),
+        )
+        .await;
+
+    approval
+}
+
+/// Wait for `user_id` to approve, reject or edit the invocation of `msg`
+async fn review(ctx: &Context, msg: &Message, user_id: UserId, parameters: String) -> Approval {
+    let Some(interaction) = msg
+        .await_component_interaction(&ctx.shard)
+        .author_id(user_id)
+        .timeout(REVIEW_TIMEOUT)
+        .await
+    else {
+        return Approval::Rejected {
+            reason: "No answer from the user".to_string(),
+        };
+    };
+
+    match interaction.data.custom_id.as_str() {
+        "approve" => {
+            let _ = interaction
+                .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+                .await;
+            Approval::Approved
+        }
+        "reject" => {
+            let modal = CreateQuickModal::new("Reject the Action")
+                .timeout(REVIEW_TIMEOUT)
+                .paragraph_field("Why?");
+
+            let reason = ask_with_modal(ctx, &interaction, modal)
+                .await
+                .filter(|reason| !reason.trim().is_empty())
+                .unwrap_or_else(|| format!("Rejected by {}", interaction.user.name));
+
+            Approval::Rejected { reason }
+        }
+        _ => {
+            let modal = CreateQuickModal::new("Edit the parameters")
+                .timeout(REVIEW_TIMEOUT)
+                .field(
+                    CreateInputText::new(InputTextStyle::Paragraph, "Parameters (YAML)", "")
+                        .value(parameters),
+                );
+
+            match ask_with_modal(ctx, &interaction, modal).await {
+                Some(parameters) => match serde_yaml::from_str(&parameters) {
+                    Ok(parameters) => Approval::Edited { parameters },
+                    Err(e) => Approval::Rejected {
+                        reason: format!("The edited parameters are invalid: {e}"),
+                    },
+                },
+                None => Approval::Rejected {
+                    reason: "The parameters were not edited in time".to_string(),
+                },
+            }
+        }
+    }
+}
+
+/// Show a `modal` with a single field in response to `interaction` and return
+/// the value submitted by the user
+async fn ask_with_modal(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    modal: CreateQuickModal,
+) -> Option<String> {
+    let response = interaction.quick_modal(ctx, modal).await.ok()??;
+
+    let _ = response
+        .interaction
+        .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+        .await;
+
+    response.inputs.into_iter().next()
+}
@@ -1,22 +1,27 @@
 //! Discord bot for the Sapiens.
+mod approval;
 mod commands;
+mod metrics;
 mod runner;
 
+use std::collections::HashMap;
 use std::env;
+use std::time::Duration;
 
 use dotenvy::dotenv_override;
 use pyo3::PyResult;
+use sapiens::control::ControlHandle;
 use serenity::all::{
-    AutoArchiveDuration, CreateAllowedMentions, CreateInteractionResponse,
+    AutoArchiveDuration, ChannelId, CreateAllowedMentions, CreateInteractionResponse,
     CreateInteractionResponseMessage, CreateMessage, CreateThread, Interaction,
 };
 use serenity::async_trait;
 use serenity::futures::channel::mpsc;
 use serenity::futures::{SinkExt, StreamExt};
 use serenity::http::CacheHttp;
-use serenity::model::channel::Message;
+use serenity::model::channel::{Channel, Message};
 use serenity::model::gateway::Ready;
-use serenity::model::id::GuildId;
+use serenity::model::id::{GuildId, UserId};
 use serenity::prelude::*;
 use tokio::spawn;
 use tracing::{debug, info, trace, warn};
@@ -27,6 +32,8 @@ use crate::runner::{JobUpdate, NewJob};
 struct Handler {
     guild_id: GuildId,
     tx: RwLock<mpsc::Sender<NewJob>>,
+    /// The controls of the running tasks - by thread
+    controls: RwLock<HashMap<ChannelId, ControlHandle>>,
 }
 
 #[async_trait]
@@ -56,6 +63,19 @@ impl EventHandler for Handler {
             return;
         }
 
+        // the commands in the threads of the tasks control them - the other
+        // messages there are left to the questions of the tasks
+        if let Channel::Guild(channel) = &channel {
+            if channel.thread_metadata.is_some() {
+                if let Some(command) = ControlCommand::parse(&new_message.content) {
+                    self.control_task(&ctx, &new_message, command).await;
+                } else {
+                    trace!("Message in a thread, not a command");
+                }
+                return;
+            }
+        }
+
         // let old_messages: Vec<Message> = new_message
         //     .channel_id
         //     .messages(&ctx.http, |messages| {
@@ -116,7 +136,66 @@ impl EventHandler for Handler {
     }
 }
 
+/// A command controlling the task of a thread
+#[derive(Debug, Clone, Copy)]
+enum ControlCommand {
+    /// `STOP` - cancel the task
+    Stop,
+    /// `PAUSE` - pause the task after the current step
+    Pause,
+    /// `RESUME` - resume a paused task
+    Resume,
+}
+
+impl ControlCommand {
+    /// The command of a message - if it is one
+    fn parse(content: &str) -> Option<Self> {
+        match content.trim() {
+            "STOP" => Some(Self::Stop),
+            "PAUSE" => Some(Self::Pause),
+            "RESUME" => Some(Self::Resume),
+            _ => None,
+        }
+    }
+}
+
 impl Handler {
+    /// Cancel, pause or resume the task of a thread
+    async fn control_task(&self, ctx: &Context, new_message: &Message, command: ControlCommand) {
+        let Some(control) = self
+            .controls
+            .read()
+            .await
+            .get(&new_message.channel_id)
+            .cloned()
+        else {
+            trace!("Message in a thread without a task, ignoring");
+            return;
+        };
+
+        let reply = match command {
+            ControlCommand::Stop => {
+                control.cancel();
+                "Stopping the task..."
+            }
+            ControlCommand::Pause => {
+                control.pause();
+                "The task is paused after the current step - `RESUME` to resume it."
+            }
+            ControlCommand::Resume => {
+                control.resume();
+                "Resuming the task..."
+            }
+        };
+
+        info!(
+            "{} on thread {}: {}",
+            new_message.author.name, new_message.channel_id, new_message.content
+        );
+
+        new_message.channel_id.say(&ctx.http, reply).await.unwrap();
+    }
+
     async fn do_task(&self, ctx: &Context, new_message: &Message) {
         let max_steps = 12;
         let task = new_message.content[4..].to_string();
@@ -138,11 +217,13 @@ impl Handler {
 
         let (tx, mut rx) = mpsc::channel::<JobUpdate>(20);
 
+        let control = ControlHandle::new();
+
         // Send the job to the runner
         self.tx
             .write()
             .await
-            .send(NewJob::new(task, max_steps, false, tx))
+            .send(NewJob::new(task, max_steps, false, control.clone(), tx))
             .await
             .unwrap();
 
@@ -175,12 +256,14 @@ impl Handler {
 
         info!("Added {} to thread: {}", new_message.author.name, thread.id);
 
+        self.controls.write().await.insert(thread.id, control);
+
         // send a welcome message
         thread
             .send_message(
                 &ctx.http,
                 CreateMessage::new()
-                    .content("Let me warm up my engines...")
+                    .content("Let me warm up my engines... `STOP`, `PAUSE` or `RESUME` in this thread to control the task.")
                     .allowed_mentions(CreateAllowedMentions::new().replied_user(true)),
             )
             .await
@@ -192,11 +275,7 @@ impl Handler {
         while let Some(job_update) = rx.next().await {
             debug!("Received job update: {:#?}", job_update);
 
-            let msgs = match job_update {
-                JobUpdate::Completed(v) | JobUpdate::Vec(v) => Some(v),
-                JobUpdate::FailedToStart(e) | JobUpdate::ToolError(e) => Some(e),
-                JobUpdate::Over => None,
-            };
+            let msgs = handle_job_update(ctx, thread.id, new_message.author.id, job_update).await;
 
             if let Some(msgs) = msgs {
                 for txt in msgs {
@@ -213,6 +292,8 @@ impl Handler {
             }
         }
 
+        self.controls.write().await.remove(&thread.id);
+
         // Say goodbye
         thread
             .send_message(
@@ -226,6 +307,83 @@ impl Handler {
     }
 }
 
+/// Handle an update of the job of the thread `thread_id` started by
+/// `user_id` - and return the messages to post in the thread
+async fn handle_job_update(
+    ctx: &Context,
+    thread_id: ChannelId,
+    user_id: UserId,
+    job_update: JobUpdate,
+) -> Option<Vec<String>> {
+    match job_update {
+        JobUpdate::Completed(v) | JobUpdate::Vec(v) => Some(v),
+        JobUpdate::FailedToStart(e) | JobUpdate::ToolError(e) => Some(e),
+        JobUpdate::ApprovalRequest(request, mut reply) => {
+            // the task stops waiting when it is stopped or times out
+            let approval =
+                approval::ask_in_thread(ctx, thread_id, user_id, request, reply.closed()).await;
+
+            if let Some(approval) = approval {
+                let _ = reply.send(approval);
+            } else {
+                debug!("The task no longer waits for the review");
+            }
+            None
+        }
+        JobUpdate::Question(question, mut reply) => {
+            let answer = ask_question_in_thread(ctx, thread_id, user_id, &question);
+
+            // the task stops waiting when it is stopped or times out
+            tokio::select! {
+                answer = answer => {
+                    let _ = reply.send(answer);
+                }
+                () = reply.closed() => debug!("The task no longer waits for the answer"),
+            }
+            None
+        }
+        JobUpdate::Over => None,
+    }
+}
+
+/// How long the user has to answer a question
+const ANSWER_TIMEOUT: Duration = Duration::from_mins(10);
+
+/// Ask a question in the thread `channel_id` - the answer is the next message
+/// of `user_id` in the thread that is not a [`ControlCommand`]
+async fn ask_question_in_thread(
+    ctx: &Context,
+    channel_id: ChannelId,
+    user_id: UserId,
+    question: &str,
+) -> String {
+    let content = format!("**Question**: {question}\n*Answer with your next message.*");
+
+    if let Err(e) = channel_id
+        .send_message(
+            &ctx.http,
+            CreateMessage::new()
+                .content(content)
+                .allowed_mentions(CreateAllowedMentions::new().replied_user(true)),
+        )
+        .await
+    {
+        warn!("Failed to ask a question: {}", e);
+        return "The question could not be asked to the user".to_string();
+    }
+
+    channel_id
+        .await_reply(&ctx.shard)
+        .author_id(user_id)
+        .filter(|message| ControlCommand::parse(&message.content).is_none())
+        .timeout(ANSWER_TIMEOUT)
+        .await
+        .map_or_else(
+            || "No answer from the user".to_string(),
+            |message| message.content,
+        )
+}
+
 // #[tokio::main(flavor = "current_thread")]
 
 #[pyo3_asyncio::tokio::main]
@@ -272,6 +430,7 @@ async fn main() -> PyResult<()> {
     let event_handler = Handler {
         guild_id,
         tx: RwLock::new(tx),
+        controls: RwLock::new(HashMap::new()),
     };
 
     // Build our client.
@@ -0,0 +1,56 @@
+use std::net::SocketAddr;
+
+use sapiens::tools::toolbox::Toolbox;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+/// Serve the statistics of the `toolbox` on `http://<addr>/metrics` - in the
+/// Prometheus text format
+pub(crate) async fn serve(toolbox: Toolbox, addr: SocketAddr) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed to serve the metrics on {}: {}", addr, e);
+            return;
+        }
+    };
+
+    info!("Serving the metrics on http://{}/metrics", addr);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(respond(toolbox.clone(), stream));
+            }
+            Err(e) => warn!("Failed to accept a connection: {}", e),
+        }
+    }
+}
+
+/// Answer a single HTTP request
+async fn respond(toolbox: Toolbox, mut stream: TcpStream) {
+    // only the request line matters
+    let mut request = [0; 1024];
+    let Ok(n) = stream.read(&mut request).await else {
+        return;
+    };
+    let request = String::from_utf8_lossy(&request[..n]);
+
+    let response = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => {
+            let body = toolbox.stats().await.to_prometheus();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        warn!("Failed to send the metrics: {}", e);
+    }
+    let _ = stream.shutdown().await;
+}
@@ -1,21 +1,31 @@
 use std::env::VarError;
 use std::fmt::Debug;
+use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
+use sapiens::chains::loops::LoopDetectionConfig;
 use sapiens::context::{ChatEntryFormatter, ContextDump, MessageFormatter};
+use sapiens::control::ControlHandle;
 use sapiens::models::SupportedModel;
+use sapiens::tools::approval::{Approval, ApprovalRequest};
+use sapiens::tools::permissions::PermissionPolicy;
 use sapiens::tools::toolbox::Toolbox;
 use sapiens::tools::TerminationMessage;
 use sapiens::{
     models, wrap_observer, Error, InvalidInvocationNotification, InvocationFailureNotification,
-    InvocationResultNotification, InvocationSuccessNotification, MessageNotification,
-    ModelNotification, RuntimeObserver, SapiensConfig, TaskState, WeakRuntimeObserver,
+    InvocationResultNotification, InvocationSuccessNotification, LoopNotification,
+    MessageNotification, ModelNotification, MultipleInvocationsNotification, RuntimeObserver,
+    SapiensConfig, TaskState, WeakRuntimeObserver,
 };
 use serenity::futures::channel::mpsc;
 use serenity::futures::{SinkExt, StreamExt};
+use tokio::sync::{oneshot, Mutex};
 use tracing::{debug, error, info, warn};
 
+use crate::approval::DiscordApprovalHandler;
 use crate::runner::utils::{sanitize_msgs_for_discord, Formatter};
 
 /// Formatting utilities
@@ -25,13 +35,30 @@ pub(crate) mod utils;
 pub(crate) struct SapiensBot {
     toolbox: Toolbox,
     config: SapiensConfig,
+    /// Where the updates of the current job go - for the approvals
+    job_tx: Arc<Mutex<Option<mpsc::Sender<JobUpdate>>>>,
+    /// What the tasks are permitted to do
+    permissions: Option<PermissionPolicy>,
+    /// Number of tasks started - to attribute the tool usage to the tasks
+    task_count: AtomicUsize,
 }
 
 impl SapiensBot {
     /// Create a new bot from the environment variables: `OPENAI_API_KEY`, ...
+    ///
+    /// `TOOL_PERMISSIONS` is the path to a YAML [`PermissionPolicy`] applied
+    /// to each task. `TASK_TIMEOUT` is how long a task can run - in seconds.
+    /// `ON_LOOP` - `correct`, `ask-user` or `abort` - is what to do when a
+    /// task goes in circles. The tool usage statistics are served on
+    /// `http://<METRICS_ADDR>/metrics` if `METRICS_ADDR` is set.
     pub(crate) async fn new_from_env() -> Self {
         let toolbox = sapiens_tools::setup::toolbox_from_env().await;
 
+        let job_tx = Arc::new(Mutex::new(None));
+        toolbox
+            .set_approval_handler(Some(Arc::new(DiscordApprovalHandler::new(job_tx.clone()))))
+            .await;
+
         let _ =
             std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set in configuration file");
 
@@ -79,22 +106,69 @@ impl SapiensBot {
             }
         };
 
+        // shared by all the tasks
+        let model = match std::env::var("MODEL_RATE_LIMIT") {
+            Ok(limit) => {
+                models::rate_limited(model, limit.parse().expect("Invalid MODEL_RATE_LIMIT"))
+            }
+            Err(_) => model,
+        };
+
+        let max_duration = std::env::var("TASK_TIMEOUT")
+            .ok()
+            .map(|secs| Duration::from_secs(secs.parse().expect("Invalid TASK_TIMEOUT")));
+
+        let loop_detection = std::env::var("ON_LOOP")
+            .ok()
+            .map(|response| LoopDetectionConfig {
+                response: response.parse().expect("Invalid ON_LOOP"),
+                ..LoopDetectionConfig::default()
+            });
+
         let config = SapiensConfig {
             model,
+            max_duration,
+            loop_detection,
             ..SapiensConfig::default()
         };
 
-        Self { toolbox, config }
+        let permissions = std::env::var("TOOL_PERMISSIONS").ok().map(|path| {
+            let policy = std::fs::read_to_string(path).expect("Failed to read TOOL_PERMISSIONS");
+            serde_yaml::from_str(&policy).expect("Invalid TOOL_PERMISSIONS")
+        });
+
+        if let Ok(addr) = std::env::var("METRICS_ADDR") {
+            let addr: SocketAddr = addr.parse().expect("Invalid METRICS_ADDR");
+            tokio::spawn(crate::metrics::serve(toolbox.clone(), addr));
+        }
+
+        Self {
+            toolbox,
+            config,
+            job_tx,
+            permissions,
+            task_count: AtomicUsize::new(0),
+        }
     }
 
-    /// Start a new task
+    /// Start a new task - controlled with `control`
     pub(crate) async fn start_task(
         &self,
         task: String,
         observer: WeakRuntimeObserver,
+        control: ControlHandle,
     ) -> Result<TaskState, Error>
 where {
-        TaskState::with_observer(self.config.clone(), self.toolbox.clone(), task, observer).await
+        let job = self.task_count.fetch_add(1, Ordering::Relaxed) + 1;
+        let toolbox = self.toolbox.with_task(format!("job-{job}"));
+
+        // each task has its own quotas
+        let toolbox = match &self.permissions {
+            Some(policy) => toolbox.with_permissions(policy.clone()),
+            None => toolbox,
+        };
+
+        TaskState::with_control(self.config.clone(), toolbox, task, observer, control).await
     }
 }
 
@@ -152,31 +226,51 @@ impl RuntimeObserver for ProgressObserver {
         // todo!()
     }
 
+    async fn on_loop(&mut self, event: LoopNotification) {
+        let msg = format!(
+            "*Loop detected*: {} - response: {:?}",
+            event.detected, event.response
+        );
+
+        let msgs = sanitize_msgs_for_discord(vec![msg]);
+        self.job_tx.send(JobUpdate::Vec(msgs)).await.unwrap();
+    }
+
     async fn on_invocation_result(&mut self, event: InvocationResultNotification) {
-        match event {
-            InvocationResultNotification::InvocationSuccess(InvocationSuccessNotification {
-                result,
-                ..
-            }) => {
-                let msg = result;
+        let events = match event {
+            InvocationResultNotification::MultipleInvocations(
+                MultipleInvocationsNotification { results, .. },
+            ) => results,
+            event => vec![event],
+        };
 
-                let msgs = sanitize_msgs_for_discord(vec![msg]);
+        for event in events {
+            match event {
+                InvocationResultNotification::InvocationSuccess(
+                    InvocationSuccessNotification { result, .. },
+                ) => {
+                    let msg = result;
 
-                self.job_tx.send(JobUpdate::Vec(msgs)).await.unwrap();
-            }
-            InvocationResultNotification::InvocationFailure(InvocationFailureNotification {
-                e,
-                ..
-            }) => {
-                let msg = format!("*Error*: {e}");
+                    let msgs = sanitize_msgs_for_discord(vec![msg]);
 
-                let msgs = sanitize_msgs_for_discord(vec![msg]);
+                    self.job_tx.send(JobUpdate::Vec(msgs)).await.unwrap();
+                }
+                InvocationResultNotification::InvocationFailure(
+                    InvocationFailureNotification { e, .. },
+                ) => {
+                    let msg = format!("*Error*: {e}");
+
+                    let msgs = sanitize_msgs_for_discord(vec![msg]);
 
-                self.job_tx.send(JobUpdate::ToolError(msgs)).await.unwrap();
+                    self.job_tx.send(JobUpdate::ToolError(msgs)).await.unwrap();
+                }
+                InvocationResultNotification::InvalidInvocation(
+                    InvalidInvocationNotification { .. },
+                )
+                | InvocationResultNotification::MultipleInvocations(
+                    MultipleInvocationsNotification { .. },
+                ) => {}
             }
-            InvocationResultNotification::InvalidInvocation(InvalidInvocationNotification {
-                ..
-            }) => {}
         }
     }
 }
@@ -188,6 +282,10 @@ pub(crate) enum JobUpdate {
     Vec(Vec<String>),
     FailedToStart(Vec<String>),
     ToolError(Vec<String>),
+    /// An invocation is waiting for the user to approve it
+    ApprovalRequest(ApprovalRequest, oneshot::Sender<Approval>),
+    /// The task is waiting for the user to answer a question
+    Question(String, oneshot::Sender<String>),
     Over,
 }
 
@@ -197,6 +295,8 @@ pub(crate) struct NewJob {
     tx: mpsc::Sender<JobUpdate>,
     max_steps: usize,
     show_warmup_prompt: bool,
+    /// Cancels, pauses and resumes the job
+    control: ControlHandle,
 }
 
 impl NewJob {
@@ -206,6 +306,7 @@ impl NewJob {
         task: String,
         max_steps: usize,
         show_warmup_prompt: bool,
+        control: ControlHandle,
         tx: mpsc::Sender<JobUpdate>,
     ) -> Self {
         Self {
@@ -213,6 +314,7 @@ impl NewJob {
             tx,
             max_steps,
             show_warmup_prompt,
+            control,
         }
     }
 }
@@ -235,6 +337,9 @@ impl Runner {
 
             let mut tx = job.tx.clone();
 
+            // the approvals go to this job
+            *self.sapiens.job_tx.lock().await = Some(job.tx.clone());
+
             let observer = ProgressObserver {
                 show_warmup_prompt: job.show_warmup_prompt,
                 job_tx: job.tx,
@@ -250,7 +355,11 @@ impl Runner {
 
             let mut current_step = 0;
 
-            match self.sapiens.start_task(job.task, w_observer).await {
+            match self
+                .sapiens
+                .start_task(job.task, w_observer, job.control)
+                .await
+            {
                 Ok(step) => {
                     let mut step = step;
                     loop {
@@ -260,6 +369,39 @@ impl Runner {
                                 // update is going to come through the handler
                                 debug!("Step for: {}", task);
                             }
+                            Ok(TaskState::AwaitingInput { awaiting }) => {
+                                info!("Task waiting for an answer: {}", task);
+
+                                let (answer_tx, answer_rx) = oneshot::channel();
+                                let question = awaiting.question.clone();
+                                tx.send(JobUpdate::Question(question, answer_tx))
+                                    .await
+                                    .unwrap();
+
+                                let answer = async {
+                                    answer_rx
+                                        .await
+                                        .unwrap_or_else(|_| "No answer from the user".to_string())
+                                };
+
+                                // waiting is not a step - but the task can time
+                                // out or be stopped meanwhile
+                                match awaiting.wait_for_answer(answer).await {
+                                    Ok(s) => {
+                                        step = s;
+                                        continue;
+                                    }
+                                    Err(e) => {
+                                        error!("Error while waiting for an answer: {}", e);
+
+                                        let msg = format!("Error: {e}");
+                                        let msgs = sanitize_msgs_for_discord(vec![msg]);
+
+                                        tx.send(JobUpdate::FailedToStart(msgs)).await.unwrap();
+                                        break;
+                                    }
+                                }
+                            }
                             Ok(TaskState::Stop { stop }) => {
                                 info!("Task finished: {}", task);
 
@@ -302,6 +444,8 @@ impl Runner {
                     tx.send(JobUpdate::FailedToStart(msgs)).await.unwrap();
                 }
             }
+
+            *self.sapiens.job_tx.lock().await = None;
         }
         warn!("Runner stopped");
     }
@@ -1,29 +1,42 @@
 //! Main for `sapiens_cli`
 use std::sync::Arc;
+use std::time::Duration;
 
 use clap::Parser;
 use colored::Colorize;
 use dotenvy::dotenv_override;
+use sapiens::chains::agents::critic::CriticConfig;
+use sapiens::chains::loops::{LoopDetectionConfig, LoopResponse};
+use sapiens::chains::reflexion::{FileReflections, MemoryReflections};
+use sapiens::chains::registry::ChainRegistry;
+use sapiens::chains::schedulers::Scheduling;
+use sapiens::chains::tot::TreeOfThoughtsConfig;
 use sapiens::chains::Message;
 use sapiens::context::{ChatEntry, ChatEntryFormatter, ContextDump, MessageFormatter};
-use sapiens::models::{Role, SupportedModel};
+use sapiens::control::ControlHandle;
+use sapiens::models::{ModelRef, Role, SupportedModel};
+use sapiens::rate_limit::RateLimit;
+use sapiens::tools::approval::{Approval, ApprovalHandler, ApprovalRequest};
+use sapiens::tools::toolbox::{InvocationMode, Toolbox};
+use sapiens::tools::TerminationMessage;
 use sapiens::{
-    models, run_to_the_end, wrap_observer, ChainType, InvocationResultNotification,
-    ModelNotification, RuntimeObserver, SapiensConfig,
+    models, wrap_observer, ChainType, InvocationResultNotification, LoopNotification,
+    MessageNotification, ModelNotification, RuntimeObserver, SapiensConfig, SubAgentEvent,
+    SubAgentNotification, TaskState, WeakRuntimeObserver,
 };
+use sapiens_tools::delegate::DelegateTool;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
+use tokio::sync::Mutex;
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
 // Usability:
 // FUTURE(ssoudan) Richer interaction
 // FUTURE(ssoudan) More tools: wx, negotiate
-// FUTURE(ssoudan) allow the bot to share its doubt and ask for help
 // FUTURE(ssoudan) Crontab-like scheduling: get a summary of the news daily
 // FUTURE(ssoudan) better errors for python code
 //
 // Deployability:
-// FUTURE(ssoudan) Limit how long a tool can run
-// FUTURE(ssoudan) monitoring
 //
 // Adoption:
 // FUTURE(ssoudan) More documentation and examples
@@ -33,11 +46,11 @@ use tracing_subscriber::EnvFilter;
 // FUTURE(ssoudan) memory?
 // FUTURE(ssoudan) vector stores?
 // FUTURE(ssoudan) prompt optimization
-// FUTURE(ssoudan) multiple models - critic?
 
 /// A bot that can do things - or at least try to.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
     /// The type of chain to use
     #[arg(long, default_value_t = ChainType::SingleStepOODA, value_enum, env)]
@@ -51,6 +64,10 @@ struct Args {
     #[arg(short, long, default_value_t = 10)]
     max_steps: usize,
 
+    /// Maximum duration of the task - in seconds
+    #[arg(long)]
+    max_duration: Option<u64>,
+
     /// Minimum tokens for completion
     #[arg(long, default_value_t = 256)]
     min_tokens_for_completion: usize,
@@ -66,6 +83,93 @@ struct Args {
     #[arg(long)]
     show_warmup_prompt: bool,
 
+    /// Execute all the Actions of a response - not only the first one
+    #[arg(long)]
+    all_invocations: bool,
+
+    /// With `--all-invocations`, execute the Actions using side-effect-free
+    /// tools concurrently
+    #[arg(long, requires = "all_invocations")]
+    concurrent_invocations: bool,
+
+    /// With `--all-invocations`, stop at the first Action that fails
+    #[arg(long, requires = "all_invocations")]
+    stop_on_error: bool,
+
+    /// How long a tool can run - in seconds - unless it declares its own
+    /// timeout
+    #[arg(long)]
+    tool_timeout: Option<u64>,
+
+    /// Invoke the tools requiring an approval without asking
+    #[arg(long)]
+    auto_approve: bool,
+
+    /// Keep the self-reflections of the Reflexion chain in this YAML file -
+    /// in memory otherwise
+    #[arg(long, env)]
+    reflections: Option<String>,
+
+    /// Tree of Thoughts chain: number of next thoughts proposed for each
+    /// explored branch
+    #[arg(long, default_value_t = TreeOfThoughtsConfig::default().candidates)]
+    tot_candidates: usize,
+
+    /// Tree of Thoughts chain: number of branches explored further at each
+    /// level
+    #[arg(long, default_value_t = TreeOfThoughtsConfig::default().breadth)]
+    tot_breadth: usize,
+
+    /// Tree of Thoughts chain: maximum number of thoughts before an Action
+    #[arg(long, default_value_t = TreeOfThoughtsConfig::default().depth)]
+    tot_depth: usize,
+
+    /// Tree of Thoughts chain: branches scored below are pruned - from 0 to
+    /// 10
+    #[arg(long, default_value_t = TreeOfThoughtsConfig::default().prune_below)]
+    tot_prune_below: u8,
+
+    /// How the agents of the multi-step OODA chain are scheduled: in a fixed
+    /// order, by rules over the last message or by a model
+    #[arg(long, default_value_t = Scheduling::RoundRobin, value_enum, env)]
+    scheduling: Scheduling,
+
+    /// What to do when the task goes in circles - repeating the same Action or
+    /// the same error: warn the model, ask the user or abort. Not detected if
+    /// not set.
+    #[arg(long, value_enum, env)]
+    on_loop: Option<LoopResponse>,
+
+    /// Number of repetitions making a loop
+    #[arg(long, default_value_t = 3)]
+    max_repeats: usize,
+
+    /// Review the Actions with a critic before they are executed
+    #[arg(long)]
+    critic: bool,
+
+    /// Model of the critic - the same as the agents if not set
+    #[arg(long, value_enum, requires = "critic")]
+    critic_model: Option<SupportedModel>,
+
+    /// Maximum number of revisions of an Action rejected by the critic
+    #[arg(long, default_value_t = 2, requires = "critic")]
+    max_revisions: usize,
+
+    /// Let the agent hand sub-tasks over to sub-agents with these tools - e.g.
+    /// `Wikipedia,SandboxedPython`. The sub-agents cannot ask the user.
+    #[arg(long, value_delimiter = ',')]
+    delegate_tools: Vec<String>,
+
+    /// Maximum number of steps of the sub-agents
+    #[arg(long, default_value_t = 5)]
+    delegate_max_steps: usize,
+
+    /// Limit the queries to the model - e.g. `3/60` to wait or `3/60:fail` to
+    /// fail when more than 3 queries are made per minute
+    #[arg(long, env)]
+    model_rate_limit: Option<RateLimit>,
+
     /// Temperature for the model sampling
     /// min: 0, max: 2
     /// The higher the temperature, the crazier the text.
@@ -127,45 +231,215 @@ impl RuntimeObserver for Observer {
         println!("=============");
     }
 
+    async fn on_message(&mut self, event: MessageNotification) {
+        // the other messages are shown as model updates
+        if let Message::Critique { .. } | Message::Routing { .. } = event.message {
+            println!("{}", event.message.to_string().magenta());
+            println!("=============");
+        }
+    }
+
     async fn on_invocation_result(&mut self, event: InvocationResultNotification) {
-        match event {
-            InvocationResultNotification::InvocationSuccess(i) => {
-                println!("{}", i.result.green());
+        print_invocation_result(event);
+
+        println!("=============");
+    }
+
+    async fn on_loop(&mut self, event: LoopNotification) {
+        print_loop(&event);
+
+        println!("=============");
+    }
+
+    async fn on_sub_agent(&mut self, event: SubAgentNotification) {
+        let marker = format!("{}[sub-agent {}]", "  ".repeat(event.depth), event.depth).cyan();
+
+        match event.event {
+            SubAgentEvent::Task(task) => println!("{marker} {}", task.green()),
+            SubAgentEvent::ModelUpdate(update) => {
+                let msg = ChatEntryFormatter::format(&ColorFormatter, &update.chat_entry);
+                println!("{marker}\n{msg}");
             }
-            InvocationResultNotification::InvocationFailure(i) => {
-                println!("{}", i.extracted_input.magenta());
-                println!("{}", i.e.to_string().red());
+            SubAgentEvent::InvocationResult(result) => {
+                println!("{marker}");
+                print_invocation_result(result);
             }
-            InvocationResultNotification::InvalidInvocation(i) => {
-                println!("{}", i.e.to_string().yellow());
+            SubAgentEvent::Termination(termination) => {
+                for message in termination.messages {
+                    println!("{marker} {}", message.conclusion.blue());
+                }
             }
+            SubAgentEvent::Loop(event) => print_loop(&event),
+            SubAgentEvent::Start(_) | SubAgentEvent::Message(_) => return,
         }
 
         println!("=============");
     }
 }
 
-#[pyo3_asyncio::tokio::main]
-async fn main() -> Result<(), pyo3::PyErr> {
-    let args = Args::parse();
+fn print_loop(event: &LoopNotification) {
+    let msg = format!(
+        "Loop detected: {} - response: {:?}",
+        event.detected, event.response
+    );
+    println!("{}", msg.yellow());
+}
 
-    let _ = dotenv_override();
+fn print_invocation_result(event: InvocationResultNotification) {
+    match event {
+        InvocationResultNotification::InvocationSuccess(i) => {
+            if i.cached {
+                println!("{}", "# Answered from the cache".dimmed());
+            }
+            println!("{}", i.result.green());
+        }
+        InvocationResultNotification::InvocationFailure(i) => {
+            println!("{}", i.extracted_input.magenta());
+            println!("{}", i.e.to_string().red());
+        }
+        InvocationResultNotification::InvalidInvocation(i) => {
+            println!("{}", i.e.to_string().yellow());
+        }
+        InvocationResultNotification::MultipleInvocations(i) => {
+            for result in i.results {
+                print_invocation_result(result);
+            }
+        }
+    }
+}
 
-    tracing_subscriber::fmt()
-        .compact()
-        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_default())
-        .init();
+/// The lines typed on the terminal - shared by the approvals and the answers
+/// to the questions
+type StdinLines = Arc<Mutex<Lines<BufReader<Stdin>>>>;
 
-    info!("Starting sapiens_cli");
+/// Ask on the terminal before invoking the tools requiring an approval
+struct StdinApprovalHandler {
+    lines: StdinLines,
+}
 
-    let toolbox = sapiens_tools::setup::toolbox_from_env().await;
+impl StdinApprovalHandler {
+    const fn new(lines: StdinLines) -> Self {
+        Self { lines }
+    }
+}
+
+#[async_trait::async_trait]
+impl ApprovalHandler for StdinApprovalHandler {
+    async fn review(&self, request: ApprovalRequest) -> Approval {
+        let mut lines = self.lines.lock().await;
+
+        println!(
+            "{}",
+            format!("The Action {} requires your approval:", request.tool_name).cyan()
+        );
+        println!(
+            "{}",
+            serde_yaml::to_string(&request.parameters)
+                .unwrap_or_default()
+                .magenta()
+        );
+
+        loop {
+            println!("{}", "[a]pprove, [r]eject or [e]dit?".cyan());
+
+            let Ok(Some(answer)) = lines.next_line().await else {
+                return Approval::Rejected {
+                    reason: "No answer from the user".to_string(),
+                };
+            };
+
+            match answer.trim() {
+                "a" | "approve" => return Approval::Approved,
+                "r" | "reject" => {
+                    println!("{}", "Why?".cyan());
+                    let reason = lines.next_line().await.ok().flatten().unwrap_or_default();
+                    let reason = if reason.trim().is_empty() {
+                        "Rejected by the user".to_string()
+                    } else {
+                        reason
+                    };
+                    return Approval::Rejected { reason };
+                }
+                "e" | "edit" => {
+                    println!(
+                        "{}",
+                        "New parameters in YAML - end with an empty line:".cyan()
+                    );
+                    let mut parameters = String::new();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if line.is_empty() {
+                            break;
+                        }
+                        parameters.push_str(&line);
+                        parameters.push('\n');
+                    }
+
+                    match serde_yaml::from_str(&parameters) {
+                        Ok(parameters) => return Approval::Edited { parameters },
+                        Err(e) => println!("{}", format!("Invalid YAML: {e}").red()),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
 
-    let model = match args.model {
+/// Run the task until it is done - the questions to the user are answered on
+/// the terminal and Ctrl-C cancels the task
+async fn run_with_answers(
+    config: SapiensConfig,
+    toolbox: Toolbox,
+    task: String,
+    observer: WeakRuntimeObserver,
+    lines: &StdinLines,
+) -> Result<Vec<TerminationMessage>, sapiens::Error> {
+    let control = ControlHandle::new();
+
+    let canceller = control.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            canceller.cancel();
+        }
+    });
+
+    let mut task_state = TaskState::with_control(config, toolbox, task, observer, control).await?;
+
+    loop {
+        task_state = match task_state {
+            TaskState::AwaitingInput { awaiting } => {
+                println!("{}", awaiting.question.cyan());
+
+                // Ctrl-C and the timeout of the task still apply
+                let answer = async {
+                    let answer = lines
+                        .lock()
+                        .await
+                        .next_line()
+                        .await
+                        .ok()
+                        .flatten()
+                        .unwrap_or_default();
+                    println!("=============");
+                    answer
+                };
+
+                awaiting.wait_for_answer(answer).await?
+            }
+            TaskState::Stop { stop } => return Ok(stop.termination_messages),
+            task_state @ TaskState::Step { .. } => task_state.step().await?,
+        };
+    }
+}
+
+/// Build the `model` - with its credentials from the environment
+async fn build_model(model: SupportedModel, temperature: f32) -> ModelRef {
+    match model {
         SupportedModel::ChatBison001 => {
             let google_api_key =
                 std::env::var("GOOGLE_API_KEY").expect("GOOGLE_API_KEY is not set");
 
-            models::vertex_ai::build(google_api_key, Some(args.temperature))
+            models::vertex_ai::build(google_api_key, Some(temperature))
                 .await
                 .expect("Failed to build model")
         }
@@ -179,29 +453,103 @@ async fn main() -> Result<(), pyo3::PyErr> {
                 .parse::<u16>()
                 .expect("OLLAMA_PORT is not a valid port");
 
-            models::ollama::build(host, port, args.model).expect("Failed to build model")
+            models::ollama::build(host, port, model).expect("Failed to build model")
         }
         _ => {
             let api_key = std::env::var("OPENAI_API_KEY").ok();
             let api_base = std::env::var("OPENAI_API_BASE").ok();
 
-            models::openai::build(
-                args.model.clone(),
-                api_key,
-                api_base,
-                Some(args.temperature),
-            )
-            .expect("Failed to build model")
+            models::openai::build(model, api_key, api_base, Some(temperature))
+                .expect("Failed to build model")
         }
+    }
+}
+
+#[pyo3_asyncio::tokio::main]
+async fn main() -> Result<(), pyo3::PyErr> {
+    let args = Args::parse();
+
+    let _ = dotenv_override();
+
+    tracing_subscriber::fmt()
+        .compact()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_default())
+        .init();
+
+    info!("Starting sapiens_cli");
+
+    let toolbox = sapiens_tools::setup::toolbox_from_env().await;
+
+    if args.all_invocations {
+        toolbox
+            .set_invocation_mode(InvocationMode::All {
+                concurrent: args.concurrent_invocations,
+                stop_on_error: args.stop_on_error,
+            })
+            .await;
+    }
+
+    if let Some(secs) = args.tool_timeout {
+        toolbox
+            .set_default_timeout(Some(Duration::from_secs(secs)))
+            .await;
+    }
+
+    let lines: StdinLines = Arc::new(Mutex::new(BufReader::new(tokio::io::stdin()).lines()));
+
+    if !args.auto_approve {
+        toolbox
+            .set_approval_handler(Some(Arc::new(StdinApprovalHandler::new(lines.clone()))))
+            .await;
+    }
+
+    let model = build_model(args.model.clone(), args.temperature).await;
+
+    let model = match args.model_rate_limit {
+        Some(limit) => models::rate_limited(model, limit),
+        None => model,
+    };
+
+    let critic = if args.critic {
+        let model = match args.critic_model.clone() {
+            Some(critic_model) => Some(build_model(critic_model, args.temperature).await),
+            None => None,
+        };
+
+        Some(CriticConfig {
+            model,
+            max_revisions: args.max_revisions,
+        })
+    } else {
+        None
     };
 
     let task = args.task.clone();
     let config = SapiensConfig {
         model,
-        chain_type: args.chain,
+        chain: args.chain.into(),
+        chains: Arc::new(ChainRegistry::default()),
         max_steps: args.max_steps,
+        max_duration: args.max_duration.map(Duration::from_secs),
         min_tokens_for_completion: args.min_tokens_for_completion,
         max_tokens: args.max_tokens,
+        reflections: match &args.reflections {
+            Some(path) => Arc::new(FileReflections::new(path)),
+            None => Arc::new(MemoryReflections::default()),
+        },
+        tree_of_thoughts: TreeOfThoughtsConfig {
+            candidates: args.tot_candidates,
+            breadth: args.tot_breadth,
+            depth: args.tot_depth,
+            prune_below: args.tot_prune_below,
+        },
+        critic,
+        scheduling: args.scheduling,
+        loop_detection: args.on_loop.map(|response| LoopDetectionConfig {
+            max_repeats: args.max_repeats,
+            response,
+            ..LoopDetectionConfig::default()
+        }),
     };
 
     // Sanitation
@@ -222,7 +570,23 @@ async fn main() -> Result<(), pyo3::PyErr> {
 
     let w_observer = Arc::downgrade(&observer);
 
-    let termination_messages = run_to_the_end(config, toolbox, task, w_observer).await;
+    if !args.delegate_tools.is_empty() {
+        let config = SapiensConfig {
+            max_steps: args.delegate_max_steps,
+            ..config.clone()
+        };
+
+        toolbox
+            .add_advanced_tool(DelegateTool::new(
+                config,
+                args.delegate_tools.clone(),
+                w_observer.clone(),
+            ))
+            .await
+            .expect("Duplicate tool name");
+    }
+
+    let termination_messages = run_with_answers(config, toolbox, task, w_observer, &lines).await;
 
     if let Err(e) = termination_messages {
         println!("{}", e.to_string().red());
@@ -21,6 +21,16 @@ struct DeriveReceiver {
     input: syn::Path,
     /// The output type
     output: syn::Path,
+    /// The tool has no side effect
+    #[darling(default)]
+    side_effect_free: bool,
+    /// How long the tool can run - in seconds
+    timeout_secs: Option<u64>,
+    /// The invocations of the tool must be approved
+    #[darling(default)]
+    requires_approval: bool,
+    /// How long the results of the tool can be reused - in seconds
+    cache_ttl_secs: Option<u64>,
 }
 
 impl ToTokens for DeriveReceiver {
@@ -32,6 +42,10 @@ impl ToTokens for DeriveReceiver {
             ref name,
             ref input,
             ref output,
+            side_effect_free,
+            timeout_secs,
+            requires_approval,
+            cache_ttl_secs,
             ..
         } = *self;
 
@@ -76,6 +90,16 @@ impl ToTokens for DeriveReceiver {
         let input_ty = &input.segments.last().unwrap().ident;
         let output_ty = &output.segments.last().unwrap().ident;
 
+        let timeout = timeout_secs.map_or_else(
+            || quote! { None },
+            |secs| quote! { Some(std::time::Duration::from_secs(#secs)) },
+        );
+
+        let cache_ttl = cache_ttl_secs.map_or_else(
+            || quote! { None },
+            |secs| quote! { Some(std::time::Duration::from_secs(#secs)) },
+        );
+
         // dbg!(fields);
         out.extend(quote! {
             impl #imp ProtoToolDescribe for #ident #ty #wher {
@@ -87,6 +111,22 @@ impl ToTokens for DeriveReceiver {
                         responses_content: #output_ty::describe(),
                     }
                 }
+
+                fn is_side_effect_free(&self) -> bool {
+                    #side_effect_free
+                }
+
+                fn timeout(&self) -> Option<std::time::Duration> {
+                    #timeout
+                }
+
+                fn requires_approval(&self) -> bool {
+                    #requires_approval
+                }
+
+                fn cache_ttl(&self) -> Option<std::time::Duration> {
+                    #cache_ttl
+                }
             }
         });
     }
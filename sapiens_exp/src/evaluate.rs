@@ -120,3 +120,58 @@ impl Trial {
         }
     }
 }
+
+/// The outcome of one of several trials of a scenario - see
+/// [`ImprovementCurve`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurvePoint {
+    /// The index of the trial - from 0
+    trial: usize,
+    /// The number of reflections available at the start of the trial
+    reflections: usize,
+    /// Completion status
+    completed: bool,
+    /// Reached accepting state
+    reached_accepting_state: bool,
+    /// The number of attempted tool invocations
+    attempted_invocations: u32,
+    /// The number of tokens
+    tokens: Usage,
+}
+
+/// The outcomes of successive trials of a scenario - e.g. with the
+/// reflections accumulated by the Reflexion chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImprovementCurve {
+    /// The task
+    task: String,
+    /// The configuration,
+    config: Config,
+    /// The outcome of each trial - in order
+    points: Vec<CurvePoint>,
+}
+
+impl ImprovementCurve {
+    /// Create an empty curve
+    #[must_use]
+    pub const fn new(config: Config, task: String) -> Self {
+        Self {
+            task,
+            config,
+            points: Vec::new(),
+        }
+    }
+
+    /// Add the outcome of a trial started with `reflections` reflections
+    pub fn push(&mut self, trial: usize, reflections: usize, record: &Trial) {
+        let analysis = &record.analysis;
+        self.points.push(CurvePoint {
+            trial,
+            reflections,
+            completed: analysis.completed,
+            reached_accepting_state: analysis.reached_accepting_state,
+            attempted_invocations: analysis.attempted_invocations,
+            tokens: analysis.tokens.clone(),
+        });
+    }
+}
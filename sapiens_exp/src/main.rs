@@ -5,9 +5,10 @@ use std::sync::Arc;
 
 use clap::{Parser, ValueEnum};
 use dotenvy::dotenv_override;
+use sapiens::chains::reflexion::{reflect, FileReflections, MemoryReflections};
 use sapiens::models::SupportedModel;
-use sapiens::{models, run_to_the_end, wrap_observer, ChainType};
-use sapiens_exp::evaluate::Trial;
+use sapiens::{models, wrap_observer, ChainType, SapiensConfig, TaskState};
+use sapiens_exp::evaluate::{ImprovementCurve, Trial};
 use sapiens_exp::tools::scenario_0;
 use sapiens_exp::traces::TraceObserver;
 use sapiens_exp::{setup, Config};
@@ -77,6 +78,17 @@ struct Args {
     /// The higher the temperature, the crazier the text.
     #[arg(long, default_value_t = 0.)]
     temperature: f32,
+
+    /// Number of trials of the scenario - each one from a fresh state. With
+    /// the Reflexion chain, the reflections on the failed trials are recalled
+    /// in the next ones. The improvement curve is saved next to the trials.
+    #[arg(long, default_value_t = 1)]
+    trials: usize,
+
+    /// Keep the self-reflections of the Reflexion chain in this YAML file -
+    /// in memory otherwise
+    #[arg(long)]
+    reflections: Option<String>,
 }
 
 impl From<&Args> for Config {
@@ -118,16 +130,6 @@ async fn main() -> Result<(), pyo3::PyErr> {
 
     info!("Going to save trials in {} ", trial_path.to_str().unwrap());
 
-    let toolbox = setup::basic_toolbox().await;
-
-    // prepare scenario
-    let (toolbox, shared_state) = match args.scenario {
-        Scenario::Scenario0 => scenario_0::build(toolbox).await,
-    };
-
-    // reset stats
-    toolbox.reset_stats().await;
-
     let temperature = Some(args.temperature);
 
     let model = match args.model {
@@ -149,7 +151,7 @@ async fn main() -> Result<(), pyo3::PyErr> {
                 .parse::<u16>()
                 .expect("OLLAMA_PORT is not a valid port");
 
-            models::ollama::build(host, port, args.model).expect("Failed to build model")
+            models::ollama::build(host, port, args.model.clone()).expect("Failed to build model")
         }
         _ => {
             let api_key = std::env::var("OPENAI_API_KEY").ok();
@@ -162,10 +164,15 @@ async fn main() -> Result<(), pyo3::PyErr> {
 
     let config = sapiens::SapiensConfig {
         max_steps: args.max_steps,
-        chain_type: args.chain,
+        chain: args.chain.into(),
         model,
         min_tokens_for_completion: args.min_tokens_for_completion,
         max_tokens: args.max_tokens,
+        reflections: match &args.reflections {
+            Some(path) => Arc::new(FileReflections::new(path)),
+            None => Arc::new(MemoryReflections::default()),
+        },
+        ..sapiens::SapiensConfig::default()
     };
 
     // Sanitation
@@ -178,25 +185,87 @@ async fn main() -> Result<(), pyo3::PyErr> {
         "Environment is not empty"
     );
 
+    let _ = std::fs::create_dir_all(&experiments_folder);
+
+    let mut curve = ImprovementCurve::new(trial_config.clone(), args.task.clone());
+
+    for trial_index in 0..args.trials {
+        let reflections = config.reflections.all().await.len();
+
+        let trial = run_trial(&args, &trial_config, &config).await;
+
+        trace!(trial = ?trial, "Trial");
+
+        // Save to {experiments_folder}/trial.json - numbered with several trials
+        let trial_path = if args.trials > 1 {
+            trial_path.with_extension(format!("{trial_index}.json"))
+        } else {
+            trial_path.clone()
+        };
+        let _ = std::fs::write(&trial_path, serde_json::to_string_pretty(&trial).unwrap());
+
+        info!("Trial saved to {}", trial_path.to_str().unwrap());
+
+        curve.push(trial_index, reflections, &trial);
+    }
+
+    if args.trials > 1 {
+        let curve_path = trial_path.with_extension("curve.json");
+        let _ = std::fs::write(&curve_path, serde_json::to_string_pretty(&curve).unwrap());
+
+        info!(
+            "Improvement curve saved to {}",
+            curve_path.to_str().unwrap()
+        );
+    }
+
+    Ok(())
+}
+
+/// Run the scenario once - from a fresh state
+///
+/// With the Reflexion chain, a reflection is written if the task was concluded
+/// without reaching the accepting state. The chain writes one itself when the
+/// maximum number of steps is reached.
+async fn run_trial(args: &Args, trial_config: &Config, config: &SapiensConfig) -> Trial {
+    let toolbox = setup::basic_toolbox().await;
+
+    // prepare scenario
+    let (toolbox, shared_state) = match args.scenario {
+        Scenario::Scenario0 => scenario_0::build(toolbox).await,
+    };
+
+    // reset stats
+    toolbox.reset_stats().await;
+
     let trace_observer = TraceObserver::new(shared_state.clone());
     let trace_observer = wrap_observer(trace_observer);
     let w_trace_observer = Arc::downgrade(&trace_observer);
 
     let task = args.task.clone();
 
-    match run_to_the_end(config, toolbox.clone(), task.clone(), w_trace_observer).await {
-        Ok(_) => {
+    let stop = match TaskState::with_observer(
+        config.clone(),
+        toolbox.clone(),
+        task.clone(),
+        w_trace_observer.clone(),
+    )
+    .await
+    {
+        Ok(task_state) => task_state.run().await,
+        Err(e) => Err(e),
+    };
+
+    let stop = match stop {
+        Ok(stop) => {
             info!("Task completed");
+            Some(stop)
         }
         Err(e) => {
             error!(error = ?e, "Task failed");
+            None
         }
-    }
-
-    let trace = { trace_observer.lock().await.trace().await };
-
-    // Collect tool utilization stats
-    let tool_stats = toolbox.stats().await;
+    };
 
     // Has the task been completed?
     let reached_accepting_state = {
@@ -210,23 +279,28 @@ async fn main() -> Result<(), pyo3::PyErr> {
         guard.state()
     };
 
+    if let (Some(stop), false, ChainType::Reflexion) = (&stop, reached_accepting_state, args.chain)
+    {
+        let failure = format!(
+            "The task was concluded but its goal was not reached - the final state is {final_state_name}."
+        );
+        if let Err(e) = reflect(config, &stop.context, &failure, w_trace_observer).await {
+            error!(error = ?e, "Failed to reflect on the trial");
+        }
+    }
+
+    let trace = { trace_observer.lock().await.trace().await };
+
+    // Collect tool utilization stats
+    let tool_stats = toolbox.stats().await;
+
     // Build trial
-    let trial = Trial::build(
-        trial_config,
+    Trial::build(
+        trial_config.clone(),
         task,
         trace,
         tool_stats,
         reached_accepting_state,
         final_state_name,
-    );
-
-    trace!(trial = ?trial, "Trial");
-
-    // Save to {experiments_folder}/trial.json
-    let _ = std::fs::create_dir_all(&experiments_folder);
-    let _ = std::fs::write(&trial_path, serde_json::to_string_pretty(&trial).unwrap());
-
-    info!("Trial saved to {}", trial_path.to_str().unwrap());
-
-    Ok(())
+    )
 }
@@ -7,11 +7,21 @@ use sapiens_tools::python::PythonTool;
 /// Initially, the toolbox contains the `PythonTool` and the `ConcludeTool`.
 /// Scenario builders like [`crate::tools::scenario_0::build`] will add
 /// their tools to the toolbox.
+///
+/// # Panics
+///
+/// if the tools cannot be registered.
 pub async fn basic_toolbox() -> toolbox::Toolbox {
     let toolbox = toolbox::Toolbox::default();
 
-    toolbox.add_advanced_tool(PythonTool::default()).await;
-    toolbox.add_terminal_tool(ConcludeTool::default()).await;
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .expect("Duplicate tool name");
+    toolbox
+        .add_terminal_tool(ConcludeTool::default())
+        .await
+        .expect("Duplicate tool name");
 
     toolbox
 }
@@ -376,6 +376,10 @@ impl From<Option<CerealBowlRecipeOutput>> for ServingOutput {
 /// The mixing is where you can mix the cereal and the milk in the bowl.
 /// The serving is where you can serve the bowl.
 /// The goal is to make a bowl of cereal and serve it.
+///
+/// # Panics
+///
+/// if the `toolbox` already has tools with the same names.
 pub async fn build(toolbox: Toolbox) -> (Toolbox, Arc<Mutex<dyn tools::State>>) {
     let state = InternalState::new();
     let shared_state = Arc::new(Mutex::new(state));
@@ -401,9 +405,12 @@ pub async fn build(toolbox: Toolbox) -> (Toolbox, Arc<Mutex<dyn tools::State>>)
             shared_state.clone(),
         );
 
-    toolbox.add_tool(closet).await;
-    toolbox.add_tool(mixing).await;
-    toolbox.add_tool(serving).await;
+    toolbox.add_tool(closet).await.expect("Duplicate tool name");
+    toolbox.add_tool(mixing).await.expect("Duplicate tool name");
+    toolbox
+        .add_tool(serving)
+        .await
+        .expect("Duplicate tool name");
 
     (toolbox, shared_state)
 }
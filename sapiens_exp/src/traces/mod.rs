@@ -2,13 +2,14 @@ use std::fmt::{Debug, Formatter};
 use std::ops::Add;
 use std::sync::Arc;
 
+use sapiens::chains::loops::{Loop, LoopResponse};
 use sapiens::chains::Message;
 use sapiens::context::{ChatEntry, ContextDump};
 use sapiens::models::Role;
 use sapiens::{
     InvalidInvocationNotification, InvocationFailureNotification, InvocationResultNotification,
-    InvocationSuccessNotification, MessageNotification, ModelNotification, RuntimeObserver,
-    TerminationNotification,
+    InvocationSuccessNotification, LoopNotification, MessageNotification, ModelNotification,
+    RuntimeObserver, TerminationNotification,
 };
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
@@ -146,6 +147,9 @@ pub enum Event {
         extracted_input: Vec<String>,
         /// The output of the tool - split into lines
         result: Vec<String>,
+        /// The output was answered from the cache
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        cached: bool,
     },
     /// Invoked tool failed
     ToolInvocationFailed {
@@ -172,6 +176,13 @@ pub enum Event {
         /// Number of invocation blocks in the message
         invocation_count: usize,
     },
+    /// The task went in circles
+    LoopDetected {
+        /// What was repeated
+        detected: Loop,
+        /// How the runtime responded to it
+        response: LoopResponse,
+    },
 }
 
 /// An event and the state after the event
@@ -195,12 +206,17 @@ impl Event {
             | Self::End(_)
             | Self::ToolInvocationSucceeded { .. }
             | Self::ToolInvocationFailed { .. }
-            | Self::InvalidInvocation { .. } => None,
+            | Self::InvalidInvocation { .. }
+            | Self::LoopDetected { .. } => None,
             Self::Message { message, .. } => match message {
                 Message::Observation { usage, .. }
                 | Message::Orientation { usage, .. }
                 | Message::Decision { usage, .. }
-                | Message::Action { usage, .. } => usage.as_ref().map(std::convert::Into::into),
+                | Message::Action { usage, .. }
+                | Message::Reflection { usage, .. }
+                | Message::Critique { usage, .. }
+                | Message::Routing { usage, .. }
+                | Message::Plan { usage, .. } => usage.as_ref().map(std::convert::Into::into),
                 Message::Task { .. } | Message::ActionResult { .. } => None,
             },
         }
@@ -314,6 +330,7 @@ impl From<InvocationSuccessNotification> for Event {
             tool_name,
             extracted_input,
             result,
+            cached,
         } = notification;
 
         Self::ToolInvocationSucceeded {
@@ -321,6 +338,7 @@ impl From<InvocationSuccessNotification> for Event {
             invocation_count,
             extracted_input: to_lines(extracted_input),
             result: to_lines(result),
+            cached,
         }
     }
 }
@@ -365,12 +383,15 @@ impl From<MessageNotification> for Event {
     }
 }
 
-impl From<InvocationResultNotification> for Event {
-    fn from(notification: InvocationResultNotification) -> Self {
-        match notification {
-            InvocationResultNotification::InvocationSuccess(x) => x.into(),
-            InvocationResultNotification::InvocationFailure(x) => x.into(),
-            InvocationResultNotification::InvalidInvocation(x) => x.into(),
+/// One event per invocation - several for
+/// [`InvocationResultNotification::MultipleInvocations`]
+fn invocation_events(notification: InvocationResultNotification) -> Vec<Event> {
+    match notification {
+        InvocationResultNotification::InvocationSuccess(x) => vec![x.into()],
+        InvocationResultNotification::InvocationFailure(x) => vec![x.into()],
+        InvocationResultNotification::InvalidInvocation(x) => vec![x.into()],
+        InvocationResultNotification::MultipleInvocations(x) => {
+            x.results.into_iter().flat_map(invocation_events).collect()
         }
     }
 }
@@ -405,12 +426,26 @@ impl RuntimeObserver for TraceObserver {
     async fn on_invocation_result(&mut self, event: InvocationResultNotification) {
         let state = self.get_state().await;
 
-        self.trace
-            .events
-            .push(Event::from(event).into_event_and_state(state));
+        self.trace.events.extend(
+            invocation_events(event)
+                .into_iter()
+                .map(|e| e.into_event_and_state(state.clone())),
+        );
     }
 
     async fn on_termination(&mut self, event: TerminationNotification) {
         self.termination = Some(event);
     }
+
+    async fn on_loop(&mut self, event: LoopNotification) {
+        let state = self.get_state().await;
+
+        self.trace.events.push(
+            Event::LoopDetected {
+                detected: event.detected,
+                response: event.response,
+            }
+            .into_event_and_state(state),
+        );
+    }
 }
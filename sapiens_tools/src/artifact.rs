@@ -0,0 +1,171 @@
+use std::fmt::Debug;
+
+use sapiens::tools::artifacts::ArtifactPage;
+use sapiens::tools::toolbox::Toolbox;
+use sapiens::tools::{
+    AdvancedTool, Describe, ProtoToolDescribe, ProtoToolInvoke, ToolDescription, ToolUseError,
+};
+use sapiens_derive::{Describe, ProtoToolDescribe};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+/// A tool to read the Action responses that were too long and were stored as
+/// artifacts.
+///
+/// Read a page - or a range of chars - of an artifact at a time.
+///
+/// The artifacts are the ones of the task invoking the tool - see
+/// [`Toolbox::artifacts`].
+#[derive(Debug, Default, ProtoToolDescribe)]
+#[tool(
+    name = "ReadArtifact",
+    input = "ReadArtifactToolInput",
+    output = "ReadArtifactToolOutput",
+    side_effect_free
+)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ReadArtifactTool {}
+
+/// The input of the tool
+#[derive(Debug, Serialize, Deserialize, Describe)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ReadArtifactToolInput {
+    /// The handle of the artifact, e.g. `artifact-1`. MANDATORY.
+    pub artifact_id: String,
+    /// The page to read - starting at 1. Defaults to 1. Ignored if `start` is
+    /// given.
+    pub page: Option<usize>,
+    /// The offset of the first char to read.
+    pub start: Option<usize>,
+    /// The number of chars to read from `start`. At most a page.
+    pub length: Option<usize>,
+}
+
+/// The output of the tool
+#[derive(Debug, Serialize, Deserialize, Describe)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ReadArtifactToolOutput {
+    /// The handle of the artifact
+    pub artifact_id: String,
+    /// The offset of the first char read
+    pub start: usize,
+    /// The number of chars of the artifact
+    pub total_chars: usize,
+    /// The content read
+    pub content: String,
+}
+
+impl From<ArtifactPage> for ReadArtifactToolOutput {
+    fn from(page: ArtifactPage) -> Self {
+        Self {
+            artifact_id: page.artifact_id,
+            start: page.start,
+            total_chars: page.total_chars,
+            content: page.content,
+        }
+    }
+}
+
+impl ReadArtifactTool {
+    #[tracing::instrument(skip(self, toolbox))]
+    async fn invoke_typed(
+        &self,
+        toolbox: Toolbox,
+        input: &ReadArtifactToolInput,
+    ) -> Result<ReadArtifactToolOutput, ToolUseError> {
+        let artifacts = toolbox.artifacts();
+
+        let page = if let Some(start) = input.start {
+            let length = input.length.unwrap_or(artifacts.policy().await.page_chars);
+            artifacts
+                .read_range(&input.artifact_id, start, length)
+                .await
+        } else {
+            artifacts
+                .read_page(&input.artifact_id, input.page.unwrap_or(1))
+                .await
+        };
+
+        page.map(Into::into)
+            .map_err(|e| ToolUseError::InvocationFailed(e.to_string()))
+    }
+}
+
+#[async_trait::async_trait]
+impl ProtoToolInvoke for ReadArtifactTool {
+    async fn invoke(&self, _input: Value) -> Result<Value, ToolUseError> {
+        Err(ToolUseError::InvocationFailed(
+            "ReadArtifact can only be invoked with a toolbox".to_string(),
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl AdvancedTool for ReadArtifactTool {
+    async fn invoke_with_toolbox(
+        &self,
+        toolbox: Toolbox,
+        input: Value,
+    ) -> Result<Value, ToolUseError> {
+        let input =
+            serde_yaml::from_value(input).map_err(|e| ToolUseError::InvalidInput(e.to_string()))?;
+        let output = self.invoke_typed(toolbox, &input).await?;
+        Ok(serde_yaml::to_value(output).map_err(|e| ToolUseError::InvalidOutput(e.to_string()))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sapiens::tools::artifacts::ArtifactPolicy;
+    use sapiens::tools::toolbox::{invoke_tool, InvokeResult, Toolbox};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_artifact() {
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_advanced_tool(ReadArtifactTool::default())
+            .await
+            .unwrap();
+
+        let artifacts = toolbox.artifacts();
+        artifacts
+            .set_policy(ArtifactPolicy {
+                page_chars: 5,
+                ..ArtifactPolicy::default()
+            })
+            .await;
+        let id = artifacts.add("Wikipedia", "0123456789abcdef").await;
+
+        let data = format!(
+            "```yaml\ntool_name: ReadArtifact\nparameters:\n  artifact_id: {id}\n  page: 2\n```"
+        );
+        let InvokeResult::Success { result, .. } = invoke_tool(toolbox.clone(), &data).await else {
+            panic!("the page should be read");
+        };
+        let output: ReadArtifactToolOutput = serde_yaml::from_str(&result).unwrap();
+        assert_eq!(output.content, "56789");
+        assert_eq!(output.start, 5);
+        assert_eq!(output.total_chars, 16);
+
+        // the artifacts of the other tasks are out of reach
+        let data = data.replace("page: 2", "page: 1");
+        assert!(matches!(
+            invoke_tool(toolbox.with_task("job-2"), &data).await,
+            InvokeResult::Error {
+                e: ToolUseError::InvocationFailed(_),
+                ..
+            }
+        ));
+
+        let data = "```yaml\ntool_name: ReadArtifact\nparameters:\n  artifact_id: artifact-42\n```";
+        assert!(matches!(
+            invoke_tool(toolbox, data).await,
+            InvokeResult::Error {
+                e: ToolUseError::InvocationFailed(_),
+                ..
+            }
+        ));
+    }
+}
@@ -13,7 +13,14 @@ use serde::{Deserialize, Serialize};
 /// engineering and systems science, and economics. Materials on this site are
 /// not peer-reviewed by arXiv.
 #[derive(Debug, ProtoToolInvoke, ProtoToolDescribe)]
-#[tool(name = "Arxiv", input = "ArxivToolInput", output = "ArxivToolOutput")]
+#[tool(
+    name = "Arxiv",
+    input = "ArxivToolInput",
+    output = "ArxivToolOutput",
+    side_effect_free,
+    timeout_secs = 60,
+    cache_ttl_secs = 86400
+)]
 #[allow(clippy::module_name_repetitions)]
 pub struct ArxivTool {}
 
@@ -0,0 +1,217 @@
+use std::fmt::Debug;
+
+use sapiens::tools::toolbox::{Toolbox, PENDING_ANSWER};
+use sapiens::tools::{
+    AdvancedTool, Describe, ProtoToolDescribe, ProtoToolInvoke, ToolDescription, ToolUseError,
+};
+use sapiens_derive::{Describe, ProtoToolDescribe};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+/// A tool to ask the user a clarifying question.
+///
+/// - Use it when the task is ambiguous or when information only the user has is
+///   missing.
+/// - Ask one short question at a time. The task waits for the answer.
+#[derive(Debug, Default, ProtoToolDescribe)]
+#[tool(
+    name = "AskUser",
+    input = "AskUserToolInput",
+    output = "AskUserToolOutput"
+)]
+#[allow(clippy::module_name_repetitions)]
+pub struct AskUserTool {}
+
+/// The input of the `AskUser` tool
+#[derive(Debug, Serialize, Deserialize, Describe)]
+#[allow(clippy::module_name_repetitions)]
+pub struct AskUserToolInput {
+    /// The question to the user. MANDATORY.
+    pub question: String,
+}
+
+/// The output of the `AskUser` tool
+#[derive(Serialize, Deserialize, Describe)]
+#[allow(clippy::module_name_repetitions)]
+pub struct AskUserToolOutput {
+    /// The answer of the user.
+    pub answer: String,
+}
+
+impl AskUserTool {
+    #[tracing::instrument(skip(self, toolbox))]
+    async fn invoke_typed(
+        &self,
+        toolbox: Toolbox,
+        input: &AskUserToolInput,
+    ) -> Result<AskUserToolOutput, ToolUseError> {
+        if input.question.trim().is_empty() {
+            return Err(ToolUseError::InvalidInput(
+                "The question is empty".to_string(),
+            ));
+        }
+
+        toolbox.ask_user(input.question.trim()).await;
+
+        // replaced by the answer when the task resumes
+        Ok(AskUserToolOutput {
+            answer: PENDING_ANSWER.to_string(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ProtoToolInvoke for AskUserTool {
+    async fn invoke(&self, _input: Value) -> Result<Value, ToolUseError> {
+        Err(ToolUseError::InvocationFailed(
+            "AskUser can only be invoked with a toolbox".to_string(),
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl AdvancedTool for AskUserTool {
+    async fn invoke_with_toolbox(
+        &self,
+        toolbox: Toolbox,
+        input: Value,
+    ) -> Result<Value, ToolUseError> {
+        let input =
+            serde_yaml::from_value(input).map_err(|e| ToolUseError::InvalidInput(e.to_string()))?;
+        let output = self.invoke_typed(toolbox, &input).await?;
+        Ok(serde_yaml::to_value(output).map_err(|e| ToolUseError::InvalidOutput(e.to_string()))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use indoc::indoc;
+    use sapiens::chains::{Message, Outcome};
+    use sapiens::{ChainType, SapiensConfig, TaskState};
+
+    use super::*;
+    use crate::conclude::ConcludeTool;
+    use crate::scripted::ScriptedModel;
+
+    #[tokio::test]
+    async fn tasks_wait_for_the_answers() {
+        let model = ScriptedModel::new(vec![
+            indoc! {"
+                Thought: Which one?
+                Action:
+                ```yaml
+                tool_name: AskUser
+                parameters:
+                  question: Which color?
+                ```"},
+            indoc! {"
+                Thought: Done.
+                Action:
+                ```yaml
+                tool_name: Conclude
+                parameters:
+                  conclusion: Blue
+                  original_question: Pick a color
+                ```"},
+        ]);
+
+        let config = SapiensConfig {
+            model: Arc::new(Box::new(model)),
+            chain: ChainType::ReAct.into(),
+            ..SapiensConfig::default()
+        };
+
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
+        toolbox
+            .add_advanced_tool(AskUserTool::default())
+            .await
+            .unwrap();
+
+        let task_state = TaskState::new(config, toolbox, "Pick a color".to_string())
+            .await
+            .unwrap()
+            .step()
+            .await
+            .unwrap();
+        assert_eq!(task_state.question(), Some("Which color?"));
+
+        // waiting is not a step
+        let task_state = task_state.step().await.unwrap();
+        let TaskState::AwaitingInput { awaiting } = task_state else {
+            panic!("the task should wait for the answer");
+        };
+
+        let task_state = awaiting.answer("Blue, please").step().await.unwrap();
+        let TaskState::Stop { stop } = task_state else {
+            panic!("the task should be done");
+        };
+        assert_eq!(stop.termination_messages[0].conclusion, "Blue");
+
+        // the answer is the result of the question
+        let results = stop
+            .context
+            .messages
+            .iter()
+            .filter_map(|m| match m {
+                Message::ActionResult {
+                    outcome: Outcome::Success { result },
+                    ..
+                } => Some(result.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(results[0], "answer: Blue, please\n");
+    }
+
+    #[tokio::test]
+    async fn tasks_time_out_while_waiting_for_the_answers() {
+        let model = ScriptedModel::new(vec![indoc! {"
+            Thought: Which one?
+            Action:
+            ```yaml
+            tool_name: AskUser
+            parameters:
+              question: Which color?
+            ```"}]);
+
+        let max_duration = std::time::Duration::from_millis(50);
+        let config = SapiensConfig {
+            model: Arc::new(Box::new(model)),
+            chain: ChainType::ReAct.into(),
+            max_duration: Some(max_duration),
+            ..SapiensConfig::default()
+        };
+
+        let toolbox = Toolbox::default();
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
+        toolbox
+            .add_advanced_tool(AskUserTool::default())
+            .await
+            .unwrap();
+
+        let task_state = TaskState::new(config, toolbox, "Pick a color".to_string())
+            .await
+            .unwrap()
+            .step()
+            .await
+            .unwrap();
+        let TaskState::AwaitingInput { awaiting } = task_state else {
+            panic!("the task should wait for the answer");
+        };
+
+        // no answer
+        let res = awaiting
+            .wait_for_answer(std::future::pending::<String>())
+            .await;
+        assert!(matches!(res, Err(sapiens::Error::TimedOut(d)) if d == max_duration));
+    }
+}
@@ -0,0 +1,464 @@
+//! Tools chaining other tools - defined in YAML
+//!
+//! ```yaml
+//! - name: SearchAndSummarize
+//!   description: Summarize the first result of a web search.
+//!   parameters:
+//!     - name: query
+//!       type: str
+//!       description: What to search for.
+//!   responses_content:
+//!     - name: summary
+//!       type: str
+//!       description: The summary of the first result.
+//!   steps:
+//!     - id: search
+//!       tool: Search
+//!       parameters:
+//!         q: "{{ parameters.query }}"
+//!         num_results: 1
+//!     - id: summarize
+//!       tool: Summarize
+//!       parameters:
+//!         text: "{{ search.results[0].snippet }}"
+//!   output:
+//!     summary: "{{ summarize.summary }}"
+//! ```
+//!
+//! A template `{{ path }}` designates the `parameters` of the composite tool or
+//! the result of a previous step by its `id`. A string made of a single
+//! template is replaced by the designated value, otherwise the values are
+//! rendered in the string. Without `output`, the result is the one of the last
+//! step.
+use std::path::Path;
+
+use sapiens::tools::toolbox::{invoke_from_toolbox, Toolbox};
+use sapiens::tools::{AdvancedTool, FieldFormat, Format, Tool, ToolDescription, ToolUseError};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+/// Error while loading composite tools
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The file could not be read
+    #[error("Failed to read the composite tools: {0}")]
+    Io(#[from] std::io::Error),
+    /// The definitions are not valid YAML
+    #[error("Invalid composite tools: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    /// A definition is inconsistent
+    #[error("Invalid composite tool {tool_name}: {reason}")]
+    Invalid {
+        /// The name of the composite tool
+        tool_name: String,
+        /// What is wrong
+        reason: String,
+    },
+}
+
+/// A step of a [`CompositeToolSpec`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Step {
+    /// The name of the step - to use its result in the next ones
+    pub id: String,
+    /// The tool to invoke
+    pub tool: String,
+    /// The parameters of the invocation - can contain templates
+    #[serde(default)]
+    pub parameters: Value,
+}
+
+/// Definition of a [`CompositeTool`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompositeToolSpec {
+    /// The name of the tool
+    pub name: String,
+    /// The description of the tool
+    pub description: String,
+    /// The parameters of the tool
+    #[serde(default)]
+    pub parameters: Vec<FieldFormat>,
+    /// The content of the result of the tool
+    #[serde(default)]
+    pub responses_content: Vec<FieldFormat>,
+    /// The tools to invoke - in order
+    pub steps: Vec<Step>,
+    /// The result of the tool - can contain templates
+    #[serde(default)]
+    pub output: Option<Value>,
+}
+
+impl CompositeToolSpec {
+    /// Check that the steps have unique ids and that the templates only use
+    /// the parameters and the results of the previous steps
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: String| Error::Invalid {
+            tool_name: self.name.clone(),
+            reason,
+        };
+
+        if self.steps.is_empty() {
+            return Err(invalid("no steps".to_string()));
+        }
+
+        let mut known = vec!["parameters"];
+
+        for step in &self.steps {
+            check_references(&step.parameters, &known)
+                .map_err(|reason| invalid(format!("step {}: {reason}", step.id)))?;
+
+            if known.contains(&step.id.as_str()) {
+                return Err(invalid(format!("duplicate step id {}", step.id)));
+            }
+            known.push(&step.id);
+        }
+
+        if let Some(output) = &self.output {
+            check_references(output, &known)
+                .map_err(|reason| invalid(format!("output: {reason}")))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A tool invoking other tools in sequence
+///
+/// Its [`ToolDescription`] comes from its [`CompositeToolSpec`].
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct CompositeTool {
+    spec: CompositeToolSpec,
+}
+
+impl CompositeTool {
+    /// Create a composite tool
+    pub fn new(spec: CompositeToolSpec) -> Result<Self, Error> {
+        spec.validate()?;
+        Ok(Self { spec })
+    }
+
+    /// Load the composite tools defined in a YAML document - a list of
+    /// [`CompositeToolSpec`]
+    pub fn from_yaml(yaml: &str) -> Result<Vec<Self>, Error> {
+        let specs: Vec<CompositeToolSpec> = serde_yaml::from_str(yaml)?;
+        specs.into_iter().map(Self::new).collect()
+    }
+
+    /// Load the composite tools defined in a YAML file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Vec<Self>, Error> {
+        Self::from_yaml(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[async_trait::async_trait]
+impl Tool for CompositeTool {
+    fn description(&self) -> ToolDescription {
+        ToolDescription {
+            name: self.spec.name.clone(),
+            description: self.spec.description.clone(),
+            parameters: Format {
+                fields: self.spec.parameters.clone(),
+            },
+            responses_content: Format {
+                fields: self.spec.responses_content.clone(),
+            },
+        }
+    }
+
+    async fn invoke(&self, _input: Value) -> Result<Value, ToolUseError> {
+        Err(ToolUseError::InvocationFailed(format!(
+            "{} can only be invoked with a toolbox",
+            self.spec.name
+        )))
+    }
+}
+
+#[async_trait::async_trait]
+impl AdvancedTool for CompositeTool {
+    async fn invoke_with_toolbox(
+        &self,
+        toolbox: Toolbox,
+        input: Value,
+    ) -> Result<Value, ToolUseError> {
+        let mut scope = serde_yaml::Mapping::new();
+        scope.insert("parameters".into(), input);
+        let mut scope = Value::Mapping(scope);
+
+        let mut last = Value::Null;
+
+        for step in &self.spec.steps {
+            let failed = |reason: String| {
+                ToolUseError::InvocationFailed(format!(
+                    "Step {} ({}) failed: {reason}",
+                    step.id, step.tool
+                ))
+            };
+
+            // the rendered parameters are checked against the description of
+            // the tool of the step
+            let parameters = render(&step.parameters, &scope).map_err(failed)?;
+            last = invoke_from_toolbox(toolbox.clone(), &step.tool, parameters)
+                .await
+                .map_err(|e| failed(e.to_string()))?;
+
+            if let Value::Mapping(scope) = &mut scope {
+                scope.insert(step.id.clone().into(), last.clone());
+            }
+        }
+
+        match &self.spec.output {
+            Some(output) => render(output, &scope).map_err(ToolUseError::InvalidOutput),
+            None => Ok(last),
+        }
+    }
+}
+
+/// The paths of the templates in `s` - e.g. `search.results[0]`
+fn templates(s: &str) -> Result<Vec<(usize, usize, &str)>, String> {
+    let mut templates = vec![];
+    let mut offset = 0;
+
+    while let Some(start) = s[offset..].find("{{") {
+        let start = offset + start;
+        let end = s[start..]
+            .find("}}")
+            .map(|end| start + end + 2)
+            .ok_or_else(|| format!("unclosed template in '{s}'"))?;
+
+        templates.push((start, end, s[start + 2..end - 2].trim()));
+        offset = end;
+    }
+
+    Ok(templates)
+}
+
+/// Check that the templates in `value` only use the `known` names
+fn check_references(value: &Value, known: &[&str]) -> Result<(), String> {
+    match value {
+        Value::String(s) => {
+            for (_, _, path) in templates(s)? {
+                let root = path.split(['.', '[']).next().unwrap_or_default();
+                if !known.contains(&root) {
+                    return Err(format!("unknown reference '{path}'"));
+                }
+            }
+            Ok(())
+        }
+        Value::Sequence(items) => items.iter().try_for_each(|v| check_references(v, known)),
+        Value::Mapping(m) => m.values().try_for_each(|v| check_references(v, known)),
+        _ => Ok(()),
+    }
+}
+
+/// Replace the templates in `value` with the values they designate in `scope`
+fn render(value: &Value, scope: &Value) -> Result<Value, String> {
+    match value {
+        Value::String(s) => {
+            let templates = templates(s)?;
+
+            // a single template keeps the type of the value
+            if let [(0, end, path)] = templates[..] {
+                if end == s.len() {
+                    return lookup(scope, path).cloned();
+                }
+            }
+
+            let mut rendered = String::new();
+            let mut offset = 0;
+            for (start, end, path) in templates {
+                rendered.push_str(&s[offset..start]);
+                rendered.push_str(&to_text(lookup(scope, path)?));
+                offset = end;
+            }
+            rendered.push_str(&s[offset..]);
+
+            Ok(Value::String(rendered))
+        }
+        Value::Sequence(items) => items
+            .iter()
+            .map(|v| render(v, scope))
+            .collect::<Result<_, _>>()
+            .map(Value::Sequence),
+        Value::Mapping(m) => m
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), render(v, scope)?)))
+            .collect::<Result<_, String>>()
+            .map(Value::Mapping),
+        v => Ok(v.clone()),
+    }
+}
+
+/// Get the value at `path` - e.g. `search.results[0].title`
+fn lookup<'a>(scope: &'a Value, path: &str) -> Result<&'a Value, String> {
+    let not_found = || format!("nothing at '{path}'");
+
+    let mut value = scope;
+    for segment in path.split('.') {
+        let mut parts = segment.split('[');
+        let name = parts.next().unwrap_or_default();
+        if !name.is_empty() {
+            value = value.get(name).ok_or_else(not_found)?;
+        }
+
+        for index in parts {
+            let index = index
+                .strip_suffix(']')
+                .and_then(|i| i.trim().parse::<usize>().ok())
+                .ok_or_else(|| format!("invalid index in '{path}'"))?;
+            value = value.get(index).ok_or_else(not_found)?;
+        }
+    }
+
+    Ok(value)
+}
+
+/// Render a value in a string
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        v => serde_yaml::to_string(v)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::dummy::DummyTool;
+
+    const TOOLS: &str = indoc! {r#"
+        - name: DoubleDummy
+          description: Invoke Dummy twice.
+          parameters:
+            - name: blah
+              type: str
+              description: What to give to Dummy.
+          responses_content:
+            - name: first
+              type: str
+              description: The first result.
+            - name: second
+              type: str
+              description: The second result.
+          steps:
+            - id: first
+              tool: Dummy
+              parameters:
+                blah: "{{ parameters.blah }}"
+            - id: second
+              tool: Dummy
+              parameters:
+                blah: "again: {{ first.something }}"
+          output:
+            first: "{{ first.something }}"
+            second: "{{ second }}"
+    "#};
+
+    #[tokio::test]
+    async fn composite_tools_chain_their_steps() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(DummyTool::default()).await.unwrap();
+        for tool in CompositeTool::from_yaml(TOOLS).unwrap() {
+            toolbox.add_advanced_tool(tool).await.unwrap();
+        }
+
+        let description = &toolbox.describe().await["DoubleDummy"];
+        assert_eq!(description.parameters.fields[0].name, "blah");
+        assert_eq!(description.responses_content.fields.len(), 2);
+
+        let data = indoc! {"
+            ```yaml
+            tool_name: DoubleDummy
+            parameters:
+              blah: hello
+            ```
+        "};
+        let sapiens::tools::toolbox::InvokeResult::Success { result, .. } =
+            sapiens::tools::toolbox::invoke_tool(toolbox, data).await
+        else {
+            panic!("the composite tool should succeed");
+        };
+
+        insta::assert_snapshot!(result);
+    }
+
+    #[tokio::test]
+    async fn step_parameters_are_validated() {
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(DummyTool::default()).await.unwrap();
+        let tools = TOOLS.replace(
+            "blah: \"{{ parameters.blah }}\"",
+            "bleh: \"{{ parameters.blah }}\"",
+        );
+        for tool in CompositeTool::from_yaml(&tools).unwrap() {
+            toolbox.add_advanced_tool(tool).await.unwrap();
+        }
+
+        let data = indoc! {"
+            ```yaml
+            tool_name: DoubleDummy
+            parameters:
+              blah: hello
+            ```
+        "};
+        let sapiens::tools::toolbox::InvokeResult::Error { e, .. } =
+            sapiens::tools::toolbox::invoke_tool(toolbox.clone(), data).await
+        else {
+            panic!("the first step should be rejected");
+        };
+
+        let e = e.to_string();
+        assert!(
+            e.contains("Step first (Dummy) failed: Invalid parameters"),
+            "{e}"
+        );
+        assert!(e.contains("bleh"), "{e}");
+        // rejected before the invocation
+        assert_eq!(
+            toolbox.stats().await.error_kinds["Dummy"]["InvalidParameters"],
+            1
+        );
+    }
+
+    #[test]
+    fn references_are_checked() {
+        let tools = TOOLS.replace("{{ first.something }}\"\n", "{{ third.something }}\"\n");
+        let e = CompositeTool::from_yaml(&tools).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Invalid composite tool DoubleDummy: step second: unknown reference 'third.something'"
+        );
+    }
+
+    #[test]
+    fn templates_are_rendered() {
+        let scope: Value =
+            serde_yaml::from_str("search: {results: [{title: Rust, rank: 1}]}").unwrap();
+
+        let rendered = render(
+            &serde_yaml::from_str("{rank: '{{ search.results[0].rank }}', text: '{{search.results[0].title}} is #{{ search.results[0].rank }}'}").unwrap(),
+            &scope,
+        )
+        .unwrap();
+        assert_eq!(
+            rendered,
+            serde_yaml::from_str::<Value>("{rank: 1, text: 'Rust is #1'}").unwrap()
+        );
+
+        assert_eq!(
+            render(
+                &Value::String("{{ search.results[1] }}".to_string()),
+                &scope
+            ),
+            Err("nothing at 'search.results[1]'".to_string())
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use sapiens::chains::loops::LoopResponse;
+use sapiens::tools::toolbox::Toolbox;
+use sapiens::tools::{
+    AdvancedTool, Describe, ProtoToolDescribe, ProtoToolInvoke, ToolDescription, ToolUseError,
+};
+use sapiens::{
+    run_to_the_end, wrap_observer, SapiensConfig, SubAgentObserver, WeakRuntimeObserver,
+};
+use sapiens_derive::{Describe, ProtoToolDescribe};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::ask::AskUserTool;
+use crate::conclude::ConcludeTool;
+
+/// A tool to hand a sub-task over to a sub-agent.
+///
+/// - The sub-agent starts from scratch: describe the sub-task completely.
+/// - It has its own budget of steps and only some of the Tools.
+/// - Use it for self-contained sub-tasks - e.g. a research question.
+#[derive(ProtoToolDescribe)]
+#[tool(
+    name = "Delegate",
+    input = "DelegateToolInput",
+    output = "DelegateToolOutput"
+)]
+#[allow(clippy::module_name_repetitions)]
+pub struct DelegateTool {
+    /// The configuration of the sub-agents - e.g. their budget of steps
+    config: SapiensConfig,
+    /// The tools of the sub-agents
+    tools: Vec<String>,
+    /// The observer the progress of the sub-agents is forwarded to
+    observer: WeakRuntimeObserver,
+}
+
+impl Debug for DelegateTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DelegateTool")
+            .field("tools", &self.tools)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DelegateTool {
+    /// Create a new [`DelegateTool`]
+    ///
+    /// The sub-agents run with the `config` and the `tools` of the toolbox
+    /// the tool is invoked with - and their own `Conclude` tool. Their
+    /// progress is forwarded to the `observer` - see [`SubAgentObserver`].
+    ///
+    /// The sub-agents cannot delegate with this tool again: it would be a
+    /// cycle of invocations. They cannot ask the user either - they run to the
+    /// end: the `AskUser` tool is left out and [`LoopResponse::AskUser`] is
+    /// replaced by [`LoopResponse::Correct`].
+    #[must_use]
+    pub fn new(
+        mut config: SapiensConfig,
+        tools: Vec<String>,
+        observer: WeakRuntimeObserver,
+    ) -> Self {
+        if let Some(loop_detection) = &mut config.loop_detection {
+            if loop_detection.response == LoopResponse::AskUser {
+                loop_detection.response = LoopResponse::Correct;
+            }
+        }
+
+        Self {
+            config,
+            tools,
+            observer,
+        }
+    }
+}
+
+/// The input of the Delegate tool
+#[derive(Debug, Serialize, Deserialize, Describe)]
+#[allow(clippy::module_name_repetitions)]
+pub struct DelegateToolInput {
+    /// The sub-task - with everything needed to complete it. MANDATORY.
+    pub task: String,
+}
+
+/// The output of the Delegate tool
+#[derive(Serialize, Deserialize, Describe)]
+#[allow(clippy::module_name_repetitions)]
+pub struct DelegateToolOutput {
+    /// The conclusion of the sub-agent.
+    pub conclusion: String,
+    /// The sub-task as understood by the sub-agent.
+    pub original_question: String,
+}
+
+impl DelegateTool {
+    #[tracing::instrument(skip(self, toolbox))]
+    async fn invoke_typed(
+        &self,
+        toolbox: Toolbox,
+        input: &DelegateToolInput,
+    ) -> Result<DelegateToolOutput, ToolUseError> {
+        // the invocation of this tool is in the context
+        let depth = toolbox.invocation_context().depth();
+
+        let toolbox = toolbox.restricted(&self.tools).await;
+        // the sub-agent would wait for an answer it cannot get
+        let _ = toolbox
+            .remove_tool(&AskUserTool::default().description().name)
+            .await;
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .map_err(|e| ToolUseError::InvocationFailed(e.to_string()))?;
+
+        let observer = wrap_observer(SubAgentObserver::new(self.observer.clone(), depth));
+        let w_observer = Arc::downgrade(&observer);
+
+        let termination_messages =
+            run_to_the_end(self.config.clone(), toolbox, input.task.clone(), w_observer)
+                .await
+                .map_err(|e| {
+                    ToolUseError::InvocationFailed(format!("The sub-agent failed: {e}"))
+                })?;
+
+        termination_messages
+            .into_iter()
+            .next()
+            .map(|message| DelegateToolOutput {
+                conclusion: message.conclusion,
+                original_question: message.original_question,
+            })
+            .ok_or_else(|| {
+                ToolUseError::InvocationFailed("The sub-agent did not conclude".to_string())
+            })
+    }
+}
+
+#[async_trait::async_trait]
+impl ProtoToolInvoke for DelegateTool {
+    async fn invoke(&self, _input: Value) -> Result<Value, ToolUseError> {
+        Err(ToolUseError::InvocationFailed(
+            "Delegate can only be invoked with a toolbox".to_string(),
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl AdvancedTool for DelegateTool {
+    async fn invoke_with_toolbox(
+        &self,
+        toolbox: Toolbox,
+        input: Value,
+    ) -> Result<Value, ToolUseError> {
+        let input =
+            serde_yaml::from_value(input).map_err(|e| ToolUseError::InvalidInput(e.to_string()))?;
+        let output = self.invoke_typed(toolbox, &input).await?;
+        Ok(serde_yaml::to_value(output).map_err(|e| ToolUseError::InvalidOutput(e.to_string()))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use sapiens::chains::loops::LoopDetectionConfig;
+    use sapiens::tools::toolbox::{invoke_tool, InvokeResult};
+    use sapiens::{ChainType, RuntimeObserver, SubAgentEvent, SubAgentNotification};
+
+    use super::*;
+    use crate::dummy::DummyTool;
+    use crate::scripted::ScriptedModel;
+
+    /// Records the depths and the kinds of the sub-agent events
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<(usize, &'static str)>,
+    }
+
+    #[async_trait::async_trait]
+    impl RuntimeObserver for Recorder {
+        async fn on_sub_agent(&mut self, event: SubAgentNotification) {
+            let kind = match event.event {
+                SubAgentEvent::Task(_) => "task",
+                SubAgentEvent::Start(_) => "start",
+                SubAgentEvent::ModelUpdate(_) => "model",
+                SubAgentEvent::Message(_) => "message",
+                SubAgentEvent::InvocationResult(_) => "invocation",
+                SubAgentEvent::Termination(_) => "termination",
+                SubAgentEvent::Loop(_) => "loop",
+            };
+            self.events.push((event.depth, kind));
+        }
+    }
+
+    #[tokio::test]
+    async fn sub_agents_conclude_with_their_own_tools() {
+        let model = ScriptedModel::new(vec![
+            indoc! {"
+                    Thought: Let's try Dummy.
+                    Action:
+                    ```yaml
+                    tool_name: Dummy
+                    parameters:
+                      blah: hello
+                    ```"},
+            indoc! {"
+                    Thought: Done.
+                    Action:
+                    ```yaml
+                    tool_name: Conclude
+                    parameters:
+                      conclusion: hello and something else
+                      original_question: Say hello
+                    ```"},
+        ]);
+
+        let config = SapiensConfig {
+            model: Arc::new(Box::new(model)),
+            chain: ChainType::ReAct.into(),
+            max_steps: 3,
+            ..SapiensConfig::default()
+        };
+
+        let recorder = wrap_observer(Recorder::default());
+        let observer = Arc::downgrade(&recorder);
+
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(DummyTool::default()).await.unwrap();
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
+        toolbox
+            .add_advanced_tool(DelegateTool::new(
+                config,
+                vec!["Dummy".to_string(), "Delegate".to_string()],
+                observer,
+            ))
+            .await
+            .unwrap();
+
+        let data = indoc! {"
+            ```yaml
+            tool_name: Delegate
+            parameters:
+              task: Say hello
+            ```
+        "};
+        let InvokeResult::Success { result, .. } = invoke_tool(toolbox.clone(), data).await else {
+            panic!("the sub-agent should conclude");
+        };
+
+        assert_eq!(
+            result,
+            "conclusion: hello and something else\noriginal_question: Say hello\n"
+        );
+
+        // the Conclude tool of the main task is untouched
+        assert!(toolbox.termination_messages().await.is_empty());
+
+        let events = recorder.lock().await.events.clone();
+        assert!(events.iter().all(|(depth, _)| *depth == 1));
+        assert_eq!(events.first(), Some(&(1, "task")));
+        assert_eq!(events.last(), Some(&(1, "termination")));
+    }
+
+    #[tokio::test]
+    async fn sub_agents_do_not_ask_the_user() {
+        let dummy = indoc! {"
+                Thought: Let's try Dummy.
+                Action:
+                ```yaml
+                tool_name: Dummy
+                parameters:
+                  blah: hello
+                ```"};
+        let model = ScriptedModel::new(vec![
+            indoc! {"
+                    Thought: Let's ask.
+                    Action:
+                    ```yaml
+                    tool_name: AskUser
+                    parameters:
+                      question: Hello what?
+                    ```"},
+            dummy,
+            dummy,
+            indoc! {"
+                    Thought: Done.
+                    Action:
+                    ```yaml
+                    tool_name: Conclude
+                    parameters:
+                      conclusion: hello
+                      original_question: Say hello
+                    ```"},
+        ]);
+
+        let config = SapiensConfig {
+            model: Arc::new(Box::new(model)),
+            chain: ChainType::ReAct.into(),
+            max_steps: 5,
+            loop_detection: Some(LoopDetectionConfig {
+                max_repeats: 2,
+                response: LoopResponse::AskUser,
+                ..LoopDetectionConfig::default()
+            }),
+            ..SapiensConfig::default()
+        };
+
+        let recorder = wrap_observer(Recorder::default());
+        let observer = Arc::downgrade(&recorder);
+
+        let toolbox = Toolbox::default();
+        toolbox.add_tool(DummyTool::default()).await.unwrap();
+        toolbox
+            .add_advanced_tool(AskUserTool::default())
+            .await
+            .unwrap();
+        toolbox
+            .add_advanced_tool(DelegateTool::new(
+                config,
+                vec!["Dummy".to_string(), "AskUser".to_string()],
+                observer,
+            ))
+            .await
+            .unwrap();
+
+        let data = indoc! {"
+            ```yaml
+            tool_name: Delegate
+            parameters:
+              task: Say hello
+            ```
+        "};
+        let InvokeResult::Success { result, .. } = invoke_tool(toolbox.clone(), data).await else {
+            panic!("the sub-agent should conclude");
+        };
+
+        assert_eq!(result, "conclusion: hello\noriginal_question: Say hello\n");
+        assert_eq!(toolbox.take_question().await, None);
+
+        // the loop is only corrected
+        let events = recorder.lock().await.events.clone();
+        assert!(events.contains(&(1, "loop")));
+    }
+}
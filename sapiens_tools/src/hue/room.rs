@@ -10,7 +10,12 @@ use crate::hue::Room;
 
 /// A tool to use that the source of truth for the Lights of a Room.
 #[derive(ProtoToolDescribe, ProtoToolInvoke)]
-#[tool(name = "Room", input = "RoomToolInput", output = "RoomToolOutput")]
+#[tool(
+    name = "Room",
+    input = "RoomToolInput",
+    output = "RoomToolOutput",
+    side_effect_free
+)]
 #[allow(clippy::module_name_repetitions)]
 pub struct RoomTool {
     bridge: huelib2::bridge::Bridge,
@@ -12,7 +12,8 @@ use crate::hue::Light;
 #[tool(
     name = "LightStatus",
     input = "StatusToolInput",
-    output = "StatusToolOutput"
+    output = "StatusToolOutput",
+    side_effect_free
 )]
 #[allow(clippy::module_name_repetitions)]
 pub struct StatusTool {
@@ -91,7 +92,8 @@ impl StatusTool {
 #[tool(
     name = "SetLightStatus",
     input = "SetStatusToolInput",
-    output = "StatusToolOutput"
+    output = "StatusToolOutput",
+    requires_approval
 )]
 #[allow(clippy::module_name_repetitions)]
 pub struct SetStatusTool {
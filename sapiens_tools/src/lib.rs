@@ -7,12 +7,28 @@ pub mod hue;
 /// Tool to conclude a chain
 pub mod conclude;
 
+/// Tool to ask the user a question
+pub mod ask;
+
+/// Tool to read the tool results stored as artifacts
+pub mod artifact;
+
 /// Tool to run some (limited) python
 pub mod python;
 
+/// Tools chaining other tools - defined in YAML
+pub mod composite;
+
+/// Tool to hand a sub-task over to a sub-agent
+pub mod delegate;
+
 /// Tool to test stuffs
 pub mod dummy;
 
+/// Model to test the tools running tasks
+#[cfg(test)]
+pub(crate) mod scripted;
+
 /// Tools related to mediawiki: Wikipedia, Wikidata, etc.
 #[cfg(feature = "wiki")]
 pub mod wiki;
@@ -6,6 +6,7 @@ use convert_case::{Case, Casing};
 use pyo3::indoc::{formatdoc, indoc};
 use pyo3::prelude::*;
 use pyo3::types::{IntoPyDict, PyDict};
+use sapiens::tools::registry::NAMESPACE_SEPARATOR;
 use sapiens::tools::toolbox::{invoke_simple_from_toolbox, Toolbox};
 use sapiens::tools::{
     AdvancedTool, Describe, ProtoToolDescribe, ProtoToolInvoke, ToolDescription, ToolUseError,
@@ -47,7 +48,8 @@ const MAX_OUTPUT_SIZE: usize = 512;
 #[tool(
     name = "SandboxedPython",
     input = "PythonToolInput",
-    output = "PythonToolOutput"
+    output = "PythonToolOutput",
+    requires_approval
 )]
 #[allow(clippy::module_name_repetitions)]
 pub struct PythonTool {}
@@ -382,7 +384,13 @@ impl PythonTool {
                 .collect::<Vec<_>>()
                 .join(", ");
 
-            for cased_name in [name.to_case(Case::Snake), name.to_case(Case::Pascal)] {
+            // the namespaces are not valid in Python identifiers
+            let identifier = name.replace(NAMESPACE_SEPARATOR, "_");
+
+            for cased_name in [
+                identifier.to_case(Case::Snake),
+                identifier.to_case(Case::Pascal),
+            ] {
                 // in Pascal case
                 tool_class_code.push_str(&indent(
                     4,
@@ -556,7 +564,10 @@ mod tests {
 
         let toolbox = Toolbox::default();
         // toolbox.add_tool(ArxivTool::new().await).await;
-        toolbox.add_terminal_tool(ConcludeTool::default()).await;
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
         // toolbox.add_advanced_tool(PythonTool::default()).await;
 
         let tools = toolbox.describe().await;
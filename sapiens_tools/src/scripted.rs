@@ -0,0 +1,41 @@
+use sapiens::models::{ChatEntryTokenNumber, ChatInput, Model, ModelResponse};
+use tokio::sync::Mutex;
+
+/// A model answering with the scripted responses in order
+pub(crate) struct ScriptedModel {
+    responses: Mutex<Vec<&'static str>>,
+}
+
+impl ScriptedModel {
+    pub(crate) fn new(responses: Vec<&'static str>) -> Self {
+        Self {
+            responses: Mutex::new(responses),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatEntryTokenNumber for ScriptedModel {
+    async fn num_tokens(&self, _input: ChatInput) -> usize {
+        0
+    }
+
+    async fn context_size(&self) -> usize {
+        4096
+    }
+}
+
+#[async_trait::async_trait]
+impl Model for ScriptedModel {
+    async fn query(
+        &self,
+        _input: ChatInput,
+        _max_tokens: Option<usize>,
+    ) -> Result<ModelResponse, sapiens::models::Error> {
+        Ok(ModelResponse {
+            msg: self.responses.lock().await.remove(0).to_string(),
+            usage: None,
+            finish_reason: None,
+        })
+    }
+}
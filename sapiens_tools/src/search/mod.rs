@@ -21,7 +21,9 @@ pub mod gce;
 #[tool(
     name = "Search",
     input = "SearchToolInput",
-    output = "SearchToolOutput"
+    output = "SearchToolOutput",
+    side_effect_free,
+    timeout_secs = 30
 )]
 #[allow(clippy::module_name_repetitions)]
 pub struct SearchTool {
@@ -1,27 +1,59 @@
 //! Sapiens CLI library
+use std::sync::Arc;
+
+use sapiens::rate_limit::RateLimit;
+use sapiens::tools::cache::{CacheBackend, DiskCache, MemoryCache};
 use sapiens::tools::toolbox::Toolbox;
 
+use crate::artifact::ReadArtifactTool;
+use crate::ask::AskUserTool;
+use crate::composite::CompositeTool;
 use crate::conclude::ConcludeTool;
 use crate::python::PythonTool;
 
 /// Assemble the toolbox of tools.
 ///
 /// - Uses features to enable/disable tools.
+/// - Lets the model ask the user questions - the tasks wait for the answers,
+///   see [`sapiens::TaskState::AwaitingInput`]
 /// - Gets API keys from environment variables.
 /// - Uses environment variables to configure tools: `HUE_BRIDGE_IP`,
 ///   `HUE_USERNAME`
+/// - Loads the composite tools defined in the YAML file `COMPOSITE_TOOLS` - see
+///   [`crate::composite`]
+/// - Caches the results of the deterministic tools in the directory
+///   `TOOL_CACHE_DIR` - in memory if not set
+/// - Limits the invocations of the tools listed in `TOOL_RATE_LIMITS` - e.g.
+///   `Search=100/86400:fail,Wikidata=5/1` - see
+///   [`sapiens::rate_limit::RateLimit`]
 ///
 /// # Panics
 ///
-/// if the required environment variables are not set.
+/// if the required environment variables are not set, if the composite
+/// tools or the rate limits are invalid or if the cache directory cannot be
+/// created.
+#[allow(clippy::too_many_lines)]
 pub async fn toolbox_from_env() -> Toolbox {
     let toolbox = Toolbox::default();
+    toolbox.set_cache(Some(cache_from_env())).await;
+
+    if let Ok(limits) = std::env::var("TOOL_RATE_LIMITS") {
+        for (tool_name, limit) in parse_rate_limits(&limits).expect("Invalid TOOL_RATE_LIMITS") {
+            toolbox
+                .set_rate_limit(tool_name, Some(limit))
+                .await
+                .expect("Invalid TOOL_RATE_LIMITS");
+        }
+    }
 
     #[cfg(feature = "search")]
     {
         use crate::search::SearchTool;
 
-        toolbox.add_tool(SearchTool::default()).await;
+        toolbox
+            .add_tool(SearchTool::default())
+            .await
+            .expect("Duplicate tool name");
     }
 
     #[cfg(feature = "hue")]
@@ -56,38 +88,96 @@ pub async fn toolbox_from_env() -> Toolbox {
 
         toolbox
             .add_tool(crate::hue::room::RoomTool::new(bridge.clone()))
-            .await;
+            .await
+            .expect("Duplicate tool name");
         toolbox
             .add_tool(crate::hue::status::SetStatusTool::new(bridge.clone()))
-            .await;
+            .await
+            .expect("Duplicate tool name");
         toolbox
             .add_tool(crate::hue::status::StatusTool::new(bridge))
-            .await;
+            .await
+            .expect("Duplicate tool name");
     }
 
     #[cfg(feature = "wiki")]
     {
         use crate::wiki::{wikidata, wikipedia};
 
-        toolbox.add_tool(wikidata::WikidataTool::new().await).await;
+        toolbox
+            .add_tool(wikidata::WikidataTool::new().await)
+            .await
+            .expect("Duplicate tool name");
         toolbox
             .add_tool(wikipedia::WikipediaTool::new().await)
-            .await;
+            .await
+            .expect("Duplicate tool name");
     }
 
     #[cfg(feature = "arxiv")]
     {
-        toolbox.add_tool(crate::arxiv::ArxivTool::new()).await;
+        toolbox
+            .add_tool(crate::arxiv::ArxivTool::new())
+            .await
+            .expect("Duplicate tool name");
     }
 
     #[cfg(feature = "summarize")]
     {
         toolbox
             .add_tool(crate::summarize::SummarizeTool::default())
-            .await;
+            .await
+            .expect("Duplicate tool name");
+    }
+
+    toolbox
+        .add_advanced_tool(ReadArtifactTool::default())
+        .await
+        .expect("Duplicate tool name");
+    toolbox
+        .add_terminal_tool(ConcludeTool::default())
+        .await
+        .expect("Duplicate tool name");
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .expect("Duplicate tool name");
+    toolbox
+        .add_advanced_tool(AskUserTool::default())
+        .await
+        .expect("Duplicate tool name");
+
+    if let Ok(path) = std::env::var("COMPOSITE_TOOLS") {
+        for tool in CompositeTool::from_file(path).expect("Failed to load COMPOSITE_TOOLS") {
+            toolbox
+                .add_advanced_tool(tool)
+                .await
+                .expect("Duplicate tool name");
+        }
     }
 
-    toolbox.add_terminal_tool(ConcludeTool::default()).await;
-    toolbox.add_advanced_tool(PythonTool::default()).await;
     toolbox
 }
+
+/// The cache of the results of the tools - on disk in `TOOL_CACHE_DIR` if set,
+/// in memory otherwise
+fn cache_from_env() -> Arc<dyn CacheBackend> {
+    match std::env::var("TOOL_CACHE_DIR") {
+        Ok(dir) => Arc::new(DiskCache::new(dir).expect("Failed to create TOOL_CACHE_DIR")),
+        Err(_) => Arc::new(MemoryCache::default()),
+    }
+}
+
+/// Parse rate limits per tool - e.g. `Search=100/86400:fail,Wikidata=5/1`
+fn parse_rate_limits(limits: &str) -> Result<Vec<(String, RateLimit)>, String> {
+    limits
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (tool_name, limit) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <tool>=<limit>: {entry}"))?;
+            Ok((tool_name.trim().to_string(), limit.trim().parse()?))
+        })
+        .collect()
+}
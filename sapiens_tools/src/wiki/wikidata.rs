@@ -17,7 +17,10 @@ use serde::{Deserialize, Serialize};
 #[tool(
     name = "Wikidata",
     input = "WikidataToolInput",
-    output = "WikidataToolOutput"
+    output = "WikidataToolOutput",
+    side_effect_free,
+    timeout_secs = 60,
+    cache_ttl_secs = 86400
 )]
 #[allow(clippy::module_name_repetitions)]
 pub struct WikidataTool {
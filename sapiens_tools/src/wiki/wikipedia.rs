@@ -15,7 +15,10 @@ use serde_yaml::Value;
 #[tool(
     name = "Wikipedia",
     input = "WikipediaToolInput",
-    output = "WikipediaToolOutput"
+    output = "WikipediaToolOutput",
+    side_effect_free,
+    timeout_secs = 30,
+    cache_ttl_secs = 86400
 )]
 #[allow(clippy::module_name_repetitions)]
 pub struct WikipediaTool {
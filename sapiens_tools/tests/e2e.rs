@@ -41,10 +41,16 @@ mod hue_test {
     "#};
 
         let toolbox = Toolbox::default();
-        toolbox.add_advanced_tool(PythonTool::default()).await;
-        toolbox.add_terminal_tool(ConcludeTool::default()).await;
-        toolbox.add_tool(FakeRoomTool::default()).await;
-        toolbox.add_tool(FakeStatusTool::default()).await;
+        toolbox
+            .add_advanced_tool(PythonTool::default())
+            .await
+            .unwrap();
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
+        toolbox.add_tool(FakeRoomTool::default()).await.unwrap();
+        toolbox.add_tool(FakeStatusTool::default()).await.unwrap();
 
         let res = invoke_tool(toolbox.clone(), data).await;
 
@@ -80,8 +86,11 @@ mod arxiv {
     #[pyo3_asyncio::tokio::test]
     async fn test_python_arxiv() -> PyResult<()> {
         let toolbox = Toolbox::default();
-        toolbox.add_tool(ArxivTool::new()).await;
-        toolbox.add_advanced_tool(PythonTool::default()).await;
+        toolbox.add_tool(ArxivTool::new()).await.unwrap();
+        toolbox
+            .add_advanced_tool(PythonTool::default())
+            .await
+            .unwrap();
 
         let data = indoc! {r#"```yaml
    tool_name: SandboxedPython
@@ -121,8 +130,11 @@ mod arxiv {
     #[pyo3_asyncio::tokio::test]
     async fn test_python_arxiv_2() -> PyResult<()> {
         let toolbox = Toolbox::default();
-        toolbox.add_tool(ArxivTool::new()).await;
-        toolbox.add_advanced_tool(PythonTool::default()).await;
+        toolbox.add_tool(ArxivTool::new()).await.unwrap();
+        toolbox
+            .add_advanced_tool(PythonTool::default())
+            .await
+            .unwrap();
 
         let data = indoc! {r#"```yaml
    tool_name: SandboxedPython
@@ -155,8 +167,11 @@ mod arxiv {
     #[pyo3_asyncio::tokio::test]
     async fn test_python_arxiv_3() -> PyResult<()> {
         let toolbox = Toolbox::default();
-        toolbox.add_tool(ArxivTool::new()).await;
-        toolbox.add_advanced_tool(PythonTool::default()).await;
+        toolbox.add_tool(ArxivTool::new()).await.unwrap();
+        toolbox
+            .add_advanced_tool(PythonTool::default())
+            .await
+            .unwrap();
 
         let data = indoc! {r#"```yaml
    tool_name: SandboxedPython
@@ -190,9 +205,15 @@ mod arxiv {
     #[pyo3_asyncio::tokio::test]
     async fn test_python_arxiv_4() -> PyResult<()> {
         let toolbox = Toolbox::default();
-        toolbox.add_tool(ArxivTool::new()).await;
-        toolbox.add_terminal_tool(ConcludeTool::default()).await;
-        toolbox.add_advanced_tool(PythonTool::default()).await;
+        toolbox.add_tool(ArxivTool::new()).await.unwrap();
+        toolbox
+            .add_terminal_tool(ConcludeTool::default())
+            .await
+            .unwrap();
+        toolbox
+            .add_advanced_tool(PythonTool::default())
+            .await
+            .unwrap();
 
         let data = indoc! {r#"```yaml
     tool_name: SandboxedPython
@@ -256,8 +277,11 @@ mod search {
     #[pyo3_asyncio::tokio::test]
     async fn test_python_search() -> PyResult<()> {
         let toolbox = Toolbox::default();
-        toolbox.add_tool(SearchTool::default()).await;
-        toolbox.add_advanced_tool(PythonTool::default()).await;
+        toolbox.add_tool(SearchTool::default()).await.unwrap();
+        toolbox
+            .add_advanced_tool(PythonTool::default())
+            .await
+            .unwrap();
 
         let data = indoc! {r#"```yaml
    tool_name: SandboxedPython
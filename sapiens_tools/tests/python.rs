@@ -20,7 +20,10 @@ async fn test_tool_invocation() -> PyResult<()> {
     "#};
 
     let toolbox = Toolbox::default();
-    toolbox.add_advanced_tool(PythonTool::default()).await;
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .unwrap();
 
     let res = invoke_tool(toolbox, data).await;
 
@@ -52,8 +55,11 @@ async fn test_tool_simple_invocation() -> PyResult<()> {
     "#};
 
     let toolbox = Toolbox::default();
-    toolbox.add_advanced_tool(PythonTool::default()).await;
-    toolbox.add_tool(ConcludeTool::default()).await;
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .unwrap();
+    toolbox.add_tool(ConcludeTool::default()).await.unwrap();
 
     let res = invoke_tool(toolbox, data).await;
 
@@ -87,8 +93,11 @@ async fn test_tool_invocation_in_python() -> PyResult<()> {
     "#};
 
     let toolbox = Toolbox::default();
-    toolbox.add_advanced_tool(PythonTool::default()).await;
-    toolbox.add_tool(DummyTool::default()).await;
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .unwrap();
+    toolbox.add_tool(DummyTool::default()).await.unwrap();
 
     let res = invoke_tool(toolbox, data).await;
 
@@ -119,8 +128,11 @@ async fn test_exit_in_python() -> PyResult<()> {
     "#};
 
     let toolbox = Toolbox::default();
-    toolbox.add_advanced_tool(PythonTool::default()).await;
-    toolbox.add_tool(DummyTool::default()).await;
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .unwrap();
+    toolbox.add_tool(DummyTool::default()).await.unwrap();
 
     let res = invoke_tool(toolbox, data).await;
 
@@ -164,13 +176,16 @@ async fn test_multiple_tool_invocations() -> PyResult<()> {
     "#};
 
     let toolbox = Toolbox::default();
-    toolbox.add_advanced_tool(PythonTool::default()).await;
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .unwrap();
 
     let res = invoke_tool(toolbox, data).await;
 
     match res {
         InvokeResult::NoValidInvocationsFound {
-            e: Error::TooManyYamlBlocks(2),
+            e: Error::TooManyYamlBlocks { count: 2, max: 1 },
             invocation_count: 2,
         } => {
             // This is expected
@@ -184,8 +199,11 @@ async fn test_multiple_tool_invocations() -> PyResult<()> {
 #[pyo3_asyncio::tokio::test]
 async fn test_python() -> PyResult<()> {
     let toolbox = Toolbox::default();
-    toolbox.add_tool(DummyTool::default()).await;
-    toolbox.add_advanced_tool(PythonTool::default()).await;
+    toolbox.add_tool(DummyTool::default()).await.unwrap();
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .unwrap();
 
     let data = indoc! {r#"```yaml
    tool_name: SandboxedPython
@@ -220,8 +238,11 @@ async fn test_python() -> PyResult<()> {
 #[pyo3_asyncio::tokio::test]
 async fn test_python_docstring() -> PyResult<()> {
     let toolbox = Toolbox::default();
-    toolbox.add_tool(DummyTool::default()).await;
-    toolbox.add_advanced_tool(PythonTool::default()).await;
+    toolbox.add_tool(DummyTool::default()).await.unwrap();
+    toolbox
+        .add_advanced_tool(PythonTool::default())
+        .await
+        .unwrap();
 
     let data = indoc! {r#"```yaml
    tool_name: SandboxedPython